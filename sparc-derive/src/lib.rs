@@ -0,0 +1,158 @@
+//! Derive macros for the conversion traits of the `sparc` crate.
+//!
+//! `#[derive(ToValue, FromValue)]` is supported on enums: each variant is converted to the SPARC
+//! constructor of the same name, whose argument is the variant's only field or the tuple of its
+//! fields, if any.
+
+#![warn(missing_docs)]
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Error, Fields, Generics, Ident};
+
+/// Derives `sparc::ToValue` for an enum.
+#[proc_macro_derive(ToValue)]
+pub fn derive_to_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_to_value(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derives `sparc::FromValue` for an enum.
+#[proc_macro_derive(FromValue)]
+pub fn derive_from_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_from_value(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn variants(input: &DeriveInput) -> Result<Vec<(&Ident, &Fields)>, Error> {
+    match &input.data {
+        Data::Enum(data) => Ok(data
+            .variants
+            .iter()
+            .map(|v| (&v.ident, &v.fields))
+            .collect()),
+        _ => Err(Error::new_spanned(
+            &input.ident,
+            "SPARC conversions can only be derived for enums",
+        )),
+    }
+}
+
+fn add_bounds(mut generics: Generics, bound: TokenStream2) -> Generics {
+    let params = generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .collect::<Vec<_>>();
+    let where_clause = generics.make_where_clause();
+    for param in params {
+        where_clause.predicates.push(parse_quote!(#param: #bound));
+    }
+    generics
+}
+
+/// Returns the bindings of the fields in a pattern destructuring the variant, and the pattern.
+fn destructure(ident: &Ident, fields: &Fields) -> (Vec<Ident>, TokenStream2) {
+    let names = (0..fields.len())
+        .map(|i| format_ident!("field{}", i))
+        .collect::<Vec<_>>();
+    let pattern = match fields {
+        Fields::Unit => quote!(Self::#ident),
+        Fields::Unnamed(_) => quote!(Self::#ident(#(#names),*)),
+        Fields::Named(fields) => {
+            let members = fields.named.iter().map(|f| f.ident.as_ref().unwrap());
+            quote!(Self::#ident { #(#members: #names),* })
+        }
+    };
+    (names, pattern)
+}
+
+fn expand_to_value(input: DeriveInput) -> Result<TokenStream2, Error> {
+    let name = &input.ident;
+    let generics = add_bounds(input.generics.clone(), quote!(::sparc::ToValue));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let arms = variants(&input)?.into_iter().map(|(ident, fields)| {
+        let ctor = ident.to_string();
+        let (names, pattern) = destructure(ident, fields);
+        let inner = match names.len() {
+            0 => quote!(::std::option::Option::None),
+            1 => quote!(::std::option::Option::Some(::sparc::ToValue::to_value(
+                field0
+            ))),
+            _ => quote!(::std::option::Option::Some(::std::sync::Arc::new(
+                ::sparc::Value::tuple(vec![#(::sparc::ToValue::to_value(#names)),*])
+            ))),
+        };
+        quote! {
+            #pattern => ::sparc::Value::Ctor {
+                ctor: #ctor.into(),
+                inner: #inner,
+            },
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::sparc::ToValue for #name #ty_generics #where_clause {
+            fn to_value(&self) -> ::std::sync::Arc<::sparc::Value> {
+                ::std::sync::Arc::new(match self {
+                    #(#arms)*
+                })
+            }
+        }
+    })
+}
+
+fn expand_from_value(input: DeriveInput) -> Result<TokenStream2, Error> {
+    let name = &input.ident;
+    let generics = add_bounds(input.generics.clone(), quote!(::sparc::FromValue));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let arms = variants(&input)?.into_iter().map(|(ident, fields)| {
+        let ctor = ident.to_string();
+        let (names, pattern) = destructure(ident, fields);
+        match names.len() {
+            0 => quote! {
+                (#ctor, ::std::option::Option::None) => ::std::option::Option::Some(#pattern),
+            },
+            1 => quote! {
+                (#ctor, ::std::option::Option::Some(inner)) => {
+                    let field0 = ::sparc::FromValue::from_value(inner)?;
+                    ::std::option::Option::Some(#pattern)
+                }
+            },
+            len => {
+                let indices = 0..len;
+                quote! {
+                    (#ctor, ::std::option::Option::Some(inner)) => {
+                        let values = inner.coerce_tuple(#len)?;
+                        #(let #names = ::sparc::FromValue::from_value(&values[#indices])?;)*
+                        ::std::option::Option::Some(#pattern)
+                    }
+                }
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::sparc::FromValue for #name #ty_generics #where_clause {
+            fn from_value(
+                value: &::std::sync::Arc<::sparc::Value>,
+            ) -> ::std::option::Option<Self> {
+                match &**value {
+                    ::sparc::Value::Ctor { ctor, inner } => match (ctor.as_str(), inner) {
+                        #(#arms)*
+                        _ => ::std::option::Option::None,
+                    },
+                    _ => ::std::option::Option::None,
+                }
+            }
+        }
+    })
+}
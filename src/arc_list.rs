@@ -27,6 +27,16 @@ impl<T> ArcList<T> {
         }
     }
 
+    pub fn head(&self) -> Option<&T> {
+        self.inner.as_ref().map(|inner| &inner.data)
+    }
+
+    pub fn tail(&self) -> Self {
+        Self {
+            inner: self.inner.as_ref().and_then(|inner| inner.next.clone()),
+        }
+    }
+
     pub fn iter<'s>(&'s self) -> ArcListIter<'s, T> {
         ArcListIter {
             inner: self
@@ -47,6 +57,7 @@ impl<T> Clone for ArcList<T> {
     }
 }
 
+#[derive(Debug)]
 pub struct ArcListIter<'s, T> {
     inner: *const Node<T>,
     _marker: PhantomData<&'s T>,
@@ -1,29 +1,167 @@
 extern crate clap;
 extern crate sparc;
 
-use clap::{App, Arg};
-use sparc::Executor;
+use clap::{Arg, ArgAction, Command};
+use sparc::{Backend, Executor, TraceOptions, WorkStealingOptions};
 use std::fs;
+use std::process;
+
+/// Reads the input file.
+fn read_input(input_file: &str) -> String {
+    fs::read_to_string(input_file)
+        .unwrap_or_else(|_| panic!("Cannot read from the file {}", input_file))
+}
 
 fn main() {
-    let matches = App::new("SPARC Interpreter")
+    let matches = Command::new("SPARC Interpreter")
         .version("0.1")
         .author("Jeehoon Kang <jeehoon.kang@sf.snu.ac.kr>")
         .about("Execute SPARC program")
         .arg(
-            Arg::with_name("INPUT_FILE")
+            Arg::new("INPUT_FILE")
                 .help("Sets the input file to use")
                 .required(true)
                 .index(1),
         )
+        .arg(
+            Arg::new("TRACE")
+                .long("trace")
+                .action(ArgAction::SetTrue)
+                .help("Prints each evaluation step to stderr"),
+        )
+        .arg(
+            Arg::new("TRACE_DEPTH")
+                .long("trace-depth")
+                .value_name("DEPTH")
+                .value_parser(clap::value_parser!(usize))
+                .requires("TRACE")
+                .help("Omits the steps nested deeper than DEPTH from the trace"),
+        )
+        .arg(
+            Arg::new("TRACE_NO_ENV")
+                .long("trace-no-env")
+                .action(ArgAction::SetTrue)
+                .requires("TRACE")
+                .help("Omits the bindings of variables from the trace"),
+        )
+        .arg(
+            Arg::new("SMALL_STEP")
+                .long("small-step")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("TRACE")
+                .help("Evaluates by substitution, printing each intermediate program to stderr"),
+        )
+        .arg(
+            Arg::new("NO_STEPS")
+                .long("no-steps")
+                .action(ArgAction::SetTrue)
+                .requires("SMALL_STEP")
+                .help("Omits the intermediate programs, which are slow to print for long runs"),
+        )
+        .arg(
+            Arg::new("BYTECODE")
+                .long("bytecode")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["TRACE", "SMALL_STEP"])
+                .help("Evaluates by compiling to bytecode, which is faster but cannot be traced"),
+        )
+        .arg(
+            Arg::new("SPACE")
+                .long("space")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["SMALL_STEP", "BYTECODE"])
+                .help("Measures the space of the evaluations, which slows them down"),
+        )
+        .arg(
+            Arg::new("WORK_STEALING")
+                .long("work-stealing")
+                .value_name("WORKERS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .conflicts_with_all(["SMALL_STEP", "BYTECODE"])
+                .help("Simulates work stealing with WORKERS workers on the main expression"),
+        )
+        .arg(
+            Arg::new("SEEDS")
+                .long("seeds")
+                .value_name("SEEDS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .default_value("10")
+                .requires("WORK_STEALING")
+                .help("Averages the simulated work stealing over SEEDS random seeds"),
+        )
+        .subcommand(
+            Command::new("compile")
+                .about("Compile SPARC program")
+                .arg(
+                    Arg::new("INPUT_FILE")
+                        .help("Sets the input file to use")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("EMIT")
+                        .long("emit")
+                        .value_name("LANGUAGE")
+                        .value_parser(["rust"])
+                        .required(true)
+                        .help("Sets the language to compile to"),
+                )
+                .arg(
+                    Arg::new("OUTPUT")
+                        .short('o')
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Writes the compiled program to FILE rather than stdout"),
+                ),
+        )
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("compile") {
+        let input = read_input(matches.get_one::<String>("INPUT_FILE").unwrap());
+        let code = match Executor::new().emit_rust(&input) {
+            Ok(code) => code,
+            Err(e) => {
+                Executor::report_error(&input, e);
+                process::exit(1);
+            }
+        };
+        match matches.get_one::<String>("OUTPUT") {
+            Some(output) => fs::write(output, code)
+                .unwrap_or_else(|_| panic!("Cannot write to the file {}", output)),
+            None => print!("{}", code),
+        }
+        return;
+    }
+
     // Calling .unwrap() is safe here because "INPUT" is required (if "INPUT" wasn't
     // required we could have used an 'if let' to conditionally get the value)
-    let input_file = matches.value_of("INPUT_FILE").unwrap();
-    let input =
-        fs::read_to_string(input_file).expect(&format!("Cannot read from the file {}", input_file));
+    let input = read_input(matches.get_one::<String>("INPUT_FILE").unwrap());
 
-    let executor = Executor::new();
+    let mut executor = Executor::new();
+    if matches.get_flag("TRACE") {
+        executor.set_trace(Some(TraceOptions {
+            max_depth: matches.get_one::<usize>("TRACE_DEPTH").copied(),
+            show_env: !matches.get_flag("TRACE_NO_ENV"),
+        }));
+    }
+    if matches.get_flag("SMALL_STEP") {
+        executor.set_backend(Backend::Substitution {
+            print_steps: !matches.get_flag("NO_STEPS"),
+        });
+    }
+    if matches.get_flag("BYTECODE") {
+        executor.set_backend(Backend::Bytecode);
+    }
+    if matches.get_flag("SPACE") {
+        executor.set_space(true);
+    }
+    if let Some(workers) = matches.get_one::<u64>("WORK_STEALING") {
+        executor.set_work_stealing(Some(WorkStealingOptions {
+            workers: *workers as usize,
+            seeds: *matches.get_one::<u64>("SEEDS").unwrap(),
+        }));
+    }
     executor.exec(&input);
 }
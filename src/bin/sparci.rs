@@ -4,24 +4,191 @@ extern crate rustyline;
 extern crate sparc;
 
 use rustyline::error::ReadlineError;
-use rustyline::Editor;
+use rustyline::DefaultEditor;
+use std::fs;
 
-use sparc::Executor;
+use sparc::{Breakpoint, Debugger, Executor, Stop, TraceOptions};
+
+/// Handles a `:trace` command, which turns tracing on or off or sets its options.
+fn trace_command(executor: &mut Executor, trace: &mut Option<TraceOptions>, args: &[&str]) {
+    let mut options = trace.unwrap_or_default();
+    match args {
+        [] | ["on"] => *trace = Some(options),
+        ["off"] => *trace = None,
+        ["depth", "none"] => {
+            options.max_depth = None;
+            *trace = Some(options);
+        }
+        ["depth", depth] => match depth.parse() {
+            Ok(depth) => {
+                options.max_depth = Some(depth);
+                *trace = Some(options);
+            }
+            Err(_) => println!("Invalid depth: {}", depth),
+        },
+        ["env", "on"] | ["env", "off"] => {
+            options.show_env = args[1] == "on";
+            *trace = Some(options);
+        }
+        _ => {
+            println!("Usage: :trace [on | off | depth (<n> | none) | env (on | off)]");
+            return;
+        }
+    }
+    executor.set_trace(*trace);
+}
+
+const DEBUG_HELP: &str = "\
+step (s)             evaluate until the next expression
+next (n)             evaluate until the next expression, stepping over applications
+finish (f)           evaluate until the current application returns
+continue (c)         evaluate until a breakpoint
+break (b) <line>     stop at the expressions on a line
+break (b) <name>     stop when a function is applied
+delete (d) <line>    remove a breakpoint, or
+delete (d) <name>
+print (p) <var>      print the value of a variable
+backtrace (bt)       print the active applications
+quit (q)             abandon the evaluation";
+
+fn breakpoint(arg: &str) -> Breakpoint {
+    match arg.parse() {
+        Ok(line) => Breakpoint::Line(line),
+        Err(_) => Breakpoint::Function(arg.to_string()),
+    }
+}
+
+/// Prints where the debugger stopped.  Returns whether the evaluation finished.
+fn show_stop(input: &str, stop: Stop) -> bool {
+    match stop {
+        Stop::Enter { line, expr } => {
+            if let Some(line) = line {
+                let source = input.lines().nth(line - 1).unwrap_or("").trim();
+                println!("line {}: {}", line, source);
+            }
+            println!("-> {}", expr);
+            false
+        }
+        Stop::Return { call, res } => {
+            let name = call.name.as_deref().unwrap_or("<fun>");
+            match res {
+                Ok(res) => println!(
+                    "{} returned {} (work: {}, span: {})",
+                    name, res.result, res.work, res.span
+                ),
+                Err(e) => println!("{} failed: {:?}", name, e),
+            }
+            false
+        }
+        Stop::Finished(Ok(Some(res))) => {
+            println!(
+                "Result: {}\nWork: {}\nSpan: {}",
+                res.result, res.work, res.span
+            );
+            true
+        }
+        Stop::Finished(Ok(None)) => true,
+        Stop::Finished(Err(e)) => {
+            Executor::report_error(input, e.into());
+            true
+        }
+    }
+}
+
+/// Handles a `:debug` command, which debugs the program given as the argument or in the file it
+/// names.
+fn debug_command(executor: &Executor, rl: &mut DefaultEditor, arg: &str) {
+    let input = fs::read_to_string(arg).unwrap_or_else(|_| arg.to_string());
+    let mut debugger: Debugger = match executor.debug(&input) {
+        Ok(debugger) => debugger,
+        Err(e) => {
+            Executor::report_error(&input, e);
+            return;
+        }
+    };
+    if show_stop(&input, debugger.step()) {
+        return;
+    }
+
+    loop {
+        let line = match rl.readline("(debug) ") {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        rl.add_history_entry(line.as_str()).unwrap();
+        let stop = match line.split_whitespace().collect::<Vec<_>>()[..] {
+            ["step"] | ["s"] => debugger.step(),
+            ["next"] | ["n"] => debugger.step_over(),
+            ["finish"] | ["f"] => debugger.finish(),
+            ["continue"] | ["c"] => debugger.cont(),
+            ["break", arg] | ["b", arg] => {
+                debugger.add_breakpoint(breakpoint(arg));
+                continue;
+            }
+            ["delete", arg] | ["d", arg] => {
+                if !debugger.remove_breakpoint(&breakpoint(arg)) {
+                    println!("No breakpoint at {}", arg);
+                }
+                continue;
+            }
+            ["print", var] | ["p", var] => {
+                match debugger.lookup(var) {
+                    Ok(value) => println!("{} = {}", var, value),
+                    Err(_) => println!("{} is not bound", var),
+                }
+                continue;
+            }
+            ["backtrace"] | ["bt"] => {
+                for (i, call) in debugger.backtrace().iter().enumerate() {
+                    print!(
+                        "#{} {} {}",
+                        i,
+                        call.name.as_deref().unwrap_or("<fun>"),
+                        call.arg
+                    );
+                    match call.line {
+                        Some(line) => println!(" at line {}", line),
+                        None => println!(),
+                    }
+                }
+                continue;
+            }
+            ["quit"] | ["q"] => return,
+            _ => {
+                println!("{}", DEBUG_HELP);
+                continue;
+            }
+        };
+        if show_stop(&input, stop) {
+            return;
+        }
+    }
+}
 
 fn main() {
     // `()` can be used when no completer is required
-    let mut rl = Editor::<()>::new();
+    let mut rl = DefaultEditor::new().unwrap();
     if rl.load_history("history.txt").is_err() {
         println!("No previous history.");
     }
 
-    let executor = Executor::new();
+    let mut executor = Executor::new();
+    let mut trace = None;
 
     loop {
         let readline = rl.readline(">>> ");
         match readline {
             Ok(line) => {
-                rl.add_history_entry(line.as_ref());
+                rl.add_history_entry(line.as_str()).unwrap();
+                let words = line.split_whitespace().collect::<Vec<_>>();
+                if words.first() == Some(&":trace") {
+                    trace_command(&mut executor, &mut trace, &words[1..]);
+                    continue;
+                }
+                if let Some(arg) = line.trim_start().strip_prefix(":debug") {
+                    debug_command(&executor, &mut rl, arg.trim());
+                    continue;
+                }
                 executor.exec(&line);
             }
             Err(ReadlineError::Interrupted) => {
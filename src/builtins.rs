@@ -0,0 +1,289 @@
+//! The functions bound in the initial environment of an executor.
+//!
+//! Strings are sequences of characters: indices and lengths count characters, and `explode` and
+//! `implode` convert between strings and lists of characters.  Building a string or a list of `n`
+//! characters has work `n` and span 1, like the corresponding sequence operations; the other
+//! functions cost a single step.
+//!
+//! `real` converts integers to floats, and `floor`, `ceil`, `round` and `trunc` convert floats to
+//! integers.  `log` is the base-2 logarithm, rounded up for integers as in cost bounds.
+
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use crate::convert::{FromValue, ToValue};
+use crate::native::NativeFn;
+use crate::semantics::{Err, Value};
+
+type Call = fn(&[Arc<Value>]) -> Result<Arc<Value>, Err>;
+
+/// A native function given by a pair of Rust functions.
+struct Builtin {
+    name: &'static str,
+    arity: usize,
+    cost: fn(&[Arc<Value>]) -> (u64, u64),
+    call: Call,
+}
+
+impl NativeFn for Builtin {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn cost(&self, args: &[Arc<Value>]) -> (u64, u64) {
+        (self.cost)(args)
+    }
+
+    fn call(&self, args: &[Arc<Value>]) -> Result<Arc<Value>, Err> {
+        (self.call)(args)
+    }
+}
+
+fn error(name: &str, message: impl Into<String>) -> Err {
+    Err::Native {
+        name: name.into(),
+        message: message.into(),
+    }
+}
+
+/// Converts the argument of `name`, reporting a type error if it is not a `what`.
+fn arg<T: FromValue>(name: &str, what: &str, value: &Arc<Value>) -> Result<T, Err> {
+    T::from_value(value).ok_or_else(|| error(name, format!("expected {}, got {}", what, value)))
+}
+
+fn unit_cost(_args: &[Arc<Value>]) -> (u64, u64) {
+    (1, 1)
+}
+
+/// The cost of building a sequence of `len` elements.
+fn seq_cost(len: usize) -> (u64, u64) {
+    (len.max(1) as u64, 1)
+}
+
+fn len(s: &str) -> usize {
+    s.chars().count()
+}
+
+/// Returns the characters `start..start + len` of `s`.
+fn slice(name: &str, s: &str, start: i64, len: i64) -> Result<String, Err> {
+    let size = self::len(s) as i64;
+    if start < 0 || len < 0 || start > size || len > size - start {
+        return Err(error(
+            name,
+            format!(
+                "range {}..{} out of bounds for size {}",
+                start,
+                i128::from(start) + i128::from(len),
+                size
+            ),
+        ));
+    }
+    Ok(s.chars().skip(start as usize).take(len as usize).collect())
+}
+
+/// Converts a float to an integer with `round`, reporting an error if the result is out of range.
+fn to_int(name: &str, value: &Arc<Value>, round: fn(f64) -> f64) -> Result<Arc<Value>, Err> {
+    let x: f64 = arg(name, "a float", value)?;
+    let rounded = round(x);
+    // The bounds are exactly representable, and the upper one is excluded.
+    if rounded.is_nan() || rounded < -(2f64.powi(63)) || rounded >= 2f64.powi(63) {
+        return Err(error(name, format!("{:?} is out of range", x)));
+    }
+    Ok((rounded as i64).to_value())
+}
+
+/// Returns the builtins.
+pub(crate) fn all() -> Vec<Arc<dyn NativeFn>> {
+    let builtins = vec![
+        Builtin {
+            name: "concat",
+            arity: 2,
+            cost: |args| {
+                let lhs = args[0].coerce_string().map_or(0, len);
+                let rhs = args[1].coerce_string().map_or(0, len);
+                seq_cost(lhs + rhs)
+            },
+            call: |args| {
+                let lhs: String = arg("concat", "a string", &args[0])?;
+                let rhs: String = arg("concat", "a string", &args[1])?;
+                Ok((lhs + &rhs).to_value())
+            },
+        },
+        Builtin {
+            name: "size",
+            arity: 1,
+            cost: unit_cost,
+            call: |args| {
+                let s: String = arg("size", "a string", &args[0])?;
+                Ok((len(&s) as i64).to_value())
+            },
+        },
+        Builtin {
+            name: "sub",
+            arity: 2,
+            cost: unit_cost,
+            call: |args| {
+                let s: String = arg("sub", "a string", &args[0])?;
+                let i: i64 = arg("sub", "an integer", &args[1])?;
+                let c = slice("sub", &s, i, 1)?;
+                Ok(c.chars().next().unwrap().to_value())
+            },
+        },
+        Builtin {
+            name: "substring",
+            arity: 3,
+            cost: |args| {
+                // Charges the characters actually copied, as an out-of-range request fails.
+                let size = args[0].coerce_string().map_or(0, len) as i64;
+                let start = args[1].coerce_int().unwrap_or(0).clamp(0, size);
+                let len = args[2].coerce_int().unwrap_or(0).clamp(0, size - start);
+                seq_cost(len as usize)
+            },
+            call: |args| {
+                let s: String = arg("substring", "a string", &args[0])?;
+                let start: i64 = arg("substring", "an integer", &args[1])?;
+                let len: i64 = arg("substring", "an integer", &args[2])?;
+                Ok(slice("substring", &s, start, len)?.to_value())
+            },
+        },
+        Builtin {
+            name: "explode",
+            arity: 1,
+            cost: |args| seq_cost(args[0].coerce_string().map_or(0, len)),
+            call: |args| {
+                let s: String = arg("explode", "a string", &args[0])?;
+                Ok(s.chars().collect::<Vec<_>>().to_value())
+            },
+        },
+        Builtin {
+            name: "implode",
+            arity: 1,
+            cost: |args| seq_cost(Vec::<Arc<Value>>::from_value(&args[0]).map_or(0, |l| l.len())),
+            call: |args| {
+                let chars: Vec<char> = arg("implode", "a list of characters", &args[0])?;
+                Ok(chars.into_iter().collect::<String>().to_value())
+            },
+        },
+        Builtin {
+            name: "str",
+            arity: 1,
+            cost: unit_cost,
+            call: |args| {
+                let c: char = arg("str", "a character", &args[0])?;
+                Ok(c.to_string().to_value())
+            },
+        },
+        Builtin {
+            name: "ord",
+            arity: 1,
+            cost: unit_cost,
+            call: |args| {
+                let c: char = arg("ord", "a character", &args[0])?;
+                Ok((c as i64).to_value())
+            },
+        },
+        Builtin {
+            name: "chr",
+            arity: 1,
+            cost: unit_cost,
+            call: |args| {
+                let i: i64 = arg("chr", "an integer", &args[0])?;
+                let c = u32::try_from(i)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| error("chr", format!("{} is not a character code", i)))?;
+                Ok(c.to_value())
+            },
+        },
+        Builtin {
+            name: "toString",
+            arity: 1,
+            cost: unit_cost,
+            call: |args| match &*args[0] {
+                Value::Integer(_) | Value::Float(_) => Ok(args[0].to_string().to_value()),
+                _ => Err(error(
+                    "toString",
+                    format!("expected a number, got {}", args[0]),
+                )),
+            },
+        },
+        Builtin {
+            name: "real",
+            arity: 1,
+            cost: unit_cost,
+            call: |args| {
+                let i: i64 = arg("real", "an integer", &args[0])?;
+                Ok((i as f64).to_value())
+            },
+        },
+        Builtin {
+            name: "floor",
+            arity: 1,
+            cost: unit_cost,
+            call: |args| to_int("floor", &args[0], f64::floor),
+        },
+        Builtin {
+            name: "ceil",
+            arity: 1,
+            cost: unit_cost,
+            call: |args| to_int("ceil", &args[0], f64::ceil),
+        },
+        Builtin {
+            name: "round",
+            arity: 1,
+            cost: unit_cost,
+            call: |args| to_int("round", &args[0], f64::round),
+        },
+        Builtin {
+            name: "trunc",
+            arity: 1,
+            cost: unit_cost,
+            call: |args| to_int("trunc", &args[0], f64::trunc),
+        },
+        Builtin {
+            name: "log",
+            arity: 1,
+            cost: unit_cost,
+            call: |args| match &*args[0] {
+                Value::Integer(n) if *n > 0 => {
+                    Ok((64 - (*n - 1).leading_zeros() as i64).to_value())
+                }
+                Value::Float(x) => Ok(x.log2().to_value()),
+                _ => Err(error(
+                    "log",
+                    format!("expected a positive integer or a float, got {}", args[0]),
+                )),
+            },
+        },
+        Builtin {
+            name: "sqrt",
+            arity: 1,
+            cost: unit_cost,
+            call: |args| {
+                let x: f64 = arg("sqrt", "a float", &args[0])?;
+                Ok(x.sqrt().to_value())
+            },
+        },
+        Builtin {
+            name: "print",
+            arity: 1,
+            cost: unit_cost,
+            call: |args| {
+                let s: String = arg("print", "a string", &args[0])?;
+                print!("{}", s);
+                io::stdout().flush().ok();
+                Ok(().to_value())
+            },
+        },
+    ];
+
+    builtins
+        .into_iter()
+        .map(|builtin| Arc::new(builtin) as Arc<dyn NativeFn>)
+        .collect()
+}
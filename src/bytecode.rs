@@ -0,0 +1,746 @@
+//! Compilation of expressions to bytecode.
+//!
+//! Each lambda, and the main expression, is compiled to a function whose variables are resolved
+//! at compile time: the variables bound in the function are stored in numbered slots of its frame,
+//! and the free ones are captured by value when the closure is created.  The free variables of the
+//! main expression are captured from the environment.
+//!
+//! The instructions operate on a stack of values paired with the cost of evaluating them, combined
+//! by the same rules as `Env::eval_expr`.  See `vm` for their execution.  Applications of lifted
+//! operators are compiled to the operators, charged as much as applying their lambdas.
+
+use std::sync::Arc;
+
+use crate::native::NativeFn;
+use crate::semantics::{Env, Value};
+use crate::syntax::{Arm, BinaryOp, CostMeasure, Ctor, Expr, Lifted, Pattern, Span, UnaryOp};
+use crate::syntax::{Value as SynValue, Var};
+
+/// Where the value of a variable is found.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Slot {
+    /// In the frame of the function.
+    Local(usize),
+    /// Among the captured values of the closure.
+    Captured(usize),
+    /// The closure itself, for a recursive function's own name.
+    This,
+}
+
+/// A pattern whose variables are resolved to slots of the frame.
+#[derive(Debug, Clone)]
+pub(crate) enum SlotPattern {
+    Wildcard,
+    Var {
+        var: Var,
+        slot: usize,
+    },
+    Integer(i64),
+    Boolean(bool),
+    String(String),
+    Char(char),
+    Tuple(Vec<SlotPattern>),
+    Ctor {
+        ctor: Ctor,
+        inner: Option<Box<SlotPattern>>,
+    },
+    As {
+        var: Var,
+        slot: usize,
+        inner: Box<SlotPattern>,
+    },
+    Or {
+        lhs: Box<SlotPattern>,
+        rhs: Box<SlotPattern>,
+    },
+}
+
+impl SlotPattern {
+    /// Returns the pattern in the syntax, for errors.
+    pub(crate) fn to_pattern(&self) -> Pattern {
+        match self {
+            SlotPattern::Wildcard => Pattern::Wildcard,
+            SlotPattern::Var { var, .. } => Pattern::Var(var.clone()),
+            SlotPattern::Integer(i) => Pattern::Integer(*i),
+            SlotPattern::Boolean(b) => Pattern::Boolean(*b),
+            SlotPattern::String(s) => Pattern::String(s.clone()),
+            SlotPattern::Char(c) => Pattern::Char(*c),
+            SlotPattern::Tuple(patterns) => {
+                Pattern::Tuple(patterns.iter().map(|p| p.to_pattern()).collect())
+            }
+            SlotPattern::Ctor { ctor, inner } => Pattern::Ctor {
+                ctor: ctor.clone(),
+                inner: inner.as_ref().map(|inner| Box::new(inner.to_pattern())),
+            },
+            SlotPattern::As { var, inner, .. } => Pattern::As {
+                var: var.clone(),
+                inner: Box::new(inner.to_pattern()),
+            },
+            SlotPattern::Or { lhs, rhs } => Pattern::Or {
+                lhs: Box::new(lhs.to_pattern()),
+                rhs: Box::new(rhs.to_pattern()),
+            },
+        }
+    }
+}
+
+/// A value built by an instruction.
+#[derive(Debug, Clone)]
+pub(crate) enum Template {
+    Const(Arc<Value>),
+    Tuple(Vec<Template>),
+    Ctor {
+        ctor: Ctor,
+        inner: Box<Template>,
+    },
+    Native {
+        func: Arc<dyn NativeFn>,
+        args: Vec<Template>,
+    },
+    /// A closure of `func`, capturing the values in `captures`.
+    Closure {
+        func: Arc<Func>,
+        captures: Vec<Slot>,
+    },
+}
+
+/// Instructions.  Unless noted otherwise, an instruction pops its operands from the stack and
+/// pushes its result.  A marker is a unit value carrying a cost.
+#[derive(Debug, Clone)]
+pub(crate) enum Instr {
+    /// Pushes the value of a variable with cost 1.
+    Load(Slot),
+    /// Fails because the variable is unbound.
+    Unbound(Var),
+    /// Pushes a value with cost 1.
+    Value(Template),
+    UnaryOp(UnaryOp),
+    BinaryOp(BinaryOp),
+    /// Pops the operands of a lifted binary operator and applies it, with the cost of applying the
+    /// lambda in which the operator is applied to them.
+    LiftedBinaryOp(BinaryOp),
+    /// Pops `len` components and pushes their tuple.
+    Tuple {
+        len: usize,
+        par: bool,
+    },
+    /// Pushes a marker.
+    Cost {
+        work: u64,
+        span: u64,
+    },
+    /// Adds a step to the work and span of the top.
+    Step,
+    /// Pops the top and the one below it, and pushes the top's value with the sum of their costs.
+    Seq,
+    /// Same as `Seq`, but with the maximum of their spans.
+    Par,
+    /// Marks the top as evaluated in parallel with what is evaluated above it, for the cost of an
+    /// exception raised meanwhile.
+    Fork,
+    /// Clears the cost of the top, the bound of a cost contract, which is not counted.
+    Uncounted,
+    /// Pops a condition and pushes a marker with its cost and a step.  Jumps to `target` if it is
+    /// false.
+    Branch {
+        target: usize,
+    },
+    Jump(usize),
+    /// Pops the left operand of a short-circuit operator.  Pushes it with a step and jumps to
+    /// `target` if it decides the result, and pushes a marker with its cost and a step otherwise.
+    ShortCircuit {
+        or: bool,
+        target: usize,
+    },
+    /// Checks that the right operand of a short-circuit operator, on the top, is a boolean.
+    CheckBool,
+    /// Matches the top against the pattern, leaving it on the stack.
+    Bind(SlotPattern),
+    /// Matches the scrutinee on the top against the pattern of an arm, leaving it on the stack.
+    /// Jumps to `target` if it does not match.
+    MatchArm {
+        pattern: SlotPattern,
+        target: usize,
+    },
+    /// Pops a guard and adds its cost to the scrutinee below.  Jumps to `target` if it is false.
+    Guard {
+        target: usize,
+    },
+    /// Fails because no arm matches the scrutinee on the top.
+    NoMatch {
+        patterns: Vec<Arc<Pattern>>,
+    },
+    /// Catches the exceptions raised until the matching `PopHandler`.  A caught exception is
+    /// pushed as the scrutinee of the handler's arms at `target`.
+    PushHandler {
+        target: usize,
+    },
+    PopHandler,
+    /// Forgets the exception caught by the handler whose arm was evaluated.
+    EndHandle,
+    /// Raises again the exception caught by the handler, which no arm matches.
+    Reraise,
+    Raise {
+        location: Span,
+    },
+    Assert {
+        location: Span,
+    },
+    /// Pops the inner expression and then its bounds, and pushes back the inner expression if
+    /// its cost is within them.
+    CheckCost {
+        measures: Vec<CostMeasure>,
+        location: Span,
+    },
+    /// Pops the count of a tick, checking that it is a non-negative integer, and pushes a marker
+    /// charging it to the counter.
+    Tick(Var),
+    /// Pops an argument and a function, and applies the function.  A closure called in tail
+    /// position replaces the frame of the caller, whose remaining cost it takes over.
+    Call {
+        tail: bool,
+    },
+    /// Pops the `len` components of a tuple argument and a function, and applies the function
+    /// to their tuple, which a tuple parameter binds without it being built.
+    CallTuple {
+        len: usize,
+        par: bool,
+        tail: bool,
+    },
+    /// Returns from the function with the top.
+    Return,
+}
+
+/// The code of a function.
+#[derive(Debug)]
+pub(crate) struct Code {
+    pub(crate) instrs: Vec<Instr>,
+    /// The names of the slots of the frame.
+    pub(crate) names: Vec<Var>,
+}
+
+/// A compiled lambda, or main expression.
+#[derive(Debug)]
+pub struct Func {
+    pub(crate) name: Option<Var>,
+    pub(crate) pattern: Arc<Pattern>,
+    pub(crate) expr: Arc<Expr>,
+    /// The pattern of the parameter, if it is a lambda.
+    pub(crate) param: Option<SlotPattern>,
+    /// The names of the captured variables.
+    pub(crate) captures: Vec<Var>,
+    pub(crate) code: Code,
+}
+
+/// The state of compiling a function.
+#[derive(Debug)]
+struct FuncState {
+    name: Option<Var>,
+    /// The variables in scope with their slots, innermost last.
+    scope: Vec<(Var, usize)>,
+    names: Vec<Var>,
+    captures: Vec<Var>,
+    /// Where the captured variables are found in the enclosing function, if any.
+    sources: Vec<Slot>,
+    instrs: Vec<Instr>,
+}
+
+impl FuncState {
+    fn new(name: Option<Var>) -> Self {
+        Self {
+            name,
+            scope: vec![],
+            names: vec![],
+            captures: vec![],
+            sources: vec![],
+            instrs: vec![],
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Compiler<'a> {
+    env: &'a Env,
+    /// The functions being compiled, innermost last.
+    funcs: Vec<FuncState>,
+    /// The values captured by the main expression from the environment.
+    globals: Vec<Arc<Value>>,
+}
+
+impl Compiler<'_> {
+    fn func(&mut self) -> &mut FuncState {
+        self.funcs.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, instr: Instr) -> usize {
+        let instrs = &mut self.func().instrs;
+        instrs.push(instr);
+        instrs.len() - 1
+    }
+
+    fn here(&mut self) -> usize {
+        self.func().instrs.len()
+    }
+
+    /// Sets the target of the jump at `at` to here.
+    fn patch(&mut self, at: usize) {
+        let here = self.here();
+        match &mut self.func().instrs[at] {
+            Instr::Branch { target }
+            | Instr::Jump(target)
+            | Instr::ShortCircuit { target, .. }
+            | Instr::MatchArm { target, .. }
+            | Instr::Guard { target }
+            | Instr::PushHandler { target } => *target = here,
+            instr => unreachable!("{:?} does not jump", instr),
+        }
+    }
+
+    /// Resolves a variable in the `depth`-th function, capturing it if it is free there.
+    fn resolve(&mut self, depth: usize, var: &Var) -> Option<Slot> {
+        let func = &self.funcs[depth];
+        if let Some((_, slot)) = func.scope.iter().rev().find(|(v, _)| v == var) {
+            return Some(Slot::Local(*slot));
+        }
+        if func.name.as_ref() == Some(var) {
+            return Some(Slot::This);
+        }
+        if let Some(i) = func.captures.iter().position(|v| v == var) {
+            return Some(Slot::Captured(i));
+        }
+
+        if depth == 0 {
+            let value = self.env.eval_var(var).ok()?;
+            self.globals.push(value);
+        } else {
+            let source = self.resolve(depth - 1, var)?;
+            self.funcs[depth].sources.push(source);
+        }
+        let func = &mut self.funcs[depth];
+        func.captures.push(var.clone());
+        Some(Slot::Captured(func.captures.len() - 1))
+    }
+
+    /// Allocates the slots of the variables of a pattern, returning it with the variables, which
+    /// the caller brings into scope.
+    fn binder(&mut self, pattern: &Pattern) -> (SlotPattern, Vec<(Var, usize)>) {
+        let mut vars = Vec::<(Var, usize)>::new();
+        for var in pattern.vars() {
+            if !vars.iter().any(|(v, _)| *v == var) {
+                let func = self.func();
+                func.names.push(var.clone());
+                vars.push((var, func.names.len() - 1));
+            }
+        }
+        (slot_pattern(pattern, &vars), vars)
+    }
+
+    fn template(&mut self, value: &SynValue) -> Template {
+        let template = match value {
+            SynValue::Tuple(values) => {
+                Template::Tuple(values.iter().map(|value| self.template(value)).collect())
+            }
+            SynValue::Ctor {
+                ctor,
+                inner: Some(inner),
+            } => Template::Ctor {
+                ctor: ctor.clone(),
+                inner: Box::new(self.template(inner)),
+            },
+            SynValue::Native { func, args } => Template::Native {
+                func: func.clone(),
+                args: args.iter().map(|arg| self.template(arg)).collect(),
+            },
+            SynValue::Lambda {
+                name,
+                pattern,
+                expr,
+                ..
+            } => {
+                let (func, captures) = self.lambda(name, pattern, expr);
+                return Template::Closure { func, captures };
+            }
+            _ => {
+                return Template::Const(Env::new().eval_value(value).expect("constants are closed"))
+            }
+        };
+
+        // Folds the values without closures into constants.
+        let consts = |templates: &[Template]| {
+            templates
+                .iter()
+                .map(|template| match template {
+                    Template::Const(value) => Some(value.clone()),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()
+        };
+        match &template {
+            Template::Tuple(templates) => match consts(templates) {
+                Some(values) => Template::Const(Arc::new(Value::Tuple(values))),
+                None => template,
+            },
+            Template::Ctor { ctor, inner } => match &**inner {
+                Template::Const(inner) => Template::Const(Arc::new(Value::Ctor {
+                    ctor: ctor.clone(),
+                    inner: Some(inner.clone()),
+                })),
+                _ => template,
+            },
+            Template::Native { func, args } => match consts(args) {
+                Some(args) => Template::Const(Arc::new(Value::Native {
+                    func: func.clone(),
+                    args,
+                })),
+                None => template,
+            },
+            _ => template,
+        }
+    }
+
+    /// Compiles a lambda, returning it with where its captured values are found.
+    fn lambda(
+        &mut self,
+        name: &Option<Var>,
+        pattern: &Arc<Pattern>,
+        expr: &Arc<Expr>,
+    ) -> (Arc<Func>, Vec<Slot>) {
+        self.funcs.push(FuncState::new(name.clone()));
+        let (param, vars) = self.binder(pattern);
+        self.func().scope.extend(vars);
+        self.expr_at(expr, true);
+        self.emit(Instr::Return);
+        let state = self.funcs.pop().unwrap();
+
+        let func = Func {
+            name: name.clone(),
+            pattern: pattern.clone(),
+            expr: expr.clone(),
+            param: Some(param),
+            captures: state.captures,
+            code: Code {
+                instrs: state.instrs,
+                names: state.names,
+            },
+        };
+        (Arc::new(func), state.sources)
+    }
+
+    /// Compiles the application of the lambda of a lifted operator to its first operand, which
+    /// creates no closure but costs as much: a step for the lambda, one for the application, and
+    /// those of its body.
+    fn lifted_operand(&mut self, operand: &Expr) {
+        self.emit(Instr::Cost { work: 1, span: 1 });
+        self.emit(Instr::Fork);
+        self.expr(operand);
+        self.emit(Instr::Par);
+        self.emit(Instr::Step);
+    }
+
+    /// Compiles the components of a tuple, forking all but the last if they are parallel.
+    fn components(&mut self, exprs: &[Expr], par: bool) {
+        for (i, component) in exprs.iter().enumerate() {
+            self.expr(component);
+            if par && i + 1 < exprs.len() {
+                self.emit(Instr::Fork);
+            }
+        }
+    }
+
+    /// Compiles the arms of a case or handler, whose scrutinee is on the stack.  `end_arm` ends
+    /// each arm, and `on_fail` is executed when no arm matches.  The arms are in tail position if
+    /// `tail` is set.
+    fn arms(&mut self, arms: &[Arm], end_arm: Option<Instr>, on_fail: Instr, tail: bool) {
+        let mut ends = vec![];
+        for arm in arms.iter() {
+            let (pattern, vars) = self.binder(&arm.pattern);
+            let len = self.func().scope.len();
+            self.func().scope.extend(vars);
+
+            let test = self.emit(Instr::MatchArm { pattern, target: 0 });
+            let guard = arm.guard.as_ref().map(|guard| {
+                self.expr(guard);
+                self.emit(Instr::Guard { target: 0 })
+            });
+            self.emit(Instr::Step);
+            self.expr_at(&arm.expr, tail);
+            self.emit(Instr::Seq);
+            if let Some(end_arm) = &end_arm {
+                self.emit(end_arm.clone());
+            }
+            ends.push(self.emit(Instr::Jump(0)));
+
+            self.func().scope.truncate(len);
+            self.patch(test);
+            if let Some(guard) = guard {
+                self.patch(guard);
+            }
+        }
+
+        self.emit(on_fail);
+        for end in ends {
+            self.patch(end);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        self.expr_at(expr, false)
+    }
+
+    /// Compiles an expression, which is in tail position if `tail` is set.  The positions are those
+    /// in which `Env::eval_expr` leaves a call pending.
+    fn expr_at(&mut self, expr: &Expr, tail: bool) {
+        match expr {
+            // Resolves variables to slots by name, regardless of their lexical addresses.
+            Expr::Var(var) | Expr::Resolved { var, .. } => {
+                let depth = self.funcs.len() - 1;
+                let instr = match self.resolve(depth, var) {
+                    Some(slot) => Instr::Load(slot),
+                    None => Instr::Unbound(var.clone()),
+                };
+                self.emit(instr);
+            }
+            Expr::Value(value) => {
+                let template = self.template(value);
+                self.emit(Instr::Value(template));
+            }
+            Expr::UnaryOp { op, inner } => {
+                self.expr(inner);
+                self.emit(Instr::UnaryOp(*op));
+            }
+            Expr::BinaryOp { op, lhs, rhs } => {
+                self.expr(lhs);
+                self.emit(Instr::Fork);
+                self.expr(rhs);
+                self.emit(Instr::BinaryOp(*op));
+            }
+            Expr::SeqTuple(exprs) | Expr::ParTuple(exprs) => {
+                let par = matches!(expr, Expr::ParTuple(_));
+                self.components(exprs, par);
+                self.emit(Instr::Tuple {
+                    len: exprs.len(),
+                    par,
+                });
+            }
+            Expr::Case { inner, arms, .. } => {
+                self.expr(inner);
+                let patterns = arms.iter().map(|arm| arm.pattern.clone()).collect();
+                self.arms(arms, None, Instr::NoMatch { patterns }, tail);
+            }
+            Expr::Raise { inner, span } => {
+                self.expr(inner);
+                self.emit(Instr::Raise { location: *span });
+            }
+            Expr::Assert { inner, span } => {
+                self.expr(inner);
+                self.emit(Instr::Assert { location: *span });
+            }
+            Expr::WithCost {
+                bounds,
+                inner,
+                span,
+            } => {
+                for (_, bound) in bounds.iter() {
+                    self.expr(bound);
+                    self.emit(Instr::Uncounted);
+                }
+                self.expr(inner);
+                self.emit(Instr::CheckCost {
+                    measures: bounds.iter().map(|(measure, _)| *measure).collect(),
+                    location: *span,
+                });
+            }
+            Expr::Tick { name, count, inner } => {
+                self.expr(count);
+                self.emit(Instr::Tick(name.clone()));
+                self.expr_at(inner, tail);
+                self.emit(Instr::Seq);
+            }
+            Expr::Handle { inner, arms } => {
+                let handler = self.emit(Instr::PushHandler { target: 0 });
+                self.expr(inner);
+                self.emit(Instr::PopHandler);
+                self.emit(Instr::Step);
+                let end = self.emit(Instr::Jump(0));
+                self.patch(handler);
+                self.arms(arms, Some(Instr::EndHandle), Instr::Reraise, false);
+                self.patch(end);
+            }
+            Expr::Ite { cond, lhs, rhs } => {
+                self.expr(cond);
+                let branch = self.emit(Instr::Branch { target: 0 });
+                self.expr_at(lhs, tail);
+                let end = self.emit(Instr::Jump(0));
+                self.patch(branch);
+                self.expr_at(rhs, tail);
+                self.patch(end);
+                self.emit(Instr::Seq);
+            }
+            Expr::AndAlso { lhs, rhs } | Expr::OrElse { lhs, rhs } => {
+                self.expr(lhs);
+                let short_circuit = self.emit(Instr::ShortCircuit {
+                    or: matches!(expr, Expr::OrElse { .. }),
+                    target: 0,
+                });
+                self.expr(rhs);
+                self.emit(Instr::CheckBool);
+                self.emit(Instr::Seq);
+                self.patch(short_circuit);
+            }
+            // Applies lifted operators directly, whose lambdas load their operands in a step.
+            Expr::App { lhs, rhs, .. } => match lifted_app(lhs) {
+                Some((Lifted::Unary(op), _)) => {
+                    self.lifted_operand(rhs);
+                    self.emit(Instr::Step);
+                    self.emit(Instr::UnaryOp(op));
+                }
+                Some((Lifted::Binary(op), Some(operand))) => {
+                    self.lifted_operand(operand);
+                    self.emit(Instr::Step);
+                    self.emit(Instr::Fork);
+                    self.expr(rhs);
+                    self.emit(Instr::LiftedBinaryOp(op));
+                }
+                _ => {
+                    self.expr(lhs);
+                    self.emit(Instr::Fork);
+                    match &**rhs {
+                        Expr::SeqTuple(exprs) | Expr::ParTuple(exprs) if !exprs.is_empty() => {
+                            let par = matches!(**rhs, Expr::ParTuple(_));
+                            self.components(exprs, par);
+                            self.emit(Instr::CallTuple {
+                                len: exprs.len(),
+                                par,
+                                tail,
+                            });
+                        }
+                        _ => {
+                            self.expr(rhs);
+                            self.emit(Instr::Call { tail });
+                        }
+                    }
+                }
+            },
+            Expr::Let { binds, expr } => {
+                let len = self.func().scope.len();
+                self.emit(Instr::Cost { work: 0, span: 0 });
+                for bind in binds.iter() {
+                    self.expr(&bind.expr);
+                    let (pattern, vars) = self.binder(&bind.pattern);
+                    self.func().scope.extend(vars);
+                    self.emit(Instr::Bind(pattern));
+                    self.emit(Instr::Seq);
+                }
+                self.emit(Instr::Step);
+                self.expr_at(expr, tail);
+                self.emit(Instr::Seq);
+                self.func().scope.truncate(len);
+            }
+            Expr::ParLet { binds, expr } => {
+                // The bindings are brought into scope only after all of them are evaluated.
+                let mut bound = vec![];
+                self.emit(Instr::Cost { work: 0, span: 0 });
+                for bind in binds.iter() {
+                    self.emit(Instr::Fork);
+                    self.expr(&bind.expr);
+                    let (pattern, vars) = self.binder(&bind.pattern);
+                    bound.extend(vars);
+                    self.emit(Instr::Bind(pattern));
+                    self.emit(Instr::Par);
+                }
+
+                let len = self.func().scope.len();
+                self.func().scope.extend(bound);
+                self.emit(Instr::Step);
+                self.expr(expr);
+                self.emit(Instr::Seq);
+                self.func().scope.truncate(len);
+            }
+        }
+    }
+}
+
+/// Returns the operator of a unary operator's lambda, or of a binary operator's applied to its first
+/// operand, which is returned with it.
+fn lifted_app(expr: &Expr) -> Option<(Lifted<'_>, Option<&Expr>)> {
+    match expr {
+        Expr::Value(value) => match value.lifted()? {
+            lifted @ Lifted::Unary(_) => Some((lifted, None)),
+            _ => None,
+        },
+        Expr::App { lhs, rhs, .. } => match &**lhs {
+            Expr::Value(value) => match value.lifted()? {
+                lifted @ Lifted::Binary(_) => Some((lifted, Some(rhs))),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Resolves the variables of a pattern to their slots.
+fn slot_pattern(pattern: &Pattern, vars: &[(Var, usize)]) -> SlotPattern {
+    let slot = |var: &Var| vars.iter().find(|(v, _)| v == var).unwrap().1;
+    match pattern {
+        Pattern::Wildcard => SlotPattern::Wildcard,
+        Pattern::Var(var) => SlotPattern::Var {
+            var: var.clone(),
+            slot: slot(var),
+        },
+        Pattern::Integer(i) => SlotPattern::Integer(*i),
+        Pattern::Boolean(b) => SlotPattern::Boolean(*b),
+        Pattern::String(s) => SlotPattern::String(s.clone()),
+        Pattern::Char(c) => SlotPattern::Char(*c),
+        Pattern::Tuple(patterns) => SlotPattern::Tuple(
+            patterns
+                .iter()
+                .map(|pattern| slot_pattern(pattern, vars))
+                .collect(),
+        ),
+        Pattern::Ctor { ctor, inner } => SlotPattern::Ctor {
+            ctor: ctor.clone(),
+            inner: inner
+                .as_ref()
+                .map(|inner| Box::new(slot_pattern(inner, vars))),
+        },
+        Pattern::As { var, inner } => SlotPattern::As {
+            var: var.clone(),
+            slot: slot(var),
+            inner: Box::new(slot_pattern(inner, vars)),
+        },
+        Pattern::Or { lhs, rhs } => SlotPattern::Or {
+            lhs: Box::new(slot_pattern(lhs, vars)),
+            rhs: Box::new(slot_pattern(rhs, vars)),
+        },
+    }
+}
+
+/// Compiles an expression evaluated in `env`, returning the closure of its function with the
+/// values it captures from `env`.
+pub(crate) fn compile(env: &Env, expr: &Expr) -> Arc<Value> {
+    let mut compiler = Compiler {
+        env,
+        funcs: vec![FuncState::new(None)],
+        globals: vec![],
+    };
+    compiler.expr_at(expr, true);
+    compiler.emit(Instr::Return);
+    let state = compiler.funcs.pop().unwrap();
+
+    let func = Func {
+        name: None,
+        pattern: Arc::new(Pattern::Wildcard),
+        expr: Arc::new(expr.clone()),
+        param: None,
+        captures: state.captures,
+        code: Code {
+            instrs: state.instrs,
+            names: state.names,
+        },
+    };
+    Arc::new(Value::Closure {
+        func: Arc::new(func),
+        captured: compiler.globals,
+    })
+}
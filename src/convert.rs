@@ -0,0 +1,225 @@
+//! Conversions between Rust values and SPARC values.
+//!
+//! Tuples, including `()`, are converted to tuples, `Option<T>` to `SOME x` and `NONE`, and `Vec<T>`
+//! to the list `Cons (x1, Cons (x2, ... Nil))`.  Enums can implement the traits with
+//! `#[derive(ToValue, FromValue)]`: a variant `C` is converted to the nullary constructor `C`, a
+//! variant `C(x)` to `C x`, and a variant with several fields to `C` applied to their tuple.
+
+use std::sync::Arc;
+
+use crate::semantics::Value;
+
+/// Types that can be converted to SPARC values.
+pub trait ToValue {
+    /// Converts `self` to a SPARC value.
+    fn to_value(&self) -> Arc<Value>;
+}
+
+/// Types that can be converted from SPARC values.
+pub trait FromValue: Sized {
+    /// Converts a SPARC value, returning `None` if it does not represent a value of `Self`.
+    fn from_value(value: &Arc<Value>) -> Option<Self>;
+}
+
+impl ToValue for Arc<Value> {
+    fn to_value(&self) -> Arc<Value> {
+        self.clone()
+    }
+}
+
+impl FromValue for Arc<Value> {
+    fn from_value(value: &Arc<Value>) -> Option<Self> {
+        Some(value.clone())
+    }
+}
+
+impl ToValue for i64 {
+    fn to_value(&self) -> Arc<Value> {
+        Arc::new(Value::Integer(*self))
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Arc<Value>) -> Option<Self> {
+        value.coerce_int()
+    }
+}
+
+impl ToValue for f64 {
+    fn to_value(&self) -> Arc<Value> {
+        Arc::new(Value::Float(*self))
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Arc<Value>) -> Option<Self> {
+        value.coerce_float()
+    }
+}
+
+impl ToValue for bool {
+    fn to_value(&self) -> Arc<Value> {
+        Arc::new(Value::Boolean(*self))
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Arc<Value>) -> Option<Self> {
+        value.coerce_bool()
+    }
+}
+
+impl ToValue for str {
+    fn to_value(&self) -> Arc<Value> {
+        Arc::new(Value::String(self.into()))
+    }
+}
+
+impl ToValue for String {
+    fn to_value(&self) -> Arc<Value> {
+        self.as_str().to_value()
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Arc<Value>) -> Option<Self> {
+        value.coerce_string().map(String::from)
+    }
+}
+
+impl ToValue for char {
+    fn to_value(&self) -> Arc<Value> {
+        Arc::new(Value::Char(*self))
+    }
+}
+
+impl FromValue for char {
+    fn from_value(value: &Arc<Value>) -> Option<Self> {
+        value.coerce_char()
+    }
+}
+
+impl<T: ToValue + ?Sized> ToValue for &T {
+    fn to_value(&self) -> Arc<Value> {
+        (**self).to_value()
+    }
+}
+
+impl<T: ToValue + ?Sized> ToValue for Box<T> {
+    fn to_value(&self) -> Arc<Value> {
+        (**self).to_value()
+    }
+}
+
+impl<T: FromValue> FromValue for Box<T> {
+    fn from_value(value: &Arc<Value>) -> Option<Self> {
+        T::from_value(value).map(Box::new)
+    }
+}
+
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value(&self) -> Arc<Value> {
+        Arc::new(match self {
+            Some(inner) => Value::Ctor {
+                ctor: "SOME".into(),
+                inner: Some(inner.to_value()),
+            },
+            None => Value::Ctor {
+                ctor: "NONE".into(),
+                inner: None,
+            },
+        })
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Arc<Value>) -> Option<Self> {
+        match &**value {
+            Value::Ctor { ctor, inner: None } if ctor == "NONE" => Some(None),
+            Value::Ctor {
+                ctor,
+                inner: Some(inner),
+            } if ctor == "SOME" => T::from_value(inner).map(Some),
+            _ => None,
+        }
+    }
+}
+
+impl<T: ToValue> ToValue for [T] {
+    fn to_value(&self) -> Arc<Value> {
+        let mut list = Arc::new(Value::Ctor {
+            ctor: "Nil".into(),
+            inner: None,
+        });
+        for item in self.iter().rev() {
+            list = Arc::new(Value::Ctor {
+                ctor: "Cons".into(),
+                inner: Some(Arc::new(Value::tuple(vec![item.to_value(), list]))),
+            });
+        }
+        list
+    }
+}
+
+impl<T: ToValue> ToValue for Vec<T> {
+    fn to_value(&self) -> Arc<Value> {
+        self.as_slice().to_value()
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(mut value: &Arc<Value>) -> Option<Self> {
+        let mut items = Vec::new();
+        loop {
+            match &**value {
+                Value::Ctor { ctor, inner: None } if ctor == "Nil" => return Some(items),
+                Value::Ctor {
+                    ctor,
+                    inner: Some(inner),
+                } if ctor == "Cons" => match &**inner {
+                    Value::Tuple(values) if values.len() == 2 => {
+                        items.push(T::from_value(&values[0])?);
+                        value = &values[1];
+                    }
+                    _ => return None,
+                },
+                _ => return None,
+            }
+        }
+    }
+}
+
+impl ToValue for () {
+    fn to_value(&self) -> Arc<Value> {
+        Arc::new(Value::unit())
+    }
+}
+
+impl FromValue for () {
+    fn from_value(value: &Arc<Value>) -> Option<Self> {
+        value.coerce_tuple(0).map(|_| ())
+    }
+}
+
+macro_rules! impl_tuple {
+    ($len:expr; $($name:ident $index:tt),*) => {
+        impl<$($name: ToValue),*> ToValue for ($($name,)*) {
+            fn to_value(&self) -> Arc<Value> {
+                Arc::new(Value::tuple(vec![$(self.$index.to_value()),*]))
+            }
+        }
+
+        impl<$($name: FromValue),*> FromValue for ($($name,)*) {
+            fn from_value(value: &Arc<Value>) -> Option<Self> {
+                let values = value.coerce_tuple($len)?;
+                Some(($($name::from_value(&values[$index])?,)*))
+            }
+        }
+    };
+}
+
+impl_tuple!(2; A 0, B 1);
+impl_tuple!(3; A 0, B 1, C 2);
+impl_tuple!(4; A 0, B 1, C 2, D 3);
+impl_tuple!(5; A 0, B 1, C 2, D 3, E 4);
+impl_tuple!(6; A 0, B 1, C 2, D 3, E 4, F 5);
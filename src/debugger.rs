@@ -0,0 +1,344 @@
+//! Debugging of evaluations.
+//!
+//! A debugged program is evaluated on a thread of its own by the executor with `Env::eval_expr`,
+//! observed by a monitor that suspends the thread whenever the debugger should stop.  While the
+//! evaluation is suspended, the debugger inspects the state the monitor maintains: the
+//! environments of the entered expressions and the active applications.
+
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+use crate::semantics::{EResult, Env, Err, Res, Value};
+use crate::syntax::Expr;
+use crate::trace::{self, abbreviate, Observer};
+
+/// The stack size of the thread of a debugged evaluation, which is that of the main thread.
+const STACK_SIZE: usize = 8 << 20;
+
+/// A breakpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Stops at the located expressions (applications, cases, raises, assertions and cost
+    /// contracts) that start on the line, counting from 1, when entered from another line.
+    Line(usize),
+    /// Stops when the function declared with the name is applied.
+    Function(String),
+}
+
+/// An active application.
+#[derive(Debug, Clone)]
+pub struct Call {
+    /// The name of the applied function, if it is declared with `fun`.
+    pub name: Option<String>,
+    /// The argument of the application.
+    pub arg: Arc<Value>,
+    /// The line of the application, if known.
+    pub line: Option<usize>,
+}
+
+/// Where a debugged evaluation stopped.
+#[derive(Debug, Clone)]
+pub enum Stop {
+    /// Before evaluating the expression `expr`, which is on `line` if known.
+    Enter {
+        /// The line of the expression.
+        line: Option<usize>,
+        /// The expression, abbreviated.
+        expr: String,
+    },
+    /// After the application `call` returned.
+    Return {
+        /// The application.
+        call: Call,
+        /// Its result.
+        res: Result<Res<Arc<Value>>, Err>,
+    },
+    /// The evaluation finished with the result of the main expression, if any.
+    Finished(Result<Option<Res<Arc<Value>>>, Err>),
+}
+
+/// When to stop next.
+#[derive(Debug, Clone, Copy)]
+enum Mode {
+    /// At the next expression.
+    Step,
+    /// At the next expression outside the applications deeper than `calls`.
+    Next { calls: usize },
+    /// When the number of active applications drops below `calls`.
+    Finish { calls: usize },
+    /// At breakpoints only.
+    Continue,
+}
+
+/// The state of a debugged evaluation.
+#[derive(Debug)]
+struct State {
+    mode: Mode,
+    breakpoints: Vec<Breakpoint>,
+    /// The environments of the entered expressions, innermost last.
+    envs: Vec<Env>,
+    /// The lines of the entered expressions, innermost last.  An expression that is not located
+    /// is on the line of the innermost one that is.
+    lines: Vec<Option<usize>>,
+    /// The active applications, innermost last.
+    calls: Vec<Call>,
+    /// Whether a function breakpoint was hit, to stop at the function's body.
+    hit: bool,
+    /// Whether the debugger is gone.
+    aborted: bool,
+}
+
+/// The observer of a debugged evaluation.
+struct Monitor {
+    state: Arc<Mutex<State>>,
+    /// The offsets of the starts of the lines of the input.
+    line_starts: Vec<usize>,
+    stops: Sender<Stop>,
+    resumes: Receiver<()>,
+}
+
+impl Monitor {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    fn line(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|start| *start <= offset)
+    }
+
+    /// Reports the stop to the debugger and waits until it resumes the evaluation.
+    fn suspend(&self, stop: Stop) -> Result<(), Err> {
+        if self.stops.send(stop).is_err() || self.resumes.recv().is_err() {
+            self.state().aborted = true;
+            return Err(Err::Interrupted);
+        }
+        Ok(())
+    }
+}
+
+impl Observer for Monitor {
+    fn enter(&mut self, expr: &Expr, env: &Env) -> Result<(), Err> {
+        let mut state = self.state();
+        if state.aborted {
+            return Err(Err::Interrupted);
+        }
+
+        let outer = state.lines.last().copied().flatten();
+        let located = expr.span().map(|span| self.line(span.start));
+        let line = located.or(outer);
+        state.envs.push(env.clone());
+        state.lines.push(line);
+
+        let stop = match state.mode {
+            Mode::Step => true,
+            Mode::Next { calls } => state.calls.len() <= calls,
+            Mode::Finish { .. } | Mode::Continue => false,
+        } || mem::take(&mut state.hit)
+            || (located.is_some()
+                && located != outer
+                && state.breakpoints.contains(&Breakpoint::Line(line.unwrap())));
+        drop(state);
+
+        if stop {
+            self.suspend(Stop::Enter {
+                line,
+                expr: abbreviate(expr),
+            })?;
+        }
+        Ok(())
+    }
+
+    fn exit(&mut self, _res: &EResult<Arc<Value>>) {
+        let mut state = self.state();
+        state.envs.pop();
+        state.lines.pop();
+    }
+
+    fn call(&mut self, func: &Arc<Value>, arg: &Arc<Value>) {
+        let name = match &**func {
+            Value::Lambda { name, .. } => name.clone(),
+            _ => None,
+        };
+        let mut state = self.state();
+        if let Some(name) = &name {
+            if state
+                .breakpoints
+                .contains(&Breakpoint::Function(name.clone()))
+            {
+                state.hit = true;
+            }
+        }
+        let line = state.lines.last().copied().flatten();
+        state.calls.push(Call {
+            name,
+            arg: arg.clone(),
+            line,
+        });
+    }
+
+    fn ret(&mut self, res: &EResult<Arc<Value>>) {
+        let mut state = self.state();
+        let call = state.calls.pop().unwrap();
+        let stop = match state.mode {
+            Mode::Finish { calls } => state.calls.len() < calls,
+            _ => false,
+        };
+        drop(state);
+
+        if stop {
+            // The evaluation is abandoned at the next expression if the debugger is gone.
+            let _ = self.suspend(Stop::Return {
+                call,
+                res: res.clone(),
+            });
+        }
+    }
+}
+
+/// A suspended evaluation of a program.
+///
+/// The evaluation starts suspended before the program; the first [`step`](Debugger::step) stops
+/// at its first expression.  Dropping the debugger abandons the evaluation.
+pub struct Debugger {
+    state: Arc<Mutex<State>>,
+    stops: Receiver<Stop>,
+    resumes: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+    finished: Option<Result<Option<Res<Arc<Value>>>, Err>>,
+}
+
+impl std::fmt::Debug for Debugger {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Debugger")
+    }
+}
+
+impl Debugger {
+    /// Starts a suspended evaluation with `run`, which evaluates the program from `input`.
+    pub(crate) fn new(
+        run: impl FnOnce() -> Result<Option<Res<Arc<Value>>>, Err> + Send + 'static,
+        input: &str,
+    ) -> Self {
+        let state = Arc::new(Mutex::new(State {
+            mode: Mode::Step,
+            breakpoints: vec![],
+            envs: vec![],
+            lines: vec![],
+            calls: vec![],
+            hit: false,
+            aborted: false,
+        }));
+        let (stop_sender, stops) = mpsc::channel();
+        let (resumes, resume_receiver) = mpsc::channel();
+        let monitor = Monitor {
+            state: state.clone(),
+            line_starts: std::iter::once(0)
+                .chain(input.match_indices('\n').map(|(i, _)| i + 1))
+                .collect(),
+            stops: stop_sender.clone(),
+            resumes: resume_receiver,
+        };
+
+        let thread = thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                if monitor.resumes.recv().is_err() {
+                    return;
+                }
+                let res = trace::with(Some(Box::new(monitor)), run);
+                let _ = stop_sender.send(Stop::Finished(res));
+            })
+            .expect("cannot spawn the thread of the debugged evaluation");
+
+        Self {
+            state,
+            stops,
+            resumes: Some(resumes),
+            thread: Some(thread),
+            finished: None,
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    fn resume(&mut self, mode: impl FnOnce(usize) -> Mode) -> Stop {
+        if let Some(res) = &self.finished {
+            return Stop::Finished(res.clone());
+        }
+
+        {
+            let mut state = self.state();
+            state.mode = mode(state.calls.len());
+        }
+        self.resumes.as_ref().unwrap().send(()).unwrap();
+        let stop = self.stops.recv().expect("the debugged evaluation panicked");
+        if let Stop::Finished(res) = &stop {
+            self.finished = Some(res.clone());
+        }
+        stop
+    }
+
+    /// Resumes the evaluation until the next expression.
+    pub fn step(&mut self) -> Stop {
+        self.resume(|_| Mode::Step)
+    }
+
+    /// Resumes the evaluation until the next expression, stepping over applications.
+    pub fn step_over(&mut self) -> Stop {
+        self.resume(|calls| Mode::Next { calls })
+    }
+
+    /// Resumes the evaluation until the innermost active application returns.
+    pub fn finish(&mut self) -> Stop {
+        self.resume(|calls| Mode::Finish { calls })
+    }
+
+    /// Resumes the evaluation until a breakpoint.
+    pub fn cont(&mut self) -> Stop {
+        self.resume(|_| Mode::Continue)
+    }
+
+    /// Adds a breakpoint.
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        let mut state = self.state();
+        if !state.breakpoints.contains(&breakpoint) {
+            state.breakpoints.push(breakpoint);
+        }
+    }
+
+    /// Removes a breakpoint.  Returns whether it was set.
+    pub fn remove_breakpoint(&mut self, breakpoint: &Breakpoint) -> bool {
+        let mut state = self.state();
+        let len = state.breakpoints.len();
+        state.breakpoints.retain(|b| b != breakpoint);
+        state.breakpoints.len() < len
+    }
+
+    /// Looks up the value bound to `var` in the environment of the expression where the
+    /// evaluation stopped.
+    pub fn lookup(&self, var: &str) -> Result<Arc<Value>, Err> {
+        match self.state().envs.last() {
+            Some(env) => env.eval_var(&var.into()),
+            None => Err(Err::EnvNotFound { var: var.into() }),
+        }
+    }
+
+    /// Returns the active applications, innermost first.
+    pub fn backtrace(&self) -> Vec<Call> {
+        self.state().calls.iter().rev().cloned().collect()
+    }
+}
+
+impl Drop for Debugger {
+    fn drop(&mut self) {
+        self.state().aborted = true;
+        self.resumes = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -1,16 +1,85 @@
-use codespan::{CodeMap, Span};
-use codespan_reporting::termcolor::StandardStream;
-use codespan_reporting::{emit, ColorArg, Diagnostic, Label, Severity};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use codespan_reporting::files::SimpleFile;
+use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
+use codespan_reporting::term::{emit, Config};
 use std::fmt;
-use std::str::FromStr;
+use std::sync::Arc;
 
-use crate::parser::ExprParser;
-use crate::semantics::Env;
+use crate::builtins;
+use crate::convert::{FromValue, ToValue};
+use crate::debugger::Debugger;
+use crate::exhaustive::Signatures;
+use crate::native::NativeFn;
+use crate::parser::{tokens, ExprParser, ProgramParser, SyntaxError};
+use crate::resolve;
+use crate::schedule::{self, WorkStealingOptions};
+use crate::semantics::{self, Allocs, EResult, Env, Err, Extras, Res, Space, Value};
+use crate::substitution;
+use crate::syntax::{Decl, Expr, Program, Type, Value as SynValue};
+use crate::trace::{self, Observer, TraceOptions, Tracer};
+use crate::transpile::{self, CompileError};
+use crate::vm;
 
-/// SPARC expression executor.
+/// Errors reported by the executor.
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// The input does not parse.
+    Parse(lalrpop_util::ParseError<usize, String, SyntaxError>),
+    /// The evaluation failed.
+    Runtime(Err),
+    /// The result does not represent a value of the requested Rust type.
+    Conversion(Arc<Value>),
+    /// The program cannot be compiled.
+    Compile(CompileError),
+}
+
+impl From<Err> for Error {
+    fn from(e: Err) -> Self {
+        Error::Runtime(e)
+    }
+}
+
+impl From<CompileError> for Error {
+    fn from(e: CompileError) -> Self {
+        Error::Compile(e)
+    }
+}
+
+/// The evaluators an executor can use.  They agree on values, work/span and ticks, and only the
+/// environment-based evaluator measures space, if requested.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// The environment-based big-step evaluator.
+    Env,
+    /// The substitution-based small-step evaluator, which prints each intermediate program to
+    /// stderr if `print_steps` is set.
+    Substitution {
+        /// Whether to print the intermediate programs.
+        print_steps: bool,
+    },
+    /// The virtual machine, which executes expressions compiled to bytecode with their variables
+    /// resolved to slots.
+    Bytecode,
+}
+
+/// Prints an intermediate program of a reduction.
+fn print_step(expr: &Expr) {
+    eprintln!("--> {}", expr);
+}
+
+/// SPARC program executor.
 pub struct Executor {
     parser: ExprParser,
+    program_parser: ProgramParser,
     env: Env,
+    signatures: Signatures,
+    trace: Option<TraceOptions>,
+    work_stealing: Option<WorkStealingOptions>,
+    space: bool,
+    backend: Backend,
+    /// Whether the evaluations are observed by a debugger rather than traced.  Their variables are
+    /// left unresolved, so that closures keep the whole environment to inspect.
+    debugged: bool,
 }
 
 impl fmt::Debug for Executor {
@@ -19,61 +88,492 @@ impl fmt::Debug for Executor {
     }
 }
 
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Executor {
-    /// Creates a new executor.
+    /// Creates a new executor, whose environment contains the builtin functions, and which declares
+    /// the builtin exceptions of violated assertions and cost contracts.
     pub fn new() -> Self {
+        let env_piece = builtins::all()
+            .into_iter()
+            .map(|func| {
+                let name = func.name().into();
+                (name, Arc::new(Value::Native { func, args: vec![] }))
+            })
+            .collect();
+        let mut signatures = Signatures::default();
+        signatures.declare_exception(&semantics::ASSERTION_FAILED.into(), &None);
+        let string = Type::Con {
+            name: "string".into(),
+            args: vec![],
+        };
+        signatures.declare_exception(&semantics::COST_CONTRACT_VIOLATED.into(), &Some(string));
+
         Self {
             parser: ExprParser::new(),
-            env: Env::new(),
+            program_parser: ProgramParser::new(),
+            env: Env::new().insert(env_piece),
+            signatures,
+            trace: None,
+            work_stealing: None,
+            space: false,
+            backend: Backend::Env,
+            debugged: false,
         }
     }
 
-    /// Executes an expression.
-    pub fn exec(&self, input: &str) {
-        let expr = match self.parser.parse(input) {
-            Ok(expr) => expr,
-            Err(e) => {
-                let mut codemap = CodeMap::new();
-                let _filemap = codemap.add_filemap("input".into(), input.into());
-                let writer = StandardStream::stderr(ColorArg::from_str("auto").unwrap().into());
-                let error = match e {
-                    lalrpop_util::ParseError::InvalidToken { location } => {
-                        Diagnostic::new(Severity::Error, "Invalid token").with_label(
-                            Label::new_primary(Span::from_offset(
-                                (location as u32).into(),
-                                1.into(),
-                            )),
-                        )
-                    }
-                    lalrpop_util::ParseError::UnrecognizedToken { token, .. } => {
-                        let error = Diagnostic::new(Severity::Error, "Unrecognized token");
-                        if let Some((start, _, end)) = token {
-                            error.with_label(Label::new_primary(Span::new(
-                                ((start + 1) as u32).into(),
-                                ((end + 1) as u32).into(),
-                            )))
-                        } else {
-                            error.with_label(Label::new_primary(Span::from_offset(
-                                (input.len() as u32).into(),
-                                1.into(),
-                            )))
-                        }
+    /// Registers a native function, binding it under its name in the environment of subsequently
+    /// executed expressions.
+    pub fn register<F: NativeFn + 'static>(&mut self, func: F) {
+        assert!(
+            func.arity() > 0,
+            "native function {} has arity 0",
+            func.name()
+        );
+
+        let name = func.name().to_string();
+        let value = Arc::new(Value::Native {
+            func: Arc::new(func),
+            args: vec![],
+        });
+        self.bind(&name, value);
+    }
+
+    /// Sets whether, and how, subsequent evaluations are traced to stderr.
+    pub fn set_trace(&mut self, options: Option<TraceOptions>) {
+        self.trace = options;
+    }
+
+    /// Sets whether, and how, work stealing is simulated on the DAGs of the main expressions of
+    /// subsequently executed programs.  Only the `Env` backend records the DAGs.
+    pub fn set_work_stealing(&mut self, options: Option<WorkStealingOptions>) {
+        if let Some(options) = options {
+            assert!(options.workers > 0, "work stealing needs a worker");
+            assert!(options.seeds > 0, "work stealing needs a seed");
+        }
+        self.work_stealing = options;
+    }
+
+    /// Sets whether subsequent evaluations measure their space, which slows them down.  Only the
+    /// `Env` backend measures it; the space of other evaluations is empty.
+    pub fn set_space(&mut self, measure: bool) {
+        self.space = measure;
+    }
+
+    /// Sets the evaluator of subsequent evaluations.  Tracing applies only to the `Env` backend.
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    /// Returns the tracer of evaluations, if they are traced.
+    fn tracer(&self) -> Option<Box<dyn Observer>> {
+        self.trace
+            .map(|options| Box::new(Tracer::new(options)) as Box<dyn Observer>)
+    }
+
+    /// Checks that the raises in `expr` raise declared exceptions, as far as their operands show.
+    fn check_raises(&self, expr: &Expr) -> Result<(), Err> {
+        let mut result = Ok(());
+        expr.walk(&mut |expr| {
+            if let Expr::Raise { inner, span } = expr {
+                if result.is_ok() && self.signatures.not_exception(inner) {
+                    result = Err(Err::NotAnException { location: *span });
+                }
+            }
+        });
+        result
+    }
+
+    /// Evaluates an expression in the environment with the backend, tracing it if requested.
+    fn eval_expr(&self, expr: &Expr) -> EResult<Arc<Value>> {
+        self.check_raises(expr)?;
+        semantics::with_exceptions(self.signatures.exceptions(), || match self.backend {
+            Backend::Env if self.debugged => self.env.eval_expr(expr),
+            Backend::Env => {
+                let expr = resolve::resolve(&self.env, expr);
+                semantics::measure_space(self.space, || {
+                    trace::with(self.tracer(), || self.env.eval_expr(&expr))
+                })
+            }
+            Backend::Substitution { print_steps } => {
+                substitution::eval_expr(&self.env, expr, print_steps.then_some(&mut print_step))
+            }
+            Backend::Bytecode => vm::eval_expr(&self.env, expr),
+        })
+    }
+
+    /// Evaluates an expression.
+    pub fn eval(&self, input: &str) -> Result<Res<Arc<Value>>, Error> {
+        let expr = self
+            .parser
+            .parse(tokens(input))
+            .map_err(|e| Error::Parse(e.map_token(|token| token.to_string())))?;
+        Ok(self.eval_expr(&expr)?)
+    }
+
+    /// Evaluates an expression and binds its value to `var` in the environment of subsequently
+    /// executed expressions.
+    pub fn define(&mut self, var: &str, input: &str) -> Result<Res<Arc<Value>>, Error> {
+        let res = self.eval(input)?;
+        self.bind(var, res.result.clone());
+        Ok(res)
+    }
+
+    /// Binds a Rust value to `var` in the environment of subsequently executed expressions.
+    pub fn bind<T: ToValue>(&mut self, var: &str, value: T) {
+        self.env = self.env.define(var.into(), value.to_value());
+    }
+
+    /// Looks up the value bound to `var`.
+    pub fn lookup(&self, var: &str) -> Result<Arc<Value>, Error> {
+        Ok(self.env.eval_var(&var.into())?)
+    }
+
+    /// Applies the function bound to `var` to a Rust value, converting the result back to Rust.
+    /// Functions of several arguments take them as a tuple.
+    pub fn call<A: ToValue, R: FromValue>(&self, var: &str, arg: A) -> Result<Res<R>, Error> {
+        self.apply(&self.lookup(var)?, arg)
+    }
+
+    /// Applies a function value to a Rust value, converting the result back to Rust.  The cost
+    /// includes that of the application itself.
+    pub fn apply<A: ToValue, R: FromValue>(
+        &self,
+        func: &Arc<Value>,
+        arg: A,
+    ) -> Result<Res<R>, Error> {
+        let app =
+            semantics::with_exceptions(self.signatures.exceptions(), || match self.backend {
+                Backend::Env => semantics::measure_space(self.space, || {
+                    trace::with(self.tracer(), || Env::apply(func, arg.to_value()))
+                }),
+                Backend::Substitution { print_steps } => substitution::apply(
+                    func,
+                    arg.to_value(),
+                    print_steps.then_some(&mut print_step),
+                ),
+                // Applies compiled closures with the virtual machine.
+                Backend::Bytecode => Env::apply(func, arg.to_value()),
+            })?;
+        let result = R::from_value(&app.result).ok_or(Error::Conversion(app.result))?;
+
+        Ok(Res {
+            result,
+            work: app.work + 1,
+            span: app.span + 1,
+            extras: app.extras,
+        })
+    }
+
+    /// Evaluates a declaration and binds what it declares.  Returns the declared value, if any.
+    fn exec_decl(&mut self, decl: &Decl) -> Result<Res<Option<Arc<Value>>>, Err> {
+        match decl {
+            Decl::Val { var, expr } => {
+                let res = self.eval_expr(expr)?;
+                self.bind(var, res.result.clone());
+                Ok(Res {
+                    result: Some(res.result),
+                    work: res.work,
+                    span: res.span,
+                    extras: res.extras,
+                })
+            }
+            Decl::Fun {
+                name,
+                pattern,
+                expr,
+            } => {
+                self.check_raises(expr)?;
+                let lambda = SynValue::Lambda {
+                    name: Some(name.clone()),
+                    pattern: pattern.clone(),
+                    expr: expr.clone(),
+                    captures: None,
+                };
+                let func = match self.backend {
+                    // Resolves and compiles the function once, rather than at each application.
+                    Backend::Env if !self.debugged => self
+                        .env
+                        .eval_value(&resolve::resolve_value(&self.env, &lambda))?,
+                    Backend::Bytecode => {
+                        vm::eval_expr(&self.env, &Expr::Value(Box::new(lambda)))?.result
                     }
-                    _ => Diagnostic::new(Severity::Error, format!("Unknown parse error: {}", e)),
+                    Backend::Env | Backend::Substitution { .. } => Arc::new(Value::Lambda {
+                        name: Some(name.clone()),
+                        pattern: pattern.clone(),
+                        expr: expr.clone(),
+                        env: self.env.clone(),
+                    }),
                 };
-                emit(&mut writer.lock(), &codemap, &error).unwrap();
+                self.bind(name, func.clone());
+                Ok(Res {
+                    result: Some(func),
+                    work: 1,
+                    span: 1,
+                    extras: semantics::measure_space(self.space, || Space::cells(Allocs::CLOSURE))
+                        .into(),
+                })
+            }
+            Decl::Datatype { ctors, .. } => {
+                self.signatures.declare(ctors);
+                Ok(Res {
+                    result: None,
+                    work: 0,
+                    span: 0,
+                    extras: Extras::default(),
+                })
+            }
+            Decl::Exception { ctor, ty } => {
+                self.signatures.declare_exception(ctor, ty);
+                Ok(Res {
+                    result: None,
+                    work: 0,
+                    span: 0,
+                    extras: Extras::default(),
+                })
+            }
+        }
+    }
+
+    /// Evaluates a program, binding its declarations in the environment of subsequently executed
+    /// expressions.  Returns the result of its main expression, if any.
+    pub fn load(&mut self, input: &str) -> Result<Option<Res<Arc<Value>>>, Error> {
+        let program = self
+            .program_parser
+            .parse(tokens(input))
+            .map_err(|e| Error::Parse(e.map_token(|token| token.to_string())))?;
+
+        Ok(self.exec_program(&program)?)
+    }
+
+    /// Evaluates the declarations of a program, binding them, and then its main expression, if any.
+    fn exec_program(&mut self, program: &Program) -> Result<Option<Res<Arc<Value>>>, Err> {
+        for decl in program.decls.iter() {
+            self.exec_decl(decl)?;
+        }
+        match &program.expr {
+            Some(expr) => Ok(Some(self.eval_expr(expr)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Starts debugging a program, evaluating it with the environment-based evaluator.  Its
+    /// declarations are not bound in the environment of subsequently executed expressions.
+    pub fn debug(&self, input: &str) -> Result<Debugger, Error> {
+        let program = self
+            .program_parser
+            .parse(tokens(input))
+            .map_err(|e| Error::Parse(e.map_token(|token| token.to_string())))?;
+        let mut executor = Self {
+            env: self.env.clone(),
+            signatures: self.signatures.clone(),
+            debugged: true,
+            ..Self::new()
+        };
+        Ok(Debugger::new(
+            move || executor.exec_program(&program),
+            input,
+        ))
+    }
+
+    /// Compiles a program to a standalone Rust source file, which depends only on rayon.
+    pub fn emit_rust(&self, input: &str) -> Result<String, Error> {
+        let program = self
+            .program_parser
+            .parse(tokens(input))
+            .map_err(|e| Error::Parse(e.map_token(|token| token.to_string())))?;
+        Ok(transpile::emit_rust(&program)?)
+    }
+
+    /// Reports an error of executing `input` to stderr.
+    pub fn report_error(input: &str, error: Error) {
+        match error {
+            Error::Parse(e) => Self::report_parse_error(input, e),
+            Error::Runtime(e) => Self::report_runtime_error(input, e),
+            Error::Conversion(value) => eprintln!("Cannot convert {}", value),
+            Error::Compile(e) => eprintln!("Compile error: {}", e),
+        }
+    }
+
+    fn report_parse_error(input: &str, e: lalrpop_util::ParseError<usize, String, SyntaxError>) {
+        let file = SimpleFile::new("input", input);
+        let writer = StandardStream::stderr(ColorChoice::Auto);
+        let error = match e {
+            lalrpop_util::ParseError::User { error } => Diagnostic::error()
+                .with_message(error.to_string())
+                .with_labels(vec![Label::primary((), error.start..error.end)]),
+            lalrpop_util::ParseError::UnrecognizedToken {
+                token: (start, _, end),
+                ..
+            } => Diagnostic::error()
+                .with_message("Unrecognized token")
+                .with_labels(vec![Label::primary((), start..end)]),
+            lalrpop_util::ParseError::UnrecognizedEof { location, .. } => Diagnostic::error()
+                .with_message("Unexpected end of input")
+                .with_labels(vec![Label::primary((), location..location)]),
+            _ => Diagnostic::error().with_message(format!("Unknown parse error: {}", e)),
+        };
+        emit(&mut writer.lock(), &Config::default(), &file, &error).unwrap();
+    }
+
+    fn report_runtime_error(input: &str, e: Err) {
+        let (message, location, label) = match e {
+            Err::Raise {
+                value, location, ..
+            } => (
+                format!("Uncaught exception {}", value),
+                location,
+                "raised here".to_string(),
+            ),
+            Err::NotAnException { location } => (
+                "Not an exception".to_string(),
+                location,
+                "raises a value that is not a declared exception".to_string(),
+            ),
+            e => {
+                eprintln!("Runtime error: {:?}", e);
                 return;
             }
         };
 
-        let result = match self.env.eval_expr(&expr) {
-            Ok(result) => result,
+        let file = SimpleFile::new("input", input);
+        let writer = StandardStream::stderr(ColorChoice::Auto);
+        let error = Diagnostic::error()
+            .with_message(message)
+            .with_labels(vec![
+                Label::primary((), location.start..location.end).with_message(label)
+            ]);
+        emit(&mut writer.lock(), &Config::default(), &file, &error).unwrap();
+    }
+
+    /// Warns about the non-exhaustive case expressions in `expr`.
+    fn report_case_warnings(&self, input: &str, expr: &Expr) {
+        let file = SimpleFile::new("input", input);
+        let writer = StandardStream::stderr(ColorChoice::Auto);
+        expr.walk(&mut |expr| {
+            if let Expr::Case { arms, span, .. } = expr {
+                if let Some(missing) = self.signatures.missing(arms) {
+                    let warning = Diagnostic::warning()
+                        .with_message("Case is not exhaustive")
+                        .with_labels(vec![Label::primary((), span.start..span.end)
+                            .with_message(format!("e.g. `{}` is not matched", missing))]);
+                    emit(&mut writer.lock(), &Config::default(), &file, &warning).unwrap();
+                }
+            }
+        });
+    }
+
+    /// Prints the space of an evaluation, if measured, and its counters.
+    fn print_space_and_counters<T>(&self, res: &Res<T>) {
+        // Only the environment-based evaluator measures the space.
+        if self.backend == Backend::Env && self.space {
+            println!(
+                "Space: {} (sequential), {} (parallel)\nAllocations: {}",
+                res.extras.space().peak_seq(),
+                res.extras.space().peak_par(),
+                res.extras.space().allocs()
+            );
+        }
+        for (name, count) in res.extras.counters().iter() {
+            println!(
+                "Counter {}: {} (work), {} (span)",
+                name, count.work, count.span
+            );
+        }
+    }
+
+    /// Executes a program, printing the result and cost of each declaration and of the main
+    /// expression.
+    pub fn exec(&mut self, input: &str) {
+        let program = match self.program_parser.parse(tokens(input)) {
+            Ok(program) => program,
             Err(e) => {
-                eprintln!("Runtime error: {:?}", e);
+                Self::report_parse_error(input, e.map_token(|token| token.to_string()));
                 return;
             }
         };
 
-        println!("Result: {:?}\nWork: {}\nSpan: {}", result.result, result.work, result.span);
+        for decl in program.decls.iter() {
+            match decl {
+                Decl::Val { expr, .. } => self.report_case_warnings(input, expr),
+                Decl::Fun { expr, .. } => self.report_case_warnings(input, expr),
+                Decl::Datatype { .. } | Decl::Exception { .. } => {}
+            }
+            let res = match self.exec_decl(decl) {
+                Ok(res) => res,
+                Err(e) => {
+                    Self::report_runtime_error(input, e);
+                    return;
+                }
+            };
+
+            match decl {
+                Decl::Val { var, .. } | Decl::Fun { name: var, .. } => {
+                    println!(
+                        "val {} = {} (work: {}, span: {})",
+                        var,
+                        res.result.as_ref().unwrap(),
+                        res.work,
+                        res.span
+                    );
+                    self.print_space_and_counters(&res);
+                }
+                Decl::Datatype {
+                    name,
+                    params,
+                    ctors,
+                } => {
+                    print!("datatype ");
+                    for param in params {
+                        print!("{} ", param);
+                    }
+                    print!("{} =", name);
+                    for (i, (ctor, ty)) in ctors.iter().enumerate() {
+                        print!("{} {}", if i == 0 { "" } else { " |" }, ctor);
+                        if let Some(ty) = ty {
+                            print!(" of {}", ty);
+                        }
+                    }
+                    println!();
+                }
+                Decl::Exception { ctor, ty } => match ty {
+                    Some(ty) => println!("exception {} of {}", ctor, ty),
+                    None => println!("exception {}", ctor),
+                },
+            }
+        }
+
+        if let Some(expr) = program.expr {
+            self.report_case_warnings(input, &expr);
+            let record = self.work_stealing.is_some();
+            match schedule::record(record, || self.eval_expr(&expr)) {
+                Ok(result) => {
+                    println!(
+                        "Result: {}\nWork: {}\nSpan: {}",
+                        result.result, result.work, result.span
+                    );
+                    self.print_space_and_counters(&result);
+                    // Only the environment-based evaluator records the DAG.
+                    if let (Some(options), Backend::Env) = (self.work_stealing, self.backend) {
+                        let averages = schedule::work_stealing(result.extras.dag(), options);
+                        println!(
+                            "Work stealing on {} workers, averaged over {} seeds: time {:.1}, \
+                             steals {:.1}, idle {:.1} (W/P + S = {:.1})",
+                            options.workers,
+                            options.seeds,
+                            averages.time,
+                            averages.steals,
+                            averages.idle,
+                            result.work as f64 / options.workers as f64 + result.span as f64
+                        );
+                    }
+                }
+                Err(e) => Self::report_runtime_error(input, e),
+            }
+        }
     }
 }
@@ -0,0 +1,271 @@
+//! Exhaustiveness checking of case expressions.
+//!
+//! The check computes the usefulness of a wildcard against the patterns of the case's arms, as in
+//! Maranget, "Warnings for pattern matching" (JFP 2007).  A constructor's siblings are known only
+//! if it is declared in a datatype; otherwise the arms are considered to match only the
+//! constructors they mention.  Guarded arms may fail to match even if their pattern does, so they
+//! are conservatively ignored.
+//!
+//! The declared exceptions are kept alongside, so that raising a value that is evidently not one
+//! of them is rejected before the evaluation, and any other at the raise.  Exceptions are open-ended, so they are never
+//! siblings of one another.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::syntax::{Arm, Ctor, Expr, Pattern, Type, Value};
+
+/// Maps each declared exception to the type of its argument, if it takes one.
+pub type Exceptions = HashMap<Ctor, Option<Type>>;
+
+/// The constructors of the declared datatypes and exceptions.
+#[derive(Debug, Default, Clone)]
+pub struct Signatures {
+    /// Maps each constructor to all constructors of its datatype, with whether they take an
+    /// argument.
+    ctors: HashMap<Ctor, Arc<Vec<(Ctor, bool)>>>,
+    exceptions: Arc<Exceptions>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Head {
+    Tuple(usize),
+    Boolean(bool),
+    Integer(i64),
+    String(String),
+    Char(char),
+    Ctor(Ctor),
+}
+
+impl Head {
+    fn of(pattern: &Pattern) -> Option<Head> {
+        match pattern {
+            Pattern::Tuple(patterns) => Some(Head::Tuple(patterns.len())),
+            Pattern::Boolean(b) => Some(Head::Boolean(*b)),
+            Pattern::Integer(i) => Some(Head::Integer(*i)),
+            Pattern::String(s) => Some(Head::String(s.clone())),
+            Pattern::Char(c) => Some(Head::Char(*c)),
+            Pattern::Ctor { ctor, .. } => Some(Head::Ctor(ctor.clone())),
+            _ => None,
+        }
+    }
+
+    /// Builds a pattern with this head and the given arguments.
+    fn build(&self, mut args: Vec<Pattern>) -> Pattern {
+        match self {
+            Head::Tuple(_) => Pattern::Tuple(args),
+            Head::Boolean(b) => Pattern::Boolean(*b),
+            Head::Integer(i) => Pattern::Integer(*i),
+            Head::String(s) => Pattern::String(s.clone()),
+            Head::Char(c) => Pattern::Char(*c),
+            Head::Ctor(ctor) => Pattern::Ctor {
+                ctor: ctor.clone(),
+                inner: args.pop().map(Box::new),
+            },
+        }
+    }
+}
+
+type Row = Vec<Pattern>;
+
+impl Signatures {
+    /// Declares the constructors of a datatype.
+    pub fn declare(&mut self, ctors: &[(Ctor, Option<Type>)]) {
+        let signature = Arc::new(
+            ctors
+                .iter()
+                .map(|(ctor, ty)| (ctor.clone(), ty.is_some()))
+                .collect::<Vec<_>>(),
+        );
+        for (ctor, _) in ctors {
+            self.ctors.insert(ctor.clone(), signature.clone());
+        }
+    }
+
+    /// Declares an exception.
+    pub fn declare_exception(&mut self, ctor: &Ctor, ty: &Option<Type>) {
+        Arc::make_mut(&mut self.exceptions).insert(ctor.clone(), ty.clone());
+    }
+
+    /// Returns the declared exceptions.
+    pub fn exceptions(&self) -> &Arc<Exceptions> {
+        &self.exceptions
+    }
+
+    /// Returns whether the operand of a raise evidently evaluates to something other than a
+    /// declared exception, i.e. a literal that is not one, or a constructor applied to an argument
+    /// that it does not take.  Other operands, e.g. variables, are taken on trust.
+    pub fn not_exception(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Value(value) => match &**value {
+                Value::Ctor { ctor, inner } => match (self.exceptions.get(ctor), inner) {
+                    (Some(None), None) => false,
+                    (Some(Some(ty)), Some(inner)) => !fits(inner, ty),
+                    _ => true,
+                },
+                _ => true,
+            },
+            Expr::App { lhs, .. } => match &**lhs {
+                Expr::Value(value) => match &**value {
+                    Value::Ctor { ctor, inner: None } => {
+                        !matches!(self.exceptions.get(ctor), Some(Some(_)))
+                    }
+                    _ => false,
+                },
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Returns a pattern matching values that no unguarded arm matches, if there are any.
+    pub fn missing(&self, arms: &[Arm]) -> Option<Pattern> {
+        let rows = arms
+            .iter()
+            .filter(|arm| arm.guard.is_none())
+            .map(|arm| vec![(*arm.pattern).clone()])
+            .collect();
+        self.missing_rows(rows, 1)
+            .map(|mut witness| witness.remove(0))
+    }
+
+    /// Returns `n` patterns that together match values that no row matches, if there are any.
+    fn missing_rows(&self, rows: Vec<Row>, n: usize) -> Option<Row> {
+        if n == 0 {
+            return if rows.is_empty() { Some(vec![]) } else { None };
+        }
+
+        let rows = normalize(rows);
+        let mut heads = Vec::<Head>::new();
+        for row in rows.iter() {
+            if let Some(head) = Head::of(&row[0]) {
+                if !heads.contains(&head) {
+                    heads.push(head);
+                }
+            }
+        }
+
+        let signature = match heads.first() {
+            Some(Head::Tuple(len)) => Some(vec![(Head::Tuple(*len), *len)]),
+            Some(Head::Boolean(_)) => {
+                Some(vec![(Head::Boolean(false), 0), (Head::Boolean(true), 0)])
+            }
+            Some(Head::Ctor(ctor)) => self.ctors.get(ctor).map(|signature| {
+                signature
+                    .iter()
+                    .map(|(ctor, arg)| (Head::Ctor(ctor.clone()), *arg as usize))
+                    .collect()
+            }),
+            Some(Head::Integer(_)) | Some(Head::String(_)) | Some(Head::Char(_)) | None => None,
+        };
+
+        if let Some(signature) = &signature {
+            if signature.iter().all(|(head, _)| heads.contains(head)) {
+                for (head, arity) in signature {
+                    let rows = specialize(&rows, head, *arity);
+                    if let Some(mut witness) = self.missing_rows(rows, n - 1 + arity) {
+                        let args = witness.drain(..arity).collect();
+                        witness.insert(0, head.build(args));
+                        return Some(witness);
+                    }
+                }
+                return None;
+            }
+        }
+
+        let rows = rows
+            .into_iter()
+            .filter(|row| matches!(row[0], Pattern::Wildcard))
+            .map(|row| row[1..].to_vec())
+            .collect();
+        let mut witness = self.missing_rows(rows, n - 1)?;
+        let head = signature
+            .and_then(|signature| {
+                signature
+                    .into_iter()
+                    .find(|(head, _)| !heads.contains(head))
+                    .map(|(head, arity)| head.build(vec![Pattern::Wildcard; arity]))
+            })
+            .unwrap_or(Pattern::Wildcard);
+        witness.insert(0, head);
+        Some(witness)
+    }
+}
+
+/// Returns whether a literal may be of the type.  Only the builtin scalar types and tuples are
+/// checked.
+fn fits(value: &Value, ty: &Type) -> bool {
+    match (value, ty) {
+        (_, Type::Tuple(tys)) => match value {
+            Value::Tuple(values) if values.len() == tys.len() => {
+                values.iter().zip(tys).all(|(value, ty)| fits(value, ty))
+            }
+            _ => false,
+        },
+        (_, Type::Con { name, args }) if args.is_empty() => match name.as_str() {
+            "int" => matches!(value, Value::Integer(_)),
+            "float" => matches!(value, Value::Float(_)),
+            "bool" => matches!(value, Value::Boolean(_)),
+            "string" => matches!(value, Value::String(_)),
+            "char" => matches!(value, Value::Char(_)),
+            "unit" => matches!(value, Value::Tuple(values) if values.is_empty()),
+            _ => true,
+        },
+        _ => true,
+    }
+}
+
+/// Rewrites the first column of the rows so that it contains only wildcards and patterns with a
+/// head.
+fn normalize(rows: Vec<Row>) -> Vec<Row> {
+    let mut result = vec![];
+    let mut stack = rows;
+    stack.reverse();
+    while let Some(mut row) = stack.pop() {
+        match row[0].clone() {
+            Pattern::Var(_) => {
+                row[0] = Pattern::Wildcard;
+                result.push(row);
+            }
+            Pattern::As { inner, .. } => {
+                row[0] = *inner;
+                stack.push(row);
+            }
+            Pattern::Or { lhs, rhs } => {
+                let mut lhs_row = row.clone();
+                lhs_row[0] = *lhs;
+                row[0] = *rhs;
+                stack.push(row);
+                stack.push(lhs_row);
+            }
+            _ => result.push(row),
+        }
+    }
+    result
+}
+
+/// Keeps the rows whose first pattern may match a value with the given head, replacing the
+/// pattern with those for the value's `arity` arguments.
+fn specialize(rows: &[Row], head: &Head, arity: usize) -> Vec<Row> {
+    rows.iter()
+        .filter_map(|row| {
+            let mut args = match (&row[0], head) {
+                (Pattern::Wildcard, _) => vec![Pattern::Wildcard; arity],
+                (Pattern::Tuple(patterns), Head::Tuple(len)) if patterns.len() == *len => {
+                    patterns.clone()
+                }
+                (Pattern::Ctor { ctor, inner }, Head::Ctor(head)) if ctor == head => match inner {
+                    Some(inner) if arity == 1 => vec![(**inner).clone()],
+                    _ => vec![Pattern::Wildcard; arity],
+                },
+                (Pattern::Boolean(b), Head::Boolean(head)) if b == head => vec![],
+                (Pattern::Integer(i), Head::Integer(head)) if i == head => vec![],
+                (Pattern::String(s), Head::String(head)) if s == head => vec![],
+                (Pattern::Char(c), Head::Char(head)) if c == head => vec![],
+                _ => return None,
+            };
+            args.extend_from_slice(&row[1..]);
+            Some(args)
+        })
+        .collect()
+}
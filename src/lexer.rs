@@ -0,0 +1,538 @@
+//! The lexer.
+//!
+//! Comments are either line comments starting with `//` or block comments `(* ... *)`, which may
+//! be nested.  Note that `(*` always starts a comment, as in SML, so the lifted multiplication is
+//! written `( * x)`: programs written for the previous lexer as `((* 3) 4)` must be changed to
+//! `(( * 3) 4)`.
+//!
+//! Identifiers consist of alphabetic and numeric characters and underscores, and may end with
+//! some number of primes.  Those starting with a lowercase letter or an underscore are variables,
+//! those starting with an uppercase letter are constructors, and those starting with a prime are
+//! type variables.  A lone underscore is the wildcard, and `#` followed by digits is a projection.
+//!
+//! Integer literals may be preceded by a sign, and are float literals if they have a fractional
+//! part or an exponent, as in `-1.5e3`.
+//!
+//! String literals are enclosed in double quotes, and character literals are written `#"c"` as in
+//! SML.  Both may contain the escapes `\n`, `\t`, `\r`, `\0`, `\\`, `\"` and `\'`.
+
+use std::fmt;
+
+/// Tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tok<'input> {
+    Var(&'input str),
+    Ctor(&'input str),
+    TyVar(&'input str),
+    Int(i64),
+    Float(f64),
+    Proj(usize),
+    /// A string literal, with its escapes not yet replaced.
+    Str(&'input str),
+    Char(char),
+    Underscore,
+
+    AndAlso,
+    As,
+    Assert,
+    Case,
+    Datatype,
+    Div,
+    Else,
+    Exception,
+    False,
+    Fun,
+    Handle,
+    If,
+    In,
+    Let,
+    Mod,
+    Of,
+    OrElse,
+    Par,
+    Quot,
+    Raise,
+    Rem,
+    Tick,
+    True,
+    Val,
+    WithCost,
+
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    Comma,
+    Semi,
+    Arrow,
+    DArrow,
+    Eq,
+    Bar,
+    BarBar,
+    Amp,
+    Caret,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    EqEq,
+    Lt,
+    Le,
+    NotEq,
+    Gt,
+    Ge,
+    Bang,
+    Tilde,
+}
+
+impl fmt::Display for Tok<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Tok::Var(s) | Tok::Ctor(s) | Tok::TyVar(s) => s,
+            Tok::Int(i) => return write!(f, "{}", i),
+            Tok::Float(x) => return write!(f, "{:?}", x),
+            Tok::Proj(i) => return write!(f, "#{}", i),
+            Tok::Str(s) => return write!(f, "\"{}\"", s),
+            Tok::Char(c) => return write!(f, "#\"{}\"", c.escape_default()),
+            Tok::Underscore => "_",
+
+            Tok::AndAlso => "andalso",
+            Tok::As => "as",
+            Tok::Assert => "assert",
+            Tok::Case => "case",
+            Tok::Datatype => "datatype",
+            Tok::Div => "div",
+            Tok::Else => "else",
+            Tok::Exception => "exception",
+            Tok::False => "false",
+            Tok::Fun => "fun",
+            Tok::Handle => "handle",
+            Tok::If => "if",
+            Tok::In => "in",
+            Tok::Let => "let",
+            Tok::Mod => "mod",
+            Tok::Of => "of",
+            Tok::OrElse => "orelse",
+            Tok::Par => "par",
+            Tok::Quot => "quot",
+            Tok::Raise => "raise",
+            Tok::Rem => "rem",
+            Tok::Tick => "tick",
+            Tok::True => "true",
+            Tok::Val => "val",
+            Tok::WithCost => "withcost",
+
+            Tok::LParen => "(",
+            Tok::RParen => ")",
+            Tok::LBrace => "{",
+            Tok::RBrace => "}",
+            Tok::Comma => ",",
+            Tok::Semi => ";",
+            Tok::Arrow => "->",
+            Tok::DArrow => "=>",
+            Tok::Eq => "=",
+            Tok::Bar => "|",
+            Tok::BarBar => "||",
+            Tok::Amp => "&",
+            Tok::Caret => "^",
+            Tok::Plus => "+",
+            Tok::Minus => "-",
+            Tok::Star => "*",
+            Tok::Slash => "/",
+            Tok::EqEq => "==",
+            Tok::Lt => "<",
+            Tok::Le => "<=",
+            Tok::NotEq => "<>",
+            Tok::Gt => ">",
+            Tok::Ge => ">=",
+            Tok::Bang => "!",
+            Tok::Tilde => "~",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Kinds of lexical errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexicalErrorKind {
+    /// A character that does not start any token.
+    UnexpectedChar(char),
+    /// A block comment that is not closed.
+    UnterminatedComment,
+    /// An integer literal that does not fit in 64 bits.
+    IntegerOverflow,
+    /// A string or character literal that is not closed.
+    UnterminatedString,
+    /// An unknown escape sequence in a string or character literal.
+    InvalidEscape(char),
+    /// A character literal that does not consist of exactly one character.
+    InvalidCharLiteral,
+}
+
+/// A lexical error, spanning the bytes `start..end` of the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexicalError {
+    /// The kind of the error.
+    pub kind: LexicalErrorKind,
+    /// The start of the span.
+    pub start: usize,
+    /// The end of the span.
+    pub end: usize,
+}
+
+impl fmt::Display for LexicalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            LexicalErrorKind::UnexpectedChar(c) => write!(f, "Unexpected character {:?}", c),
+            LexicalErrorKind::UnterminatedComment => write!(
+                f,
+                "Unterminated comment; note that `(*` always starts a comment, so the lifted \
+                 multiplication is written `( * x)`"
+            ),
+            LexicalErrorKind::IntegerOverflow => write!(f, "Integer literal out of range"),
+            LexicalErrorKind::UnterminatedString => write!(f, "Unterminated string literal"),
+            LexicalErrorKind::InvalidEscape(c) => write!(f, "Unknown escape sequence \\{}", c),
+            LexicalErrorKind::InvalidCharLiteral => {
+                write!(f, "Character literal must contain exactly one character")
+            }
+        }
+    }
+}
+
+/// Returns the character denoted by the escape sequence `\c`, if any.
+fn escape(c: char) -> Option<char> {
+    match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        '0' => Some('\0'),
+        '\\' | '"' | '\'' => Some(c),
+        _ => None,
+    }
+}
+
+/// Replaces the escape sequences in the contents of a string literal accepted by the lexer.
+pub fn unescape(raw: &str) -> String {
+    let mut result = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            result.push(chars.next().and_then(escape).unwrap());
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+pub type Spanned<'input> = Result<(usize, Tok<'input>, usize), LexicalError>;
+
+#[derive(Debug)]
+pub struct Lexer<'input> {
+    input: &'input str,
+    pos: usize,
+}
+
+impl<'input> Lexer<'input> {
+    pub fn new(input: &'input str) -> Self {
+        Self { input, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.pos).cloned()
+    }
+
+    fn peek2(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.pos + 1).cloned()
+    }
+
+    fn is_ident(c: u8) -> bool {
+        c.is_ascii_alphanumeric() || c == b'_'
+    }
+
+    /// Skips whitespace and comments.
+    fn skip_trivia(&mut self) -> Result<(), LexicalError> {
+        loop {
+            match (self.peek(), self.peek2()) {
+                (Some(c), _) if c.is_ascii_whitespace() => self.pos += 1,
+                (Some(b'/'), Some(b'/')) => {
+                    while !matches!(self.peek(), None | Some(b'\n')) {
+                        self.pos += 1;
+                    }
+                }
+                (Some(b'('), Some(b'*')) => {
+                    let start = self.pos;
+                    let mut depth = 0;
+                    loop {
+                        match (self.peek(), self.peek2()) {
+                            (Some(b'('), Some(b'*')) => {
+                                depth += 1;
+                                self.pos += 2;
+                            }
+                            (Some(b'*'), Some(b')')) => {
+                                depth -= 1;
+                                self.pos += 2;
+                                if depth == 0 {
+                                    break;
+                                }
+                            }
+                            (Some(_), _) => self.pos += 1,
+                            (None, _) => {
+                                return Err(LexicalError {
+                                    kind: LexicalErrorKind::UnterminatedComment,
+                                    start,
+                                    end: start + 2,
+                                })
+                            }
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Lexes an identifier, including its trailing primes.
+    fn ident(&mut self) -> &'input str {
+        let start = self.pos;
+        while self.peek().is_some_and(Self::is_ident) {
+            self.pos += 1;
+        }
+        while self.peek() == Some(b'\'') {
+            self.pos += 1;
+        }
+        &self.input[start..self.pos]
+    }
+
+    fn digits(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+    }
+
+    /// Lexes an integer literal, or a float literal if it has a fractional part or an exponent.
+    fn number(&mut self, start: usize) -> Result<Tok<'input>, LexicalError> {
+        self.digits();
+
+        let mut float = false;
+        if self.peek() == Some(b'.') && self.peek2().is_some_and(|c| c.is_ascii_digit()) {
+            float = true;
+            self.pos += 1;
+            self.digits();
+        }
+        if matches!(self.peek(), Some(b'e') | Some(b'E')) {
+            let bytes = self.input.as_bytes();
+            let sign = matches!(bytes.get(self.pos + 1), Some(b'-') | Some(b'+')) as usize;
+            if bytes
+                .get(self.pos + 1 + sign)
+                .is_some_and(|c| c.is_ascii_digit())
+            {
+                float = true;
+                self.pos += 1 + sign;
+                self.digits();
+            }
+        }
+        if float {
+            // Every such literal parses, possibly to an infinity.
+            return Ok(Tok::Float(self.input[start..self.pos].parse().unwrap()));
+        }
+
+        self.input[start..self.pos]
+            .parse()
+            .map(Tok::Int)
+            .map_err(|_| LexicalError {
+                kind: LexicalErrorKind::IntegerOverflow,
+                start,
+                end: self.pos,
+            })
+    }
+
+    fn proj(&mut self, start: usize) -> Result<Tok<'input>, LexicalError> {
+        self.pos += 1;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.input[start + 1..self.pos]
+            .parse()
+            .map(Tok::Proj)
+            .map_err(|_| LexicalError {
+                kind: LexicalErrorKind::IntegerOverflow,
+                start,
+                end: self.pos,
+            })
+    }
+
+    /// Lexes a string literal whose opening quote is at the current position, and returns its
+    /// contents.
+    /// An unterminated literal is reported as such even if it contains an unknown escape, e.g. a
+    /// backslash before the end of the line.
+    fn string(&mut self) -> Result<&'input str, LexicalError> {
+        let start = self.pos;
+        let mut invalid = None;
+        self.pos += 1;
+        loop {
+            match self.peek() {
+                Some(b'"') => break,
+                Some(b'\\') => match self.input[self.pos + 1..].chars().next() {
+                    Some(c) => {
+                        if escape(c).is_none() && invalid.is_none() {
+                            invalid = Some(LexicalError {
+                                kind: LexicalErrorKind::InvalidEscape(c),
+                                start: self.pos,
+                                end: self.pos + 1 + c.len_utf8(),
+                            });
+                        }
+                        self.pos += 1 + c.len_utf8();
+                    }
+                    None => self.pos += 1,
+                },
+                Some(_) => self.pos += 1,
+                None => break,
+            }
+        }
+        if self.peek() != Some(b'"') {
+            return Err(LexicalError {
+                kind: LexicalErrorKind::UnterminatedString,
+                start,
+                end: start + 1,
+            });
+        }
+        if let Some(invalid) = invalid {
+            return Err(invalid);
+        }
+        self.pos += 1;
+        Ok(&self.input[start + 1..self.pos - 1])
+    }
+
+    fn char(&mut self, start: usize) -> Result<Tok<'input>, LexicalError> {
+        self.pos += 1;
+        let contents = unescape(self.string()?);
+        let mut chars = contents.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(Tok::Char(c)),
+            _ => Err(LexicalError {
+                kind: LexicalErrorKind::InvalidCharLiteral,
+                start,
+                end: self.pos,
+            }),
+        }
+    }
+
+    fn token(&mut self, start: usize) -> Result<Tok<'input>, LexicalError> {
+        let c = self.peek().unwrap();
+
+        if c.is_ascii_digit() {
+            return self.number(start);
+        }
+        if (c == b'-' || c == b'+') && self.peek2().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+            return self.number(start);
+        }
+        if c == b'#' && self.peek2().is_some_and(|c| c.is_ascii_digit()) {
+            return self.proj(start);
+        }
+        if c == b'#' && self.peek2() == Some(b'"') {
+            return self.char(start);
+        }
+        if c == b'"' {
+            return self.string().map(Tok::Str);
+        }
+        if c.is_ascii_alphabetic() || c == b'_' {
+            let ident = self.ident();
+            return Ok(match ident {
+                "_" => Tok::Underscore,
+                "andalso" => Tok::AndAlso,
+                "as" => Tok::As,
+                "assert" => Tok::Assert,
+                "case" => Tok::Case,
+                "datatype" => Tok::Datatype,
+                "div" => Tok::Div,
+                "else" => Tok::Else,
+                "exception" => Tok::Exception,
+                "false" => Tok::False,
+                "fun" => Tok::Fun,
+                "handle" => Tok::Handle,
+                "if" => Tok::If,
+                "in" => Tok::In,
+                "let" => Tok::Let,
+                "mod" => Tok::Mod,
+                "of" => Tok::Of,
+                "orelse" => Tok::OrElse,
+                "par" => Tok::Par,
+                "quot" => Tok::Quot,
+                "raise" => Tok::Raise,
+                "rem" => Tok::Rem,
+                "tick" => Tok::Tick,
+                "true" => Tok::True,
+                "val" => Tok::Val,
+                "withcost" => Tok::WithCost,
+                _ if c.is_ascii_uppercase() => Tok::Ctor(ident),
+                _ => Tok::Var(ident),
+            });
+        }
+        if c == b'\'' && self.peek2().is_some_and(|c| c.is_ascii_alphabetic()) {
+            self.pos += 1;
+            self.ident();
+            return Ok(Tok::TyVar(&self.input[start..self.pos]));
+        }
+
+        let (tok, len) = match (c, self.peek2()) {
+            (b'-', Some(b'>')) => (Tok::Arrow, 2),
+            (b'=', Some(b'>')) => (Tok::DArrow, 2),
+            (b'=', Some(b'=')) => (Tok::EqEq, 2),
+            (b'|', Some(b'|')) => (Tok::BarBar, 2),
+            (b'<', Some(b'=')) => (Tok::Le, 2),
+            (b'<', Some(b'>')) => (Tok::NotEq, 2),
+            (b'>', Some(b'=')) => (Tok::Ge, 2),
+            (b'(', _) => (Tok::LParen, 1),
+            (b')', _) => (Tok::RParen, 1),
+            (b'{', _) => (Tok::LBrace, 1),
+            (b'}', _) => (Tok::RBrace, 1),
+            (b',', _) => (Tok::Comma, 1),
+            (b';', _) => (Tok::Semi, 1),
+            (b'=', _) => (Tok::Eq, 1),
+            (b'|', _) => (Tok::Bar, 1),
+            (b'&', _) => (Tok::Amp, 1),
+            (b'^', _) => (Tok::Caret, 1),
+            (b'+', _) => (Tok::Plus, 1),
+            (b'-', _) => (Tok::Minus, 1),
+            (b'*', _) => (Tok::Star, 1),
+            (b'/', _) => (Tok::Slash, 1),
+            (b'<', _) => (Tok::Lt, 1),
+            (b'>', _) => (Tok::Gt, 1),
+            (b'!', _) => (Tok::Bang, 1),
+            (b'~', _) => (Tok::Tilde, 1),
+            _ => {
+                let c = self.input[start..].chars().next().unwrap();
+                return Err(LexicalError {
+                    kind: LexicalErrorKind::UnexpectedChar(c),
+                    start,
+                    end: start + c.len_utf8(),
+                });
+            }
+        };
+        self.pos += len;
+        Ok(tok)
+    }
+}
+
+impl<'input> Iterator for Lexer<'input> {
+    type Item = Spanned<'input>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.skip_trivia() {
+            self.pos = self.input.len();
+            return Some(Err(e));
+        }
+        self.peek()?;
+
+        let start = self.pos;
+        match self.token(start) {
+            Ok(tok) => Some(Ok((start, tok, self.pos))),
+            Err(e) => {
+                self.pos = e.end;
+                Some(Err(e))
+            }
+        }
+    }
+}
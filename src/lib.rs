@@ -9,11 +9,35 @@
 extern crate rayon;
 #[macro_use]
 extern crate lalrpop_util;
+extern crate sparc_derive;
 
 mod arc_list;
+mod builtins;
+mod bytecode;
+mod convert;
+mod debugger;
 mod executor;
+mod exhaustive;
+mod lexer;
+mod native;
 mod parser;
+mod resolve;
+mod schedule;
 mod semantics;
+mod substitution;
 mod syntax;
+mod trace;
+mod transpile;
+mod vm;
 
-pub use executor::Executor;
+pub use convert::{FromValue, ToValue};
+pub use debugger::{Breakpoint, Call, Debugger, Stop};
+pub use executor::{Backend, Error, Executor};
+pub use lexer::{LexicalError, LexicalErrorKind};
+pub use native::NativeFn;
+pub use parser::{SyntaxError, SyntaxErrorKind};
+pub use schedule::{Dag, WorkStealingOptions};
+pub use semantics::{Allocs, Count, Counters, Err, Extras, Res, Space, Value};
+pub use sparc_derive::{FromValue, ToValue};
+pub use trace::TraceOptions;
+pub use transpile::CompileError;
@@ -0,0 +1,35 @@
+//! Host functions callable from SPARC.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::semantics::{Err, Value};
+
+/// A function implemented in Rust that can be bound in the initial environment of an
+/// [`Executor`](crate::Executor).
+///
+/// A native function of arity `n` is curried: it is applied to its arguments one at a time, and
+/// only when the `n`-th argument is supplied are [`cost`](NativeFn::cost) and
+/// [`call`](NativeFn::call) invoked with all of them.
+pub trait NativeFn: Send + Sync {
+    /// The name the function is bound to.
+    fn name(&self) -> &str;
+
+    /// The number of arguments the function takes.  Must be positive.
+    fn arity(&self) -> usize;
+
+    /// The work and span charged for applying the function to `args`, on top of the cost of the
+    /// application itself.
+    fn cost(&self, _args: &[Arc<Value>]) -> (u64, u64) {
+        (1, 1)
+    }
+
+    /// Applies the function to `args`, whose length is always `self.arity()`.
+    fn call(&self, args: &[Arc<Value>]) -> Result<Arc<Value>, Err>;
+}
+
+impl fmt::Debug for dyn NativeFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native {}/{}>", self.name(), self.arity())
+    }
+}
@@ -1,8 +1,170 @@
-// TODO: "In SPARC, variables, type constructors, and data constructors are given a name, or an
-// identifier. An identifer consist of only alphabetic and numeric characters (a-z, A-Z, 0-9), the
-// underscore character (“ ”), and optionally end with some number of “primes”. Example identifiers
-// include, x′, x1, xl, myV ar, myT ype, myData, and my data."
+use std::fmt;
 
-lalrpop_mod!(parser_inner); // synthesized by LALRPOP
+use crate::lexer::{Lexer, LexicalError, LexicalErrorKind, Tok};
+use crate::syntax::{Bind, CostMeasure, Expr, Pattern, PatternError, Var};
 
-pub use parser_inner::ExprParser;
+lalrpop_mod!(
+    #[allow(clippy::all)]
+    parser_inner
+); // synthesized by LALRPOP
+
+pub use parser_inner::{ExprParser, ProgramParser};
+
+/// Kinds of syntax errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyntaxErrorKind {
+    /// A lexical error.
+    Lexical(LexicalErrorKind),
+    /// An ill-formed pattern.
+    Pattern(PatternError),
+    /// A binding of a parallel let refers to a variable bound in the same group.
+    ParDependency(Var),
+    /// A cost contract bounds something other than work or span.
+    UnknownCostMeasure(String),
+    /// A cost contract bounds the same measure more than once.
+    DuplicateCostBound(CostMeasure),
+}
+
+/// A syntax error other than an unexpected token, spanning the bytes `start..end` of the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    /// The kind of the error.
+    pub kind: SyntaxErrorKind,
+    /// The start of the span.
+    pub start: usize,
+    /// The end of the span.
+    pub end: usize,
+}
+
+impl From<LexicalError> for SyntaxError {
+    fn from(e: LexicalError) -> Self {
+        Self {
+            kind: SyntaxErrorKind::Lexical(e.kind),
+            start: e.start,
+            end: e.end,
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            SyntaxErrorKind::Lexical(kind) => LexicalError {
+                kind: kind.clone(),
+                start: self.start,
+                end: self.end,
+            }
+            .fmt(f),
+            SyntaxErrorKind::Pattern(PatternError::NonLinear(var)) => {
+                write!(f, "Variable {} is bound more than once", var)
+            }
+            SyntaxErrorKind::Pattern(PatternError::OrMismatch) => {
+                write!(f, "Alternatives of an or-pattern bind different variables")
+            }
+            SyntaxErrorKind::ParDependency(var) => write!(
+                f,
+                "Variable {} is bound in the same parallel let as its use",
+                var
+            ),
+            SyntaxErrorKind::UnknownCostMeasure(measure) => {
+                write!(f, "Unknown cost measure {}, expected work or span", measure)
+            }
+            SyntaxErrorKind::DuplicateCostBound(measure) => {
+                write!(f, "The {} is bounded more than once", measure)
+            }
+        }
+    }
+}
+
+/// Tokenizes the input for the parsers.
+pub fn tokens(
+    input: &str,
+) -> impl Iterator<Item = Result<(usize, Tok<'_>, usize), SyntaxError>> + '_ {
+    Lexer::new(input).map(|tok| tok.map_err(SyntaxError::from))
+}
+
+fn error<T>(
+    kind: SyntaxErrorKind,
+    start: usize,
+    end: usize,
+) -> lalrpop_util::ParseError<usize, T, SyntaxError> {
+    lalrpop_util::ParseError::User {
+        error: SyntaxError { kind, start, end },
+    }
+}
+
+/// Checks a pattern, spanning `start..end`, that binds variables.
+fn check_pattern<T>(
+    pattern: Pattern,
+    start: usize,
+    end: usize,
+) -> Result<Pattern, lalrpop_util::ParseError<usize, T, SyntaxError>> {
+    match pattern.binders() {
+        Ok(_) => Ok(pattern),
+        Err(e) => Err(error(SyntaxErrorKind::Pattern(e), start, end)),
+    }
+}
+
+/// Checks the bindings of a parallel let, each spanning `start..end`: they may neither bind the
+/// same variable nor refer to each other.
+fn check_par_binds<T>(
+    binds: Vec<(usize, Bind, usize)>,
+) -> Result<Vec<Bind>, lalrpop_util::ParseError<usize, T, SyntaxError>> {
+    let mut vars = Vec::<(Var, usize)>::new();
+    for (i, (start, bind, end)) in binds.iter().enumerate() {
+        let binders = bind
+            .pattern
+            .binders()
+            .map_err(|e| error(SyntaxErrorKind::Pattern(e), *start, *end))?;
+        for var in binders {
+            if vars.iter().any(|(v, _)| *v == var) {
+                let kind = SyntaxErrorKind::Pattern(PatternError::NonLinear(var));
+                return Err(error(kind, *start, *end));
+            }
+            vars.push((var, i));
+        }
+    }
+
+    for (i, (start, bind, end)) in binds.iter().enumerate() {
+        let free_vars = bind.expr.free_vars();
+        for (var, j) in vars.iter() {
+            if i != *j && free_vars.contains(var) {
+                let kind = SyntaxErrorKind::ParDependency(var.clone());
+                return Err(error(kind, *start, *end));
+            }
+        }
+    }
+
+    Ok(binds.into_iter().map(|(_, bind, _)| bind).collect())
+}
+
+/// Returns the cost measure named `name`, spanning `start..end`.
+fn cost_measure<T>(
+    name: Var,
+    start: usize,
+    end: usize,
+) -> Result<CostMeasure, lalrpop_util::ParseError<usize, T, SyntaxError>> {
+    match name.as_str() {
+        "work" => Ok(CostMeasure::Work),
+        "span" => Ok(CostMeasure::Span),
+        _ => Err(error(SyntaxErrorKind::UnknownCostMeasure(name), start, end)),
+    }
+}
+
+/// Checks that the bounds of a cost contract, each spanning `start..end`, bound distinct measures.
+fn check_cost_bounds<T>(
+    bounds: Vec<(usize, CostMeasure, Expr, usize)>,
+) -> Result<Vec<(CostMeasure, Expr)>, lalrpop_util::ParseError<usize, T, SyntaxError>> {
+    let mut result = Vec::<(CostMeasure, Expr)>::new();
+    for (start, measure, bound, end) in bounds {
+        if result.iter().any(|(m, _)| *m == measure) {
+            return Err(error(
+                SyntaxErrorKind::DuplicateCostBound(measure),
+                start,
+                end,
+            ));
+        }
+        result.push((measure, bound));
+    }
+    Ok(result)
+}
@@ -0,0 +1,289 @@
+//! Resolution of variables to their lexical addresses.
+//!
+//! The environment of an evaluation is a stack of frames, one pushed by each binding of a let, each
+//! arm of a case or handler, and each application of a lambda, above a single frame of the
+//! top-level bindings.  The frames are laid out statically, so each variable bound inside an
+//! expression is resolved to the `(depth, index)` of its value: the `index`-th value of the
+//! `depth`-th innermost frame.  So are the variables bound in the environment where the expression
+//! is evaluated.  A lookup thus walks only the frames of the enclosing lets, arms and lambda.
+//!
+//! The closure of a resolved lambda captures the values of the free variables of its body in a frame
+//! of its own, instead of the whole environment where it is created.  The variables that cannot be
+//! resolved are left as they are, and reported when they are evaluated.
+
+use std::sync::Arc;
+
+use crate::semantics::Env;
+use crate::syntax::{Arm, Bind, Captures, Expr, Pattern, Value, Var};
+
+/// The frames of a lambda being resolved, or of the main expression.
+#[derive(Debug)]
+struct Func {
+    /// The variables of its frames, innermost last.
+    frames: Vec<Vec<Var>>,
+    /// The variables it captures with their addresses in the enclosing function.
+    captures: Vec<(Var, (usize, usize))>,
+}
+
+#[derive(Debug)]
+struct Resolver<'a> {
+    env: &'a Env,
+    /// The functions being resolved, innermost last.
+    funcs: Vec<Func>,
+}
+
+impl Resolver<'_> {
+    fn func(&mut self) -> &mut Func {
+        self.funcs.last_mut().unwrap()
+    }
+
+    /// Resolves a variable in the `level`-th function, capturing it if it is free there.
+    fn lookup(&mut self, level: usize, var: &Var) -> Option<(usize, usize)> {
+        let func = &self.funcs[level];
+        for (depth, frame) in func.frames.iter().rev().enumerate() {
+            if let Some(index) = frame.iter().rposition(|v| v == var) {
+                return Some((depth, index));
+            }
+        }
+
+        // The frame of the captured values, or the environment, is below the function's frames.
+        let depth = func.frames.len();
+        if level == 0 {
+            return self
+                .env
+                .iter()
+                .enumerate()
+                .find_map(|(i, env_piece)| Some((depth + i, env_piece.index(var)?)));
+        }
+        if let Some(index) = func.captures.iter().position(|(v, _)| v == var) {
+            return Some((depth, index));
+        }
+        let addr = self.lookup(level - 1, var)?;
+        let captures = &mut self.funcs[level].captures;
+        captures.push((var.clone(), addr));
+        Some((depth, captures.len() - 1))
+    }
+
+    /// Resolves `f` in the scope of a frame of the variables.
+    fn with_frame<T>(&mut self, vars: Vec<Var>, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.func().frames.push(vars);
+        let result = f(self);
+        self.func().frames.pop();
+        result
+    }
+
+    fn value(&mut self, value: &Value) -> Value {
+        match value {
+            Value::Tuple(values) => Value::Tuple(values.iter().map(|v| self.value(v)).collect()),
+            Value::Ctor {
+                ctor,
+                inner: Some(inner),
+            } => Value::Ctor {
+                ctor: ctor.clone(),
+                inner: Some(Arc::new(self.value(inner))),
+            },
+            Value::Lambda {
+                name,
+                pattern,
+                expr,
+                ..
+            } => {
+                let vars = name.iter().cloned().chain(pattern.vars()).collect();
+                self.funcs.push(Func {
+                    frames: vec![vars],
+                    captures: vec![],
+                });
+                let expr = self.expr(expr);
+                let func = self.funcs.pop().unwrap();
+
+                let (vars, addrs): (Vec<_>, _) = func.captures.into_iter().unzip();
+                Value::Lambda {
+                    name: name.clone(),
+                    pattern: pattern.clone(),
+                    expr: Arc::new(expr),
+                    captures: Some(Arc::new(Captures {
+                        vars: vars.into(),
+                        addrs,
+                    })),
+                }
+            }
+            Value::Native { func, args } => Value::Native {
+                func: func.clone(),
+                args: args.iter().map(|arg| self.value(arg)).collect(),
+            },
+            _ => value.clone(),
+        }
+    }
+
+    fn arms(&mut self, arms: &[Arm]) -> Vec<Arm> {
+        arms.iter()
+            .map(|arm| {
+                self.with_frame(arm.pattern.vars(), |resolver| Arm {
+                    pattern: arm.pattern.clone(),
+                    guard: arm
+                        .guard
+                        .as_ref()
+                        .map(|guard| Box::new(resolver.expr(guard))),
+                    expr: Box::new(resolver.expr(&arm.expr)),
+                })
+            })
+            .collect()
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Expr {
+        let mut boxed = |expr: &Expr| Box::new(self.expr(expr));
+        match expr {
+            Expr::Var(var) | Expr::Resolved { var, .. } => {
+                let level = self.funcs.len() - 1;
+                match self.lookup(level, var) {
+                    Some((depth, index)) => Expr::Resolved {
+                        var: var.clone(),
+                        depth,
+                        index,
+                    },
+                    None => Expr::Var(var.clone()),
+                }
+            }
+            Expr::Value(value) => Expr::Value(Box::new(self.value(value))),
+            Expr::UnaryOp { op, inner } => Expr::UnaryOp {
+                op: *op,
+                inner: boxed(inner),
+            },
+            Expr::BinaryOp { op, lhs, rhs } => Expr::BinaryOp {
+                op: *op,
+                lhs: boxed(lhs),
+                rhs: boxed(rhs),
+            },
+            Expr::SeqTuple(exprs) => Expr::SeqTuple(exprs.iter().map(|e| self.expr(e)).collect()),
+            Expr::ParTuple(exprs) => Expr::ParTuple(exprs.iter().map(|e| self.expr(e)).collect()),
+            Expr::Case { inner, arms, span } => Expr::Case {
+                inner: boxed(inner),
+                arms: self.arms(arms),
+                span: *span,
+            },
+            Expr::Ite { cond, lhs, rhs } => Expr::Ite {
+                cond: boxed(cond),
+                lhs: boxed(lhs),
+                rhs: boxed(rhs),
+            },
+            Expr::AndAlso { lhs, rhs } => Expr::AndAlso {
+                lhs: boxed(lhs),
+                rhs: boxed(rhs),
+            },
+            Expr::OrElse { lhs, rhs } => Expr::OrElse {
+                lhs: boxed(lhs),
+                rhs: boxed(rhs),
+            },
+            Expr::Raise { inner, span } => Expr::Raise {
+                inner: boxed(inner),
+                span: *span,
+            },
+            Expr::Handle { inner, arms } => Expr::Handle {
+                inner: boxed(inner),
+                arms: self.arms(arms),
+            },
+            Expr::Assert { inner, span } => Expr::Assert {
+                inner: boxed(inner),
+                span: *span,
+            },
+            Expr::WithCost {
+                bounds,
+                inner,
+                span,
+            } => Expr::WithCost {
+                bounds: bounds
+                    .iter()
+                    .map(|(measure, bound)| (*measure, self.expr(bound)))
+                    .collect(),
+                inner: Box::new(self.expr(inner)),
+                span: *span,
+            },
+            Expr::Tick { name, count, inner } => Expr::Tick {
+                name: name.clone(),
+                count: boxed(count),
+                inner: boxed(inner),
+            },
+            Expr::App { lhs, rhs, span } => Expr::App {
+                lhs: boxed(lhs),
+                rhs: boxed(rhs),
+                span: *span,
+            },
+            // Each binding pushes a frame, seen by the bindings after it.
+            Expr::Let { binds, expr } => {
+                let binds = binds
+                    .iter()
+                    .map(|bind| {
+                        let expr = self.expr(&bind.expr);
+                        self.func().frames.push(bind.pattern.vars());
+                        Bind {
+                            pattern: bind.pattern.clone(),
+                            expr: Box::new(expr),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                let expr = self.expr(expr);
+                let len = self.func().frames.len();
+                self.func().frames.truncate(len - binds.len());
+                Expr::Let {
+                    binds,
+                    expr: Box::new(expr),
+                }
+            }
+            // Each binding pushes a frame, seen only by the body.
+            Expr::ParLet { binds, expr } => {
+                let binds = binds
+                    .iter()
+                    .map(|bind| Bind {
+                        pattern: bind.pattern.clone(),
+                        expr: Box::new(self.expr(&bind.expr)),
+                    })
+                    .collect::<Vec<_>>();
+                let patterns = binds.iter().map(|bind| &bind.pattern);
+                let expr = self.with_frames(patterns, |resolver| resolver.expr(expr));
+                Expr::ParLet {
+                    binds,
+                    expr: Box::new(expr),
+                }
+            }
+        }
+    }
+
+    /// Resolves `f` in the scope of frames of the variables of the patterns.
+    fn with_frames<'p, T>(
+        &mut self,
+        patterns: impl Iterator<Item = &'p Arc<Pattern>>,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        let len = self.func().frames.len();
+        for pattern in patterns {
+            self.func().frames.push(pattern.vars());
+        }
+        let result = f(self);
+        self.func().frames.truncate(len);
+        result
+    }
+}
+
+/// Resolves the variables of an expression evaluated in `env`.
+pub(crate) fn resolve(env: &Env, expr: &Expr) -> Expr {
+    Resolver {
+        env,
+        funcs: vec![Func {
+            frames: vec![],
+            captures: vec![],
+        }],
+    }
+    .expr(expr)
+}
+
+/// Resolves the variables of a value evaluated in `env`.
+pub(crate) fn resolve_value(env: &Env, value: &Value) -> Value {
+    Resolver {
+        env,
+        funcs: vec![Func {
+            frames: vec![],
+            captures: vec![],
+        }],
+    }
+    .value(value)
+}
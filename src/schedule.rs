@@ -0,0 +1,335 @@
+//! Simulation of randomized work stealing.
+//!
+//! While recording, `Env::eval_expr` builds the fork-join DAG of the steps it counts: the steps of
+//! an expression form a chain, the subexpressions whose spans are combined by their maximum are
+//! forked and joined, and the others follow one another.  The recorder is thread-local, so parallel
+//! expressions are evaluated sequentially while recording; their costs are unaffected.
+//!
+//! The DAG is executed by simulated workers, each executing one step at a time.  A worker that
+//! reaches a fork pushes the right branch to the bottom of its deque and continues with the left
+//! one.  A worker that reaches a join continues past it if it is the last to arrive, and otherwise
+//! pops the bottom of its deque.  A worker without work spends each step on an attempt to steal
+//! the top of the deque of a victim chosen uniformly at random.  The expected completion time is
+//! within W/P + O(S) for work W, span S and P workers.
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::sync::Arc;
+
+thread_local! {
+    static RECORDING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `f`, recording the DAGs of the evaluations in it if `record` is set.
+pub(crate) fn record<T>(record: bool, f: impl FnOnce() -> T) -> T {
+    let saved = RECORDING.with(|cell| cell.replace(record));
+    let result = f();
+    RECORDING.with(|cell| cell.set(saved));
+    result
+}
+
+/// Returns whether the DAGs of evaluations are recorded.
+pub(crate) fn recording() -> bool {
+    RECORDING.with(Cell::get)
+}
+
+/// The fork-join DAG of the steps of an evaluation, which is empty unless recorded.
+#[derive(Debug, Clone, Default)]
+pub struct Dag(Option<Arc<Node>>);
+
+#[derive(Debug)]
+enum Node {
+    /// A chain of steps.
+    Steps(u64),
+    /// At least two DAGs, one after another.
+    Seq(Vec<Dag>),
+    /// Two DAGs forked and joined.
+    Par(Dag, Dag),
+}
+
+impl Dag {
+    pub(crate) const EMPTY: Self = Self(None);
+
+    fn node(node: Node) -> Self {
+        Self(Some(Arc::new(node)))
+    }
+
+    /// Returns whether the DAG is empty, i.e. not recorded.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    /// Returns a chain of `steps` steps.
+    pub(crate) fn steps(steps: u64) -> Self {
+        if steps == 0 || !recording() {
+            return Self::default();
+        }
+        Self::node(Node::Steps(steps))
+    }
+
+    /// Returns the DAG of a native function of the given cost: a chain of `span` steps, forked with
+    /// the rest of the work as independent steps.
+    pub(crate) fn native(work: u64, span: u64) -> Self {
+        /// Returns `steps` independent steps, forked in halves.
+        fn independent(steps: u64) -> Dag {
+            if steps <= 1 {
+                return Dag::steps(steps);
+            }
+            independent(steps / 2).par(independent(steps - steps / 2))
+        }
+
+        Self::steps(span).par(independent(work.saturating_sub(span)))
+    }
+
+    /// Returns the DAG of `self` and then `next`.
+    pub(crate) fn then(self, next: Self) -> Self {
+        let (prev, next) = match (self.0, next.0) {
+            (None, dag) | (dag, None) => return Self(dag),
+            (Some(prev), Some(next)) => (prev, next),
+        };
+        if let (Node::Steps(lhs), Node::Steps(rhs)) = (&*prev, &*next) {
+            return Self::node(Node::Steps(lhs + rhs));
+        }
+
+        // Extends a sequence in place, so that a loop of tail calls gives a flat one.
+        let mut dags = match Arc::try_unwrap(prev) {
+            Ok(Node::Seq(dags)) => dags,
+            Ok(node) => vec![Self::node(node)],
+            Err(prev) => vec![Self(Some(prev))],
+        };
+        match (dags.last().and_then(|dag| dag.0.as_deref()), &*next) {
+            (Some(Node::Steps(lhs)), Node::Steps(rhs)) => {
+                let steps = lhs + rhs;
+                *dags.last_mut().unwrap() = Self::node(Node::Steps(steps));
+            }
+            _ => dags.push(Self(Some(next))),
+        }
+        Self::node(Node::Seq(dags))
+    }
+
+    /// Returns the DAG of `self` and `next` in parallel.
+    pub(crate) fn par(self, next: Self) -> Self {
+        match (self.0, next.0) {
+            (None, dag) | (dag, None) => Self(dag),
+            (Some(lhs), Some(rhs)) => Self::node(Node::Par(Self(Some(lhs)), Self(Some(rhs)))),
+        }
+    }
+}
+
+/// Options of simulating work stealing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkStealingOptions {
+    /// The number of workers.  Must be positive.
+    pub workers: usize,
+    /// The number of simulations, with the random seeds `0..seeds`, to average over.  Must be
+    /// positive.
+    pub seeds: u64,
+}
+
+/// The averages of simulated work-stealing schedules.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Averages {
+    /// The number of steps until the DAG is executed.
+    pub(crate) time: f64,
+    /// The number of successful steals.
+    pub(crate) steals: f64,
+    /// The total number of steps the workers spend without work, i.e. on attempts to steal.
+    pub(crate) idle: f64,
+}
+
+/// Simulates work stealing on the DAG with each seed, and returns the averages.
+pub(crate) fn work_stealing(dag: &Dag, options: WorkStealingOptions) -> Averages {
+    let mut time = 0;
+    let mut steals = 0;
+    let mut idle = 0;
+    for seed in 0..options.seeds {
+        let run = Simulation::new(options.workers, seed).run(dag);
+        time += run.time;
+        steals += run.steals;
+        idle += run.idle;
+    }
+
+    let seeds = options.seeds as f64;
+    Averages {
+        time: time as f64 / seeds,
+        steals: steals as f64 / seeds,
+        idle: idle as f64 / seeds,
+    }
+}
+
+/// The SplitMix64 generator, which suffices for choosing victims.
+#[derive(Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// What follows the execution of a DAG.
+#[derive(Debug)]
+enum Cont<'a> {
+    /// The end of the evaluation.
+    Done,
+    /// The rest of a sequence, which is not empty, and then `next`.
+    Seq {
+        rest: &'a [Dag],
+        next: Rc<Cont<'a>>,
+    },
+    Join(Rc<Join<'a>>),
+}
+
+/// A join, awaiting the `pending` branches of its fork before `next`.
+#[derive(Debug)]
+struct Join<'a> {
+    pending: Cell<usize>,
+    next: Rc<Cont<'a>>,
+}
+
+/// A DAG to execute, and what follows it.
+type Job<'a> = (&'a Dag, Rc<Cont<'a>>);
+
+#[derive(Debug, Default)]
+struct Worker<'a> {
+    /// The number of steps left on the chain being executed, and what follows it.
+    chain: Option<(u64, Rc<Cont<'a>>)>,
+    deque: VecDeque<Job<'a>>,
+}
+
+/// The result of a simulation.
+#[derive(Debug, Default)]
+struct Run {
+    time: u64,
+    steals: u64,
+    idle: u64,
+}
+
+#[derive(Debug)]
+struct Simulation<'a> {
+    workers: Vec<Worker<'a>>,
+    rng: Rng,
+    done: bool,
+}
+
+impl<'a> Simulation<'a> {
+    fn new(workers: usize, seed: u64) -> Self {
+        Self {
+            workers: (0..workers).map(|_| Worker::default()).collect(),
+            rng: Rng(seed),
+            done: false,
+        }
+    }
+
+    /// Returns the job that follows the execution of a DAG, unless it is a join awaiting other
+    /// branches.
+    fn resume(&mut self, mut cont: Rc<Cont<'a>>) -> Option<Job<'a>> {
+        loop {
+            let next = match &*cont {
+                Cont::Done => {
+                    self.done = true;
+                    return None;
+                }
+                Cont::Seq { rest, next } => {
+                    let rest: &'a [Dag] = rest;
+                    let next = match rest.len() {
+                        1 => next.clone(),
+                        _ => Rc::new(Cont::Seq {
+                            rest: &rest[1..],
+                            next: next.clone(),
+                        }),
+                    };
+                    return Some((&rest[0], next));
+                }
+                Cont::Join(join) => {
+                    join.pending.set(join.pending.get() - 1);
+                    if join.pending.get() > 0 {
+                        return None;
+                    }
+                    join.next.clone()
+                }
+            };
+            cont = next;
+        }
+    }
+
+    /// Lets the worker proceed to the next chain of steps, starting from `job` if given, and
+    /// otherwise from the bottom of its deque.  The forks and joins on the way take no time.
+    fn proceed(&mut self, worker: usize, mut job: Option<Job<'a>>) {
+        loop {
+            let (dag, cont) = match job.take() {
+                Some(job) => job,
+                None => match self.workers[worker].deque.pop_back() {
+                    Some(job) => job,
+                    None => return,
+                },
+            };
+            match dag.0.as_deref() {
+                None => job = self.resume(cont),
+                Some(Node::Steps(steps)) => {
+                    self.workers[worker].chain = Some((*steps, cont));
+                    return;
+                }
+                Some(Node::Seq(dags)) => {
+                    let next = Rc::new(Cont::Seq {
+                        rest: &dags[1..],
+                        next: cont,
+                    });
+                    job = Some((&dags[0], next));
+                }
+                Some(Node::Par(lhs, rhs)) => {
+                    let join = Rc::new(Join {
+                        pending: Cell::new(2),
+                        next: cont,
+                    });
+                    let rhs = (rhs, Rc::new(Cont::Join(join.clone())));
+                    self.workers[worker].deque.push_back(rhs);
+                    job = Some((lhs, Rc::new(Cont::Join(join))));
+                }
+            }
+        }
+    }
+
+    /// Executes the DAG.  In each step, the workers take their turns in order.
+    fn run(mut self, dag: &'a Dag) -> Run {
+        let mut run = Run::default();
+        self.proceed(0, Some((dag, Rc::new(Cont::Done))));
+        let workers = self.workers.len();
+        while !self.done {
+            run.time += 1;
+            for worker in 0..workers {
+                match self.workers[worker].chain.take() {
+                    Some((steps, cont)) if steps > 1 => {
+                        self.workers[worker].chain = Some((steps - 1, cont))
+                    }
+                    Some((_, cont)) => {
+                        let job = self.resume(cont);
+                        self.proceed(worker, job);
+                    }
+                    None => {
+                        run.idle += 1;
+                        if workers == 1 {
+                            continue;
+                        }
+                        let victim = (worker + 1 + self.rng.below(workers - 1)) % workers;
+                        if let Some(job) = self.workers[victim].deque.pop_front() {
+                            run.steals += 1;
+                            self.proceed(worker, Some(job));
+                        }
+                    }
+                }
+            }
+        }
+        run
+    }
+}
@@ -1,25 +1,661 @@
-// TODO: what is `Arc` and what is `Rc`?
-
+use std::cell::{Cell, RefCell};
 use std::cmp;
-use std::collections::HashMap;
-use std::rc::Rc;
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops;
+use std::sync::{Arc, OnceLock};
+
+use rayon::prelude::*;
 
 use crate::arc_list::ArcList;
-use crate::syntax::{BinaryOp, Ctor, Expr, Pattern, UnaryOp, Value as SynValue, Var};
+use crate::bytecode::Func;
+use crate::exhaustive::Exceptions;
+use crate::native::NativeFn;
+use crate::schedule::{self, Dag};
+use crate::syntax::{
+    Arm, BinaryOp, Bind, CostMeasure, Ctor, Expr, Pattern, Span, UnaryOp, Value as SynValue, Var,
+};
+use crate::trace;
+use crate::vm;
 
+/// The result of an evaluation together with its cost.
 #[derive(Debug, Clone)]
 pub struct Res<T> {
+    /// The result.
     pub result: T,
+    /// The work, i.e. the total number of steps.
     pub work: u64,
+    /// The span, i.e. the number of steps on the critical path.
     pub span: u64,
+    /// The space, the counts charged by `tick` and the fork-join DAG of the steps.
+    pub extras: Extras,
+}
+
+/// The numbers of cells allocated by an evaluation, by kind.  Constants such as literal tuples, the
+/// unit and nullary constructors are not allocated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Allocs {
+    /// Tuples, e.g. pairs.
+    pub tuples: u64,
+    /// Constructors applied to arguments.
+    pub ctors: u64,
+    /// Closures, including partial applications of native functions.
+    pub closures: u64,
+    /// Strings, the only sequences, which are built by native functions.
+    pub strings: u64,
+}
+
+impl Allocs {
+    const TUPLE: Self = Self {
+        tuples: 1,
+        ctors: 0,
+        closures: 0,
+        strings: 0,
+    };
+    const CTOR: Self = Self {
+        tuples: 0,
+        ctors: 1,
+        closures: 0,
+        strings: 0,
+    };
+    pub(crate) const CLOSURE: Self = Self {
+        tuples: 0,
+        ctors: 0,
+        closures: 1,
+        strings: 0,
+    };
+
+    /// Returns the total number of cells.
+    pub fn total(&self) -> u64 {
+        self.tuples + self.ctors + self.closures + self.strings
+    }
+
+    /// Returns the cells of a value built afresh, e.g. by a native function.
+    fn of(value: &Value) -> Self {
+        match value {
+            Value::Tuple(values) if !values.is_empty() => values
+                .iter()
+                .fold(Self::TUPLE, |allocs, value| allocs + Self::of(value)),
+            Value::Ctor {
+                inner: Some(inner), ..
+            } => Self::CTOR + Self::of(inner),
+            Value::Lambda { .. } | Value::Closure { .. } => Self::CLOSURE,
+            Value::Native { args, .. } if !args.is_empty() => args
+                .iter()
+                .fold(Self::CLOSURE, |allocs, arg| allocs + Self::of(arg)),
+            Value::String(_) => Self {
+                strings: 1,
+                ..Self::default()
+            },
+            _ => Self::default(),
+        }
+    }
+
+    /// Returns the cells allocated by evaluating a literal, i.e. the closures of its lambdas.
+    fn of_literal(value: &SynValue) -> Self {
+        match value {
+            SynValue::Tuple(values) => values.iter().fold(Self::default(), |allocs, value| {
+                allocs + Self::of_literal(value)
+            }),
+            SynValue::Ctor {
+                inner: Some(inner), ..
+            } => Self::of_literal(inner),
+            // A lambda without free variables is a constant.
+            SynValue::Lambda {
+                captures: Some(captures),
+                ..
+            } if captures.vars.is_empty() => Self::default(),
+            SynValue::Lambda { .. } => Self::CLOSURE,
+            SynValue::Native { args, .. } if !args.is_empty() => args
+                .iter()
+                .fold(Self::CLOSURE, |allocs, arg| allocs + Self::of_literal(arg)),
+            _ => Self::default(),
+        }
+    }
+}
+
+impl ops::Add for Allocs {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            tuples: self.tuples + other.tuples,
+            ctors: self.ctors + other.ctors,
+            closures: self.closures + other.closures,
+            strings: self.strings + other.strings,
+        }
+    }
+}
+
+impl fmt::Display for Allocs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "tuples: {}, constructors: {}, closures: {}, strings: {}",
+            self.tuples, self.ctors, self.closures, self.strings
+        )
+    }
+}
+
+thread_local! {
+    static MEASURING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `f`, measuring the space of the evaluations in it if `measure` is set.
+pub(crate) fn measure_space<T>(measure: bool, f: impl FnOnce() -> T) -> T {
+    let saved = MEASURING.with(|cell| cell.replace(measure));
+    let result = f();
+    MEASURING.with(|cell| cell.set(saved));
+    result
+}
+
+/// Returns whether the space of evaluations is measured.
+fn measuring_space() -> bool {
+    MEASURING.with(Cell::get)
+}
+
+/// The builtin exception raised by an assertion that does not hold.
+pub(crate) const ASSERTION_FAILED: &str = "AssertionFailed";
+
+/// The builtin exception raised by a violated cost contract, with a description of the violation.
+pub(crate) const COST_CONTRACT_VIOLATED: &str = "CostContractViolated";
+
+thread_local! {
+    static EXCEPTIONS: RefCell<Arc<Exceptions>> = RefCell::default();
+}
+
+/// Runs `f`, in whose evaluations only the `exceptions` may be raised.
+pub(crate) fn with_exceptions<T>(exceptions: &Arc<Exceptions>, f: impl FnOnce() -> T) -> T {
+    let saved = EXCEPTIONS.with(|cell| cell.replace(exceptions.clone()));
+    let result = f();
+    EXCEPTIONS.with(|cell| *cell.borrow_mut() = saved);
+    result
+}
+
+/// Returns the exceptions that may be raised.
+fn exceptions() -> Arc<Exceptions> {
+    EXCEPTIONS.with(|cell| cell.borrow().clone())
+}
+
+/// Returns whether the constructor, applied to an argument if `applied`, is a declared exception.
+pub(crate) fn is_exception(ctor: &Ctor, applied: bool) -> bool {
+    EXCEPTIONS.with(|cell| {
+        cell.borrow()
+            .get(ctor)
+            .is_some_and(|ty| ty.is_some() == applied)
+    })
+}
+
+/// The space of an evaluation, counted in the cells it allocates, which is empty unless measured.
+/// An empty space is that of an evaluation allocating nothing.
+///
+/// A cell is live while it is part of the value of a variable in scope, or of a result that is yet
+/// to be used.  When the scope of a variable ends, or the function it is bound in makes a tail call,
+/// its value is freed if the result of the scope is a scalar, e.g. an integer, and is taken to be
+/// part of the result otherwise.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Space(Option<Box<Usage>>);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Usage {
+    /// The allocated cells.
+    allocs: Allocs,
+    /// The number of live cells of the result.
+    live: u64,
+    /// The peak numbers of live cells before the call in tail position, if any, under the
+    /// sequential and the parallel schedules.
+    seq: u64,
+    par: u64,
+    /// The peak numbers during the call in tail position, when the values of the variables of the
+    /// function making the call are freed.
+    tail_seq: u64,
+    tail_par: u64,
+}
+
+impl Usage {
+    fn peak_seq(&self) -> u64 {
+        cmp::max(self.seq, self.tail_seq)
+    }
+
+    fn peak_par(&self) -> u64 {
+        cmp::max(self.par, self.tail_par)
+    }
+
+    fn close(self) -> Self {
+        Self {
+            seq: self.peak_seq(),
+            par: self.peak_par(),
+            tail_seq: 0,
+            tail_par: 0,
+            ..self
+        }
+    }
+
+    fn then(self, next: Self) -> Self {
+        let (prev, next) = (self.close(), next.close());
+        Self {
+            allocs: prev.allocs + next.allocs,
+            live: prev.live + next.live,
+            seq: cmp::max(prev.seq, prev.live + next.seq),
+            par: cmp::max(prev.par, prev.live + next.par),
+            tail_seq: 0,
+            tail_par: 0,
+        }
+    }
+
+    fn par(self, next: Self) -> Self {
+        let (prev, next) = (self.close(), next.close());
+        Self {
+            par: prev.par + next.par,
+            ..prev.then(next)
+        }
+    }
+
+    fn free(self, body: Self) -> Self {
+        let prev = self.close();
+        Self {
+            allocs: prev.allocs + body.allocs,
+            live: body.live,
+            seq: cmp::max(prev.seq, prev.live + body.seq),
+            par: cmp::max(prev.par, prev.live + body.par),
+            tail_seq: body.tail_seq,
+            tail_par: body.tail_par,
+        }
+    }
+
+    fn call(self, body: Self) -> Self {
+        Self {
+            allocs: self.allocs + body.allocs,
+            live: body.live,
+            seq: self.seq,
+            par: self.par,
+            tail_seq: cmp::max(self.tail_seq, cmp::max(self.live + body.seq, body.tail_seq)),
+            tail_par: cmp::max(self.tail_par, cmp::max(self.live + body.par, body.tail_par)),
+        }
+    }
 }
 
+impl Space {
+    /// Returns the space of allocating the cells of a result.
+    pub(crate) fn cells(allocs: Allocs) -> Self {
+        let total = allocs.total();
+        if total == 0 || !measuring_space() {
+            return Self::default();
+        }
+        Self(Some(Box::new(Usage {
+            allocs,
+            live: total,
+            seq: total,
+            par: total,
+            tail_seq: 0,
+            tail_par: 0,
+        })))
+    }
+
+    /// Returns the space of allocating the cells of a value built afresh, e.g. by a native
+    /// function.
+    fn of(value: &Value) -> Self {
+        if !measuring_space() {
+            return Self::default();
+        }
+        Self::cells(Allocs::of(value))
+    }
+
+    /// Returns the space of evaluating a literal.
+    fn of_literal(value: &SynValue) -> Self {
+        if !measuring_space() {
+            return Self::default();
+        }
+        Self::cells(Allocs::of_literal(value))
+    }
+
+    fn usage(&self) -> Usage {
+        self.0.as_deref().copied().unwrap_or_default()
+    }
+
+    /// Applies `f` to the usages, unless both spaces are empty.
+    fn map2(self, other: Self, f: impl FnOnce(Usage, Usage) -> Usage) -> Self {
+        match (self.0, other.0) {
+            (None, None) => Self::default(),
+            (lhs, rhs) => {
+                let usage = f(
+                    lhs.as_deref().copied().unwrap_or_default(),
+                    rhs.as_deref().copied().unwrap_or_default(),
+                );
+                // Reuses an allocation of either.
+                let mut boxed = lhs.or(rhs).unwrap();
+                *boxed = usage;
+                Self(Some(boxed))
+            }
+        }
+    }
+
+    /// Returns the allocated cells.
+    pub fn allocs(&self) -> Allocs {
+        self.usage().allocs
+    }
+
+    /// Returns the number of live cells of the result.
+    pub fn live(&self) -> u64 {
+        self.usage().live
+    }
+
+    /// Returns the peak number of live cells when parallel subexpressions are evaluated one after
+    /// another from left to right, i.e. under the sequential depth-first schedule.
+    pub fn peak_seq(&self) -> u64 {
+        self.usage().peak_seq()
+    }
+
+    /// Returns the peak number of live cells when parallel subexpressions are evaluated at the same
+    /// time, i.e. under the parallel schedule.
+    pub fn peak_par(&self) -> u64 {
+        self.usage().peak_par()
+    }
+
+    /// Returns the space of the evaluation out of tail position.
+    fn close(self) -> Self {
+        self.map2(Self::default(), |usage, _| usage.close())
+    }
+
+    /// Returns the space of evaluating `self` and then `next` while the result of `self` is live.
+    /// The result consists of both results.
+    fn then(self, next: Self) -> Self {
+        self.map2(next, Usage::then)
+    }
+
+    /// Returns the space of evaluating `self` and `next` in parallel.  The result consists of both
+    /// results.
+    fn par(self, next: Self) -> Self {
+        self.map2(next, Usage::par)
+    }
+
+    /// Returns the space of evaluating `self` and then `body` in tail position of its scope, whose
+    /// result is `result`.
+    fn scope(self, body: Self, result: &Value) -> Self {
+        if result.holds_cells() {
+            self.then(body)
+        } else {
+            self.free(body)
+        }
+    }
+
+    /// Returns the space of evaluating `self` and then `body` in tail position of its scope, where
+    /// the result of `self` is freed at the end or at a tail call.
+    fn free(self, body: Self) -> Self {
+        self.map2(body, Usage::free)
+    }
+
+    /// Returns the space of evaluating a function and an argument in `self`, and then calling the
+    /// function in tail position, where `body` is that of the function's body.  The function and
+    /// the argument are freed at the end or at the body's tail call.
+    fn call(self, body: Self) -> Self {
+        self.map2(body, Usage::call)
+    }
+
+    /// Returns the space with the result discarded, e.g. when it is a scalar computed from it.
+    fn discard(self) -> Self {
+        self.map2(Self::default(), |usage, _| Usage {
+            live: 0,
+            ..usage.close()
+        })
+    }
+
+    /// Returns the space with `held` more live cells, which are freed at the end or at a tail call.
+    fn hold(self, held: u64) -> Self {
+        if held == 0 {
+            return self;
+        }
+        self.map2(Self::default(), |usage, _| Usage {
+            live: usage.live + held,
+            ..usage
+        })
+    }
+}
+
+/// The counts charged to a counter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Count {
+    /// The total count.
+    pub work: u64,
+    /// The count on the critical path, i.e. the largest sum of the counts charged one after
+    /// another.
+    pub span: u64,
+}
+
+/// The counts charged by `tick`, by counter.  They add up as work and span do: the counts of
+/// parallel evaluations are summed in work, and the largest of them is taken in span.
+///
+/// Most evaluations charge no counter, so the map is allocated only once one is charged.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Counters(Option<Arc<BTreeMap<Var, Count>>>);
+
+impl Counters {
+    /// Returns the counters in the order of their names.
+    pub fn iter(&self) -> impl Iterator<Item = (&Var, &Count)> {
+        self.0.iter().flat_map(|counters| counters.iter())
+    }
+
+    /// Charges `count` to the counter `name`.
+    pub(crate) fn tick(self, name: &str, count: u64) -> Self {
+        let mut counters = self.0.unwrap_or_default();
+        let entry = Arc::make_mut(&mut counters)
+            .entry(name.to_string())
+            .or_default();
+        entry.work += count;
+        entry.span += count;
+        Self(Some(counters))
+    }
+
+    /// Combines the counts of two evaluations, taking `span` of the counts in span.
+    fn combine(self, other: Self, span: fn(u64, u64) -> u64) -> Self {
+        match (self.0, other.0) {
+            (None, counters) | (counters, None) => Self(counters),
+            (Some(mut counters), Some(other)) => {
+                for (name, count) in other.iter() {
+                    let entry = Arc::make_mut(&mut counters)
+                        .entry(name.clone())
+                        .or_default();
+                    entry.work += count.work;
+                    entry.span = span(entry.span, count.span);
+                }
+                Self(Some(counters))
+            }
+        }
+    }
+
+    /// Returns the counts of evaluating `self` and then `next`.
+    pub(crate) fn then(self, next: Self) -> Self {
+        self.combine(next, |lhs, rhs| lhs + rhs)
+    }
+
+    /// Returns the counts of evaluating `self` and `next` in parallel.
+    pub(crate) fn par(self, next: Self) -> Self {
+        self.combine(next, cmp::max)
+    }
+}
+
+/// The costs of an evaluation besides its work and span: its space, counters and DAG.
+///
+/// Most evaluations measure no space, charge no counter and record no DAG, so the costs are
+/// allocated only once one of them is not empty.  This keeps results small, and with them the stack
+/// frames of the evaluator, which recurses once per nested expression.
+#[derive(Debug, Clone, Default)]
+pub struct Extras(Option<Box<ExtraCosts>>);
+
+#[derive(Debug, Clone, Default)]
+struct ExtraCosts {
+    space: Space,
+    counters: Counters,
+    dag: Dag,
+}
+
+static EMPTY: ExtraCosts = ExtraCosts {
+    space: Space(None),
+    counters: Counters(None),
+    dag: Dag::EMPTY,
+};
+
+impl From<Space> for Extras {
+    fn from(space: Space) -> Self {
+        Self::new(space, Counters::default(), Dag::default())
+    }
+}
+
+impl From<Counters> for Extras {
+    fn from(counters: Counters) -> Self {
+        Self::new(Space::default(), counters, Dag::default())
+    }
+}
+
+impl From<Dag> for Extras {
+    fn from(dag: Dag) -> Self {
+        Self::new(Space::default(), Counters::default(), dag)
+    }
+}
+
+impl Extras {
+    /// Returns the costs, which are allocated unless all of them are empty.
+    pub(crate) fn new(space: Space, counters: Counters, dag: Dag) -> Self {
+        if space.0.is_none() && counters.0.is_none() && dag.is_empty() {
+            return Self::default();
+        }
+        Self(Some(Box::new(ExtraCosts {
+            space,
+            counters,
+            dag,
+        })))
+    }
+
+    /// Returns the costs of a chain of `steps` steps.
+    pub(crate) fn steps(steps: u64) -> Self {
+        Dag::steps(steps).into()
+    }
+
+    fn costs(&self) -> &ExtraCosts {
+        self.0.as_deref().unwrap_or(&EMPTY)
+    }
+
+    fn into_costs(self) -> ExtraCosts {
+        self.0.map(|costs| *costs).unwrap_or_default()
+    }
+
+    /// Returns the space.  Only the environment-based evaluator measures it, and only when
+    /// requested; it is empty otherwise.
+    pub fn space(&self) -> &Space {
+        &self.costs().space
+    }
+
+    /// Returns the counts charged by `tick`.
+    pub fn counters(&self) -> &Counters {
+        &self.costs().counters
+    }
+
+    /// Returns the fork-join DAG of the steps.  Only the environment-based evaluator records it,
+    /// and only when requested.
+    pub fn dag(&self) -> &Dag {
+        &self.costs().dag
+    }
+
+    /// Combines the costs of two evaluations, unless both are empty.
+    fn map2(
+        self,
+        other: Self,
+        space: impl FnOnce(Space, Space) -> Space,
+        counters: fn(Counters, Counters) -> Counters,
+        dag: fn(Dag, Dag) -> Dag,
+    ) -> Self {
+        if self.0.is_none() && other.0.is_none() {
+            return Self::default();
+        }
+        let (lhs, rhs) = (self.into_costs(), other.into_costs());
+        Self::new(
+            space(lhs.space, rhs.space),
+            counters(lhs.counters, rhs.counters),
+            dag(lhs.dag, rhs.dag),
+        )
+    }
+
+    /// Applies `f` to the space, unless the costs are empty.
+    fn map_space(self, f: impl FnOnce(Space) -> Space) -> Self {
+        match self.0 {
+            None => self,
+            Some(costs) => Self::new(f(costs.space), costs.counters, costs.dag),
+        }
+    }
+
+    /// Returns the costs with the space taken out.
+    fn take_space(self) -> (Space, Self) {
+        match self.0 {
+            None => (Space::default(), self),
+            Some(costs) => (
+                costs.space,
+                Self::new(Space::default(), costs.counters, costs.dag),
+            ),
+        }
+    }
+
+    /// Returns the costs with the space replaced by `space`.
+    fn with_space(self, space: Space) -> Self {
+        let costs = self.into_costs();
+        Self::new(space, costs.counters, costs.dag)
+    }
+
+    /// Returns the costs of evaluating `self` and then `next`.
+    fn then(self, next: Self) -> Self {
+        self.map2(next, Space::then, Counters::then, Dag::then)
+    }
+
+    /// Returns the costs of evaluating `self` and `next` in parallel.
+    fn par(self, next: Self) -> Self {
+        self.map2(next, Space::par, Counters::par, Dag::par)
+    }
+
+    /// Returns the costs of evaluating `self` and then `body` in tail position of its scope, whose
+    /// result is `result`.
+    fn scope(self, body: Self, result: &Value) -> Self {
+        self.map2(
+            body,
+            |space, body| space.scope(body, result),
+            Counters::then,
+            Dag::then,
+        )
+    }
+
+    /// Returns the costs followed by a chain of `steps` steps, which leaves the space as it is.
+    fn then_steps(self, steps: u64) -> Self {
+        let dag = Dag::steps(steps);
+        if dag.is_empty() {
+            return self;
+        }
+        let costs = self.into_costs();
+        Self::new(costs.space, costs.counters, costs.dag.then(dag))
+    }
+
+    /// Returns the costs with the space out of tail position.
+    fn close(self) -> Self {
+        self.map_space(Space::close)
+    }
+
+    /// Returns the costs with the result discarded from the space.
+    fn discard(self) -> Self {
+        self.map_space(Space::discard)
+    }
+}
+
+/// Runtime errors.
+#[allow(missing_docs)]
 #[derive(Debug, Clone)]
 pub enum Err {
     InvalidIteCond {
         cond: Arc<Value>,
     },
+    InvalidGuard {
+        cond: Arc<Value>,
+    },
+    InvalidShortCircuitArg {
+        arg: Arc<Value>,
+    },
     InvalidUnaryOpArgs {
         op: UnaryOp,
         inner: Arc<Value>,
@@ -32,9 +668,10 @@ pub enum Err {
     InvalidAppArgs {
         inner: Arc<Value>,
     },
+    DivisionByZero,
     CaseNoMatch {
         inner: Arc<Value>,
-        patterns: Vec<Rc<Pattern>>,
+        patterns: Vec<Arc<Pattern>>,
     },
     EnvNotFound {
         var: Var,
@@ -47,99 +684,554 @@ pub enum Err {
         ctor_pattern: Ctor,
         ctor_value: Ctor,
     },
+    Native {
+        name: String,
+        message: String,
+    },
+    /// An exception raised by `raise` at `location`.  `work`, `span` and `extras` are the cost of
+    /// the evaluation it abandons up to the raise, i.e. of the raise expression and of what was
+    /// evaluated before it since the evaluation began or the innermost handler was entered.
+    Raise {
+        value: Arc<Value>,
+        location: Span,
+        work: u64,
+        span: u64,
+        extras: Extras,
+    },
+    /// The raise at `location` raises a value that is not a declared exception, or a declared
+    /// exception without the argument it takes or with one it does not.  Evidently wrong raises
+    /// are rejected before the evaluation, and the others when they are evaluated.
+    NotAnException {
+        location: Span,
+    },
+    InvalidAssertion {
+        cond: Arc<Value>,
+    },
+    InvalidCostBound {
+        bound: Arc<Value>,
+    },
+    /// The count of a tick is not a non-negative integer.
+    InvalidTickCount {
+        count: Arc<Value>,
+    },
+    /// The evaluation was abandoned by the debugger.
+    Interrupted,
+}
+
+impl Err {
+    /// Adds the cost of an evaluation before the one that failed, if it raised an exception.
+    fn after(self, work: u64, span: u64, extras: &Extras) -> Self {
+        match self {
+            Err::Raise {
+                value,
+                location,
+                work: w,
+                span: s,
+                extras: e,
+            } => Err::Raise {
+                value,
+                location,
+                work: work + w,
+                span: span + s,
+                extras: extras.clone().then(e),
+            },
+            e => e,
+        }
+    }
+
+    /// Adds the cost of an evaluation in parallel with the one that failed, if it raised an
+    /// exception.  Only the evaluations to the left of the failed one are counted, as if parallel
+    /// expressions were evaluated from left to right, so that every schedule reports the same cost.
+    fn beside(self, work: u64, span: u64, extras: &Extras) -> Self {
+        match self {
+            Err::Raise {
+                value,
+                location,
+                work: w,
+                span: s,
+                extras: e,
+            } => Err::Raise {
+                value,
+                location,
+                work: work + w,
+                span: cmp::max(span, s),
+                extras: extras.clone().par(e),
+            },
+            e => e,
+        }
+    }
 }
 
-type EResult<T> = Result<Res<T>, Err>;
+pub(crate) type EResult<T> = Result<Res<T>, Err>;
 
+/// Runtime values.
+#[allow(missing_docs)]
 #[derive(Debug, Clone)]
 pub enum Value {
     Integer(i64),
+    Float(f64),
     Boolean(bool),
-    Pair {
-        lhs: Arc<Value>,
-        rhs: Arc<Value>,
-    },
+    String(String),
+    Char(char),
+    /// A tuple, whose number of components is other than one.  The empty tuple is the unit.
+    Tuple(Vec<Arc<Value>>),
     Ctor {
         ctor: Ctor,
-        inner: Arc<Value>,
+        inner: Option<Arc<Value>>,
     },
+    /// A closure.  A recursive function's own name is bound to the closure in its body.
     Lambda {
-        pattern: Rc<Pattern>,
-        expr: Rc<Expr>,
+        name: Option<Var>,
+        pattern: Arc<Pattern>,
+        expr: Arc<Expr>,
         env: Env,
     },
+    /// A closure compiled to bytecode, with the values of the variables it captures.
+    Closure {
+        func: Arc<Func>,
+        captured: Vec<Arc<Value>>,
+    },
+    Native {
+        func: Arc<dyn NativeFn>,
+        args: Vec<Arc<Value>>,
+    },
 }
 
 impl Value {
+    /// Creates a tuple of the given values.
+    pub fn tuple(values: Vec<Arc<Value>>) -> Value {
+        Value::Tuple(values)
+    }
+
+    /// Returns the unit value.
+    pub fn unit() -> Value {
+        Value::Tuple(vec![])
+    }
+
+    /// Returns the integer, if the value is one.
+    pub fn coerce_int(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// Returns the float, if the value is one.
+    pub fn coerce_float(&self) -> Option<f64> {
+        match self {
+            Value::Float(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    /// Returns the boolean, if the value is one.
     pub fn coerce_bool(&self) -> Option<bool> {
         match self {
             Value::Boolean(b) => Some(*b),
             _ => None,
         }
     }
+
+    /// Returns the string, if the value is one.
+    pub fn coerce_string(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the character, if the value is one.
+    pub fn coerce_char(&self) -> Option<char> {
+        match self {
+            Value::Char(c) => Some(*c),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the value may refer to cells other than itself.
+    fn holds_cells(&self) -> bool {
+        match self {
+            Value::Tuple(values) => !values.is_empty(),
+            Value::Ctor { inner, .. } => inner.is_some(),
+            Value::Lambda { .. } | Value::Closure { .. } => true,
+            Value::Native { args, .. } => !args.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// Returns the exception raised by an assertion that does not hold.
+    pub(crate) fn assertion_failed() -> Value {
+        Value::Ctor {
+            ctor: ASSERTION_FAILED.into(),
+            inner: None,
+        }
+    }
+
+    /// Returns the exception raised by a cost contract whose `measure` is `cost`, which exceeds
+    /// `bound`.
+    pub(crate) fn cost_contract_violated(measure: CostMeasure, cost: u64, bound: &Value) -> Value {
+        let message = format!("{} {} exceeds the bound {}", measure, cost, bound);
+        Value::Ctor {
+            ctor: COST_CONTRACT_VIOLATED.into(),
+            inner: Some(Arc::new(Value::String(message))),
+        }
+    }
+
+    /// Returns whether the value is a declared exception, which may be raised.
+    pub(crate) fn is_exception(&self) -> bool {
+        match self {
+            Value::Ctor { ctor, inner } => is_exception(ctor, inner.is_some()),
+            _ => false,
+        }
+    }
+
+    /// Returns the `len` components, if the value is a tuple of that many components.
+    pub fn coerce_tuple(&self, len: usize) -> Option<Vec<Arc<Value>>> {
+        match self {
+            Value::Tuple(values) if values.len() == len => Some(values.clone()),
+            _ => None,
+        }
+    }
+
+    /// Moves the values that the value alone holds, and that hold others in turn, into `values`.
+    /// The others are released as the value is dropped.
+    fn take_components(&mut self, values: &mut Vec<Arc<Value>>) {
+        let unique = |value: &Arc<Value>| Arc::strong_count(value) == 1 && value.holds_cells();
+        match self {
+            Value::Tuple(components)
+            | Value::Closure {
+                captured: components,
+                ..
+            }
+            | Value::Native {
+                args: components, ..
+            } if components.iter().any(unique) => {
+                values.extend(components.drain(..).filter(unique))
+            }
+            Value::Ctor { inner, .. } if inner.as_ref().is_some_and(unique) => {
+                values.extend(inner.take())
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Drops the values the value holds one at a time, rather than recursively, so that dropping a long
+/// list does not overflow the stack.
+impl Drop for Value {
+    fn drop(&mut self) {
+        let mut values = vec![];
+        self.take_components(&mut values);
+        while let Some(value) = values.pop() {
+            if let Ok(mut value) = Arc::try_unwrap(value) {
+                value.take_components(&mut values);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Char(c) => write!(f, "#{:?}", c.to_string()),
+            Value::Tuple(values) => {
+                write!(f, "(")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, ")")
+            }
+            Value::Ctor { ctor, inner: None } => write!(f, "{}", ctor),
+            Value::Ctor {
+                ctor,
+                inner: Some(inner),
+            } => match &**inner {
+                Value::Ctor { inner: Some(_), .. } => write!(f, "{} ({})", ctor, inner),
+                _ => write!(f, "{} {}", ctor, inner),
+            },
+            Value::Lambda { .. } | Value::Closure { .. } => write!(f, "<fun>"),
+            Value::Native { func, .. } => write!(f, "<native {}>", func.name()),
+        }
+    }
+}
+
+/// Returns the number of leading characters that two strings have in common.
+fn common_prefix(lhs: &str, rhs: &str) -> u64 {
+    lhs.chars()
+        .zip(rhs.chars())
+        .take_while(|(lhs, rhs)| lhs == rhs)
+        .count() as u64
 }
 
-pub type EnvPiece = HashMap<Var, Arc<Value>>;
+/// Maps `f` over the items in parallel, or sequentially while tracing so that the trace is not
+/// interleaved, and while recording DAGs since the recorder is thread-local.  Whether space is
+/// measured, and the exceptions that may be raised, are passed on to the threads.
+fn par_map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync + Send) -> Vec<R> {
+    if trace::enabled() || schedule::recording() {
+        items.iter().map(f).collect()
+    } else {
+        let (measure, exceptions) = (measuring_space(), exceptions());
+        items
+            .par_iter()
+            .map(|item| with_exceptions(&exceptions, || measure_space(measure, || f(item))))
+            .collect()
+    }
+}
+
+/// The result of an expression in tail position: either its value, or an application left to the
+/// caller so that tail calls do not grow the stack.  The cost is that of the expression apart from
+/// the pending application, whose function and argument are the result of `space`.  The values of
+/// the variables in scope, of `held` cells, are freed at the call unless its result refers to them.
+/// While observed, the exits of the expressions entered around the application are `deferred`
+/// until the call returns, innermost first.
+#[derive(Debug)]
+enum Tail {
+    Done(Res<Arc<Value>>),
+    Call {
+        func: Arc<Value>,
+        arg: Arc<Value>,
+        work: u64,
+        span: u64,
+        extras: Extras,
+        held: u64,
+        deferred: Vec<Deferred>,
+    },
+}
+
+/// An event whose report to the observer is deferred until a pending call returns, so that tail
+/// calls do not grow the stack while observed.
+#[derive(Debug)]
+enum Deferred {
+    /// The exit of an expression in tail position, whose cost apart from the call's is `work`,
+    /// `span` and `extras`.
+    Exit {
+        work: u64,
+        span: u64,
+        extras: Extras,
+    },
+    /// The return of a call of a lambda.
+    Ret,
+}
+
+impl Tail {
+    /// Adds the cost of an evaluation before, in whose scope the expression is.
+    fn add(self, work: u64, span: u64, extras: Extras) -> Self {
+        match self {
+            Tail::Done(res) => Tail::Done(Res {
+                extras: extras.scope(res.extras, &res.result),
+                result: res.result,
+                work: res.work + work,
+                span: res.span + span,
+            }),
+            Tail::Call {
+                func,
+                arg,
+                work: w,
+                span: s,
+                extras: e,
+                held,
+                deferred,
+            } => Tail::Call {
+                func,
+                arg,
+                work: w + work,
+                span: s + span,
+                held: held + extras.space().live(),
+                extras: extras.map2(e, Space::free, Counters::then, Dag::then),
+                deferred,
+            },
+        }
+    }
+}
+
+/// The variables of a frame.
+#[derive(Debug, Clone)]
+enum Vars {
+    /// Those of a pattern, after the name of the function if the frame is of an application of a
+    /// recursive function.  They are listed on the first lookup by name.
+    Pattern {
+        name: Option<Var>,
+        pattern: Arc<Pattern>,
+        listed: OnceLock<Arc<[Var]>>,
+    },
+    List(Arc<[Var]>),
+}
+
+impl Vars {
+    fn pattern(name: Option<Var>, pattern: Arc<Pattern>) -> Self {
+        Vars::Pattern {
+            name,
+            pattern,
+            listed: OnceLock::new(),
+        }
+    }
+}
+
+/// A frame of variable bindings.  The values are addressed by their indices, and the variables
+/// are kept for lookups by name.
+#[derive(Debug, Clone)]
+pub struct EnvPiece {
+    vars: Vars,
+    values: Vec<Arc<Value>>,
+}
+
+impl EnvPiece {
+    /// Returns the variables of the frame, in the order of their values.
+    fn vars(&self) -> &Arc<[Var]> {
+        match &self.vars {
+            Vars::Pattern {
+                name,
+                pattern,
+                listed,
+            } => listed.get_or_init(|| name.iter().cloned().chain(pattern.vars()).collect()),
+            Vars::List(vars) => vars,
+        }
+    }
+
+    /// Returns the index of the value bound to `var`.  A later variable shadows an earlier one.
+    pub(crate) fn index(&self, var: &Var) -> Option<usize> {
+        self.vars().iter().rposition(|v| v == var)
+    }
+}
+
+impl FromIterator<(Var, Arc<Value>)> for EnvPiece {
+    fn from_iter<I: IntoIterator<Item = (Var, Arc<Value>)>>(iter: I) -> Self {
+        let (vars, values): (Vec<_>, _) = iter.into_iter().unzip();
+        Self {
+            vars: Vars::List(vars.into()),
+            values,
+        }
+    }
+}
+
+/// An environment, i.e. a stack of frames.
 pub type Env = ArcList<EnvPiece>;
 
 impl Env {
-    fn eval_var(&self, var: &Var) -> Result<Arc<Value>, Err> {
-        for map in self.iter() {
-            if let Some(res) = map.get(var) {
-                return Ok(res.clone());
+    /// Looks up a variable by name.
+    pub(crate) fn eval_var(&self, var: &Var) -> Result<Arc<Value>, Err> {
+        for env_piece in self.iter() {
+            if let Some(index) = env_piece.index(var) {
+                return Ok(env_piece.values[index].clone());
             }
         }
 
         Err(Err::EnvNotFound { var: var.clone() })
     }
 
-    fn eval_value(&self, value: &SynValue) -> Result<Arc<Value>, Err> {
+    /// Binds `var` to `value` in a copy of the innermost frame, rather than in a frame of its own.
+    /// Top-level bindings share a single frame this way, so that looking up a global variable does
+    /// not walk a frame per declaration.
+    pub(crate) fn define(&self, var: Var, value: Arc<Value>) -> Env {
+        let (mut vars, mut values) = match self.head() {
+            Some(env_piece) => (env_piece.vars().to_vec(), env_piece.values.clone()),
+            None => (vec![], vec![]),
+        };
+        match vars.iter().rposition(|v| *v == var) {
+            Some(index) => values[index] = value,
+            None => {
+                vars.push(var);
+                values.push(value);
+            }
+        }
+        self.tail().insert(EnvPiece {
+            vars: Vars::List(vars.into()),
+            values,
+        })
+    }
+
+    /// Looks up the `index`-th value of the `depth`-th innermost frame.
+    fn lookup(&self, depth: usize, index: usize) -> Arc<Value> {
+        let env_piece = self
+            .iter()
+            .nth(depth)
+            .expect("resolved variables are bound");
+        env_piece.values[index].clone()
+    }
+
+    pub(crate) fn eval_value(&self, value: &SynValue) -> Result<Arc<Value>, Err> {
         match value {
             SynValue::Integer(inner) => Ok(Arc::new(Value::Integer(*inner))),
+            SynValue::Float(inner) => Ok(Arc::new(Value::Float(*inner))),
             SynValue::Boolean(inner) => Ok(Arc::new(Value::Boolean(*inner))),
-            SynValue::Pair { lhs, rhs } => {
-                let lhs = self.eval_value(lhs)?;
-                let rhs = self.eval_value(rhs)?;
-                Ok(Arc::new(Value::Pair { lhs, rhs }))
-            }
-            SynValue::Ctor { ctor, inner } => {
-                let inner = self.eval_value(inner)?;
-                Ok(Arc::new(Value::Ctor {
-                    ctor: ctor.clone(),
-                    inner,
-                }))
-            }
-            SynValue::Lambda { pattern, expr } => Ok(Arc::new(Value::Lambda {
+            SynValue::String(inner) => Ok(Arc::new(Value::String(inner.clone()))),
+            SynValue::Char(inner) => Ok(Arc::new(Value::Char(*inner))),
+            SynValue::Tuple(values) => Ok(Arc::new(Value::Tuple(
+                values
+                    .iter()
+                    .map(|value| self.eval_value(value))
+                    .collect::<Result<_, _>>()?,
+            ))),
+            SynValue::Ctor { ctor, inner } => Ok(Arc::new(Value::Ctor {
+                ctor: ctor.clone(),
+                inner: match inner {
+                    Some(inner) => Some(self.eval_value(inner)?),
+                    None => None,
+                },
+            })),
+            SynValue::Lambda {
+                name,
+                pattern,
+                expr,
+                captures,
+            } => Ok(Arc::new(Value::Lambda {
+                name: name.clone(),
                 pattern: pattern.clone(),
                 expr: expr.clone(),
-                env: self.clone(),
+                // A resolved lambda captures only its free variables.
+                env: match captures {
+                    Some(captures) => Env::new().insert(EnvPiece {
+                        vars: Vars::List(captures.vars.clone()),
+                        values: captures
+                            .addrs
+                            .iter()
+                            .map(|(depth, index)| self.lookup(*depth, *index))
+                            .collect(),
+                    }),
+                    None => self.clone(),
+                },
+            })),
+            SynValue::Native { func, args } => Ok(Arc::new(Value::Native {
+                func: func.clone(),
+                args: args
+                    .iter()
+                    .map(|arg| self.eval_value(arg))
+                    .collect::<Result<_, _>>()?,
             })),
         }
     }
 
+    /// Matches the value against the pattern, pushing the values bound to its variables in their
+    /// order.
     fn eval_pattern_inner(
         &self,
         pattern: &Pattern,
         value: &Arc<Value>,
-        env_piece: &mut EnvPiece,
+        values: &mut Vec<Arc<Value>>,
     ) -> Result<(), Err> {
         match (pattern, &**value) {
-            (Pattern::Var(var), _) => {
-                env_piece.insert(var.clone(), value.clone());
+            (Pattern::Wildcard, _) => Ok(()),
+            (Pattern::Var(_), _) => {
+                values.push(value.clone());
                 Ok(())
             }
-            (
-                Pattern::Pair {
-                    lhs: lhs_pattern,
-                    rhs: rhs_pattern,
-                },
-                Value::Pair {
-                    lhs: lhs_value,
-                    rhs: rhs_value,
-                },
-            ) => {
-                self.eval_pattern_inner(lhs_pattern, lhs_value, env_piece)?;
-                self.eval_pattern_inner(rhs_pattern, rhs_value, env_piece)?;
+            (Pattern::Integer(i), Value::Integer(j)) if i == j => Ok(()),
+            (Pattern::Boolean(b), Value::Boolean(c)) if b == c => Ok(()),
+            (Pattern::String(s), Value::String(t)) if s == t => Ok(()),
+            (Pattern::Char(c), Value::Char(d)) if c == d => Ok(()),
+            (Pattern::Tuple(patterns), Value::Tuple(components))
+                if patterns.len() == components.len() =>
+            {
+                for (pattern, value) in patterns.iter().zip(components.iter()) {
+                    self.eval_pattern_inner(pattern, value, values)?;
+                }
                 Ok(())
             }
             (
@@ -158,7 +1250,38 @@ impl Env {
                         ctor_value: ctor_value.clone(),
                     });
                 }
-                self.eval_pattern_inner(inner_pattern, inner_value, env_piece)?;
+                match (inner_pattern, inner_value) {
+                    (None, None) => Ok(()),
+                    (Some(inner_pattern), Some(inner_value)) => {
+                        self.eval_pattern_inner(inner_pattern, inner_value, values)
+                    }
+                    _ => Err(Err::PatternNotMatched {
+                        pattern: pattern.clone(),
+                        value: value.clone(),
+                    }),
+                }
+            }
+            (Pattern::As { inner, .. }, _) => {
+                values.push(value.clone());
+                self.eval_pattern_inner(inner, value, values)
+            }
+            (Pattern::Or { lhs, rhs }, _) => {
+                let len = values.len();
+                if self.eval_pattern_inner(lhs, value, values).is_ok() {
+                    return Ok(());
+                }
+                values.truncate(len);
+                self.eval_pattern_inner(rhs, value, values)?;
+
+                // Lays out the values bound by `rhs` in the order of the variables of `lhs`.
+                let (lhs_vars, rhs_vars) = (lhs.vars(), rhs.vars());
+                if lhs_vars != rhs_vars {
+                    let bound = values.split_off(len);
+                    values.extend(lhs_vars.iter().map(|var| {
+                        let index = rhs_vars.iter().position(|v| v == var).unwrap();
+                        bound[index].clone()
+                    }));
+                }
                 Ok(())
             }
             _ => Err(Err::PatternNotMatched {
@@ -168,201 +1291,960 @@ impl Env {
         }
     }
 
-    fn eval_pattern(&self, pattern: &Pattern, value: &Arc<Value>) -> Result<EnvPiece, Err> {
-        let mut env_piece = EnvPiece::new();
-        self.eval_pattern_inner(pattern, value, &mut env_piece)?;
-        Ok(env_piece)
+    pub(crate) fn eval_pattern(
+        &self,
+        pattern: &Arc<Pattern>,
+        value: &Arc<Value>,
+    ) -> Result<EnvPiece, Err> {
+        let mut values = vec![];
+        self.eval_pattern_inner(pattern, value, &mut values)?;
+        Ok(EnvPiece {
+            vars: Vars::pattern(None, pattern.clone()),
+            values,
+        })
     }
 
-    fn eval_unary_op(op: UnaryOp, inner: &Arc<Value>) -> Result<Value, Err> {
-        match (op, &**inner) {
+    pub(crate) fn eval_unary_op(op: UnaryOp, inner: &Value) -> Result<Value, Err> {
+        match (op, inner) {
             (UnaryOp::Not, Value::Boolean(inner)) => Ok(Value::Boolean(!inner)),
             (UnaryOp::Neg, Value::Integer(inner)) => Ok(Value::Integer(-inner)),
+            (UnaryOp::Neg, Value::Float(inner)) => Ok(Value::Float(-inner)),
+            (UnaryOp::Proj(i), Value::Tuple(values)) if 1 <= i && i <= values.len() => {
+                Ok((*values[i - 1]).clone())
+            }
             _ => Err(Err::InvalidUnaryOpArgs {
                 op,
-                inner: inner.clone(),
+                inner: Arc::new(inner.clone()),
             }),
         }
     }
 
-    fn eval_binary_op(op: BinaryOp, lhs: &Arc<Value>, rhs: &Arc<Value>) -> Result<Value, Err> {
-        match (op, &**lhs, &**rhs) {
+    /// Returns whether two values are structurally equal and the number of steps taken to compare
+    /// them, or `None` if they cannot be compared, e.g. because they contain functions.
+    fn equal(lhs: &Value, rhs: &Value) -> Option<(bool, u64)> {
+        match (lhs, rhs) {
+            (Value::Integer(lhs), Value::Integer(rhs)) => Some((lhs == rhs, 1)),
+            (Value::Float(lhs), Value::Float(rhs)) => Some((lhs == rhs, 1)),
+            (Value::Boolean(lhs), Value::Boolean(rhs)) => Some((lhs == rhs, 1)),
+            (Value::Char(lhs), Value::Char(rhs)) => Some((lhs == rhs, 1)),
+            (Value::String(lhs), Value::String(rhs)) => {
+                Some((lhs == rhs, common_prefix(lhs, rhs) + 1))
+            }
+            (Value::Tuple(lhs), Value::Tuple(rhs)) if lhs.len() == rhs.len() => {
+                let mut steps = 1;
+                for (lhs, rhs) in lhs.iter().zip(rhs.iter()) {
+                    let (equal, inner_steps) = Self::equal(lhs, rhs)?;
+                    steps += inner_steps;
+                    if !equal {
+                        return Some((false, steps));
+                    }
+                }
+                Some((true, steps))
+            }
+            (
+                Value::Ctor {
+                    ctor: lhs_ctor,
+                    inner: lhs_inner,
+                },
+                Value::Ctor {
+                    ctor: rhs_ctor,
+                    inner: rhs_inner,
+                },
+            ) => match (lhs_inner, rhs_inner) {
+                _ if lhs_ctor != rhs_ctor => Some((false, 1)),
+                (None, None) => Some((true, 1)),
+                (Some(lhs), Some(rhs)) => {
+                    let (equal, steps) = Self::equal(lhs, rhs)?;
+                    Some((equal, steps + 1))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Compares two integers, floats, booleans, characters or strings.  Returns their ordering,
+    /// which is `None` if a float is NaN, and the number of steps taken to compare them, or `None`
+    /// if they cannot be compared.
+    fn compare(lhs: &Value, rhs: &Value) -> Option<(Option<cmp::Ordering>, u64)> {
+        match (lhs, rhs) {
+            (Value::Integer(lhs), Value::Integer(rhs)) => Some((Some(lhs.cmp(rhs)), 1)),
+            (Value::Float(lhs), Value::Float(rhs)) => Some((lhs.partial_cmp(rhs), 1)),
+            (Value::Boolean(lhs), Value::Boolean(rhs)) => Some((Some(lhs.cmp(rhs)), 1)),
+            (Value::Char(lhs), Value::Char(rhs)) => Some((Some(lhs.cmp(rhs)), 1)),
+            (Value::String(lhs), Value::String(rhs)) => {
+                Some((Some(lhs.cmp(rhs)), common_prefix(lhs, rhs) + 1))
+            }
+            _ => None,
+        }
+    }
+
+    /// Applies a binary operator, returning the result and the number of steps taken.
+    pub(crate) fn eval_binary_op(
+        op: BinaryOp,
+        lhs: &Value,
+        rhs: &Value,
+    ) -> Result<(Value, u64), Err> {
+        let invalid = || Err::InvalidBinaryOpArgs {
+            op,
+            lhs: Arc::new(lhs.clone()),
+            rhs: Arc::new(rhs.clone()),
+        };
+
+        match op {
+            BinaryOp::Equal | BinaryOp::NotEqual => {
+                let (equal, steps) = Self::equal(lhs, rhs).ok_or_else(invalid)?;
+                let result = equal == matches!(op, BinaryOp::Equal);
+                return Ok((Value::Boolean(result), steps));
+            }
+            BinaryOp::Less | BinaryOp::Le | BinaryOp::Greater | BinaryOp::Ge => {
+                let (ordering, steps) = Self::compare(lhs, rhs).ok_or_else(invalid)?;
+                let result = ordering.is_some_and(|ordering| match op {
+                    BinaryOp::Less => ordering.is_lt(),
+                    BinaryOp::Le => ordering.is_le(),
+                    BinaryOp::Greater => ordering.is_gt(),
+                    _ => ordering.is_ge(),
+                });
+                return Ok((Value::Boolean(result), steps));
+            }
+            _ => {}
+        }
+
+        let result = match (op, lhs, rhs) {
             (BinaryOp::Or, Value::Boolean(lhs), Value::Boolean(rhs)) => {
-                Ok(Value::Boolean(*lhs || *rhs))
+                Value::Boolean(*lhs || *rhs)
             }
             (BinaryOp::And, Value::Boolean(lhs), Value::Boolean(rhs)) => {
-                Ok(Value::Boolean(*lhs && *rhs))
+                Value::Boolean(*lhs && *rhs)
             }
             (BinaryOp::Xor, Value::Boolean(lhs), Value::Boolean(rhs)) => {
-                Ok(Value::Boolean(*lhs ^ *rhs))
+                Value::Boolean(*lhs ^ *rhs)
             }
 
             (BinaryOp::Plus, Value::Integer(lhs), Value::Integer(rhs)) => {
-                Ok(Value::Integer(*lhs + *rhs))
+                Value::Integer(*lhs + *rhs)
             }
             (BinaryOp::Minus, Value::Integer(lhs), Value::Integer(rhs)) => {
-                Ok(Value::Integer(*lhs - *rhs))
+                Value::Integer(*lhs - *rhs)
             }
             (BinaryOp::Times, Value::Integer(lhs), Value::Integer(rhs)) => {
-                Ok(Value::Integer(*lhs * *rhs))
+                Value::Integer(*lhs * *rhs)
+            }
+            (
+                BinaryOp::Over | BinaryOp::Div | BinaryOp::Mod | BinaryOp::Quot | BinaryOp::Rem,
+                Value::Integer(_),
+                Value::Integer(0),
+            ) => return Err(Err::DivisionByZero),
+            (BinaryOp::Over | BinaryOp::Quot, Value::Integer(lhs), Value::Integer(rhs)) => {
+                Value::Integer(lhs.wrapping_div(*rhs))
+            }
+            (BinaryOp::Rem, Value::Integer(lhs), Value::Integer(rhs)) => {
+                Value::Integer(lhs.wrapping_rem(*rhs))
             }
-            (BinaryOp::Over, Value::Integer(lhs), Value::Integer(rhs)) => {
-                Ok(Value::Integer(*lhs / *rhs))
+            (BinaryOp::Div, Value::Integer(lhs), Value::Integer(rhs)) => {
+                let quot = lhs.wrapping_div(*rhs);
+                let rem = lhs.wrapping_rem(*rhs);
+                Value::Integer(if rem != 0 && (rem < 0) != (*rhs < 0) {
+                    quot - 1
+                } else {
+                    quot
+                })
             }
+            (BinaryOp::Mod, Value::Integer(lhs), Value::Integer(rhs)) => {
+                let rem = lhs.wrapping_rem(*rhs);
+                Value::Integer(if rem != 0 && (rem < 0) != (*rhs < 0) {
+                    rem + rhs
+                } else {
+                    rem
+                })
+            }
+
+            (BinaryOp::Plus, Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs + rhs),
+            (BinaryOp::Minus, Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs - rhs),
+            (BinaryOp::Times, Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs * rhs),
+            (BinaryOp::Over, Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs / rhs),
+
+            (_, _, _) => return Err(invalid()),
+        };
+        Ok((result, 1))
+    }
 
-            (BinaryOp::Equal, Value::Integer(lhs), Value::Integer(rhs)) => {
-                Ok(Value::Boolean(*lhs == *rhs))
+    /// Evaluates the first arm whose pattern matches the result of `inner` and whose guard holds,
+    /// if any, leaving a call in the tail position of its body pending.  The cost includes that of
+    /// `inner` and of the evaluated guards, as does that of an exception they raise.
+    fn eval_arms(&self, arms: &[Arm], inner: Res<Arc<Value>>) -> Result<Option<Tail>, Err> {
+        let Res {
+            result: value,
+            mut work,
+            mut span,
+            mut extras,
+        } = inner;
+        for arm in arms.iter() {
+            let env_piece = self.eval_pattern(&arm.pattern, &value);
+            if trace::enabled() {
+                trace::pattern(&arm.pattern, &value, env_piece.is_ok());
             }
-            (BinaryOp::Less, Value::Integer(lhs), Value::Integer(rhs)) => {
-                Ok(Value::Boolean(*lhs < *rhs))
+            let env_piece = match env_piece {
+                Ok(env_piece) => env_piece,
+                Err(_) => continue,
+            };
+            let env = self.clone().insert(env_piece);
+
+            if let Some(guard) = &arm.guard {
+                let guard = env
+                    .eval_expr(guard)
+                    .map_err(|e| e.after(work, span, &extras))?;
+                work += guard.work;
+                span += guard.span;
+                extras = extras.then(guard.extras);
+                let guard_result = guard
+                    .result
+                    .coerce_bool()
+                    .ok_or(Err::InvalidGuard { cond: guard.result })?;
+                if !guard_result {
+                    continue;
+                }
+            }
+
+            let extras = extras.then_steps(1);
+            let body = env
+                .eval_tail_pos(&arm.expr)
+                .map_err(|e| e.after(work + 1, span + 1, &extras))?;
+            return Ok(Some(body.add(work + 1, span + 1, extras)));
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the environment of the body of a lambda applied to an argument.
+    fn bind_arg(
+        func: &Arc<Value>,
+        name: &Option<Var>,
+        pattern: &Arc<Pattern>,
+        env: &Env,
+        arg: &Arc<Value>,
+    ) -> Result<Env, Err> {
+        let mut values = vec![];
+        if name.is_some() {
+            values.push(func.clone());
+        }
+        env.eval_pattern_inner(pattern, arg, &mut values)?;
+        Ok(env.clone().insert(EnvPiece {
+            vars: Vars::pattern(name.clone(), pattern.clone()),
+            values,
+        }))
+    }
+
+    /// Performs the pending calls, each in the tail position of the previous one's body, in a loop.
+    /// While observed, the calls of lambdas are reported as they are performed, and the events
+    /// deferred until they return are reported once the last one does.
+    fn tail_calls(mut tail: Tail) -> EResult<Arc<Value>> {
+        let mut work = 0;
+        let mut span = 0;
+        // The costs of the calls performed so far apart from their space.
+        let mut extras = Extras::default();
+        // The space of the calls performed so far, whose body is yet to be added, with the values of
+        // the variables in scope freed and kept at the calls.
+        let mut calls: Option<(Space, Space)> = None;
+        // The deferred events, innermost last.
+        let mut deferred = vec![];
+        loop {
+            match tail {
+                Tail::Done(res) => {
+                    if !deferred.is_empty() {
+                        Self::report_deferred(deferred, Ok(&res));
+                    }
+                    let (body, rest) = res.extras.take_space();
+                    let space = match calls.take() {
+                        Some((_, kept)) if res.result.holds_cells() => kept.then(body),
+                        Some((calls, _)) => calls.call(body),
+                        None => body,
+                    };
+                    return Ok(Res {
+                        result: res.result,
+                        work: work + res.work,
+                        span: span + res.span,
+                        extras: extras.then(rest).with_space(space),
+                    });
+                }
+                Tail::Call {
+                    func,
+                    arg,
+                    work: w,
+                    span: s,
+                    extras: e,
+                    held,
+                    deferred: d,
+                } => {
+                    work += w;
+                    span += s;
+                    let (space, rest) = e.take_space();
+                    extras = extras.then(rest);
+                    deferred.extend(d.into_iter().rev());
+                    let kept = space.clone().hold(held);
+                    // An exception raised by the call abandons the calls so far, with the values
+                    // kept at them.
+                    let abandoned = match &calls {
+                        Some((_, prev)) => prev.clone().then(kept.clone()),
+                        None => kept.clone(),
+                    };
+                    let next = match &*func {
+                        Value::Lambda {
+                            name,
+                            pattern,
+                            expr,
+                            env,
+                        } => {
+                            calls = Some(match calls.take() {
+                                Some((calls, prev)) => (calls.call(space), prev.then(kept)),
+                                None => (space, kept),
+                            });
+                            Self::bind_arg(&func, name, pattern, env, &arg).and_then(|env| {
+                                if trace::enabled() {
+                                    trace::call(&func, &arg);
+                                    deferred.push(Deferred::Ret);
+                                }
+                                env.eval_tail_pos(expr)
+                            })
+                        }
+                        _ => Self::apply(&func, arg).map(|res| {
+                            let (body, rest) = res.extras.take_space();
+                            let space = if res.result.holds_cells() {
+                                kept.then(body)
+                            } else {
+                                space.call(body)
+                            };
+                            Tail::Done(Res {
+                                extras: rest.with_space(space),
+                                ..res
+                            })
+                        }),
+                    };
+                    tail = match next {
+                        Ok(tail) => tail,
+                        Err(e) => {
+                            if !deferred.is_empty() {
+                                Self::report_deferred(deferred, Err(&e));
+                            }
+                            return Err(e.after(work, span, &extras.with_space(abandoned)));
+                        }
+                    };
+                }
             }
-            (BinaryOp::Le, Value::Integer(lhs), Value::Integer(rhs)) => {
-                Ok(Value::Boolean(*lhs <= *rhs))
+        }
+    }
+
+    /// Reports the deferred events to the observer, innermost first, given the result of the
+    /// innermost call's body.  The cost of an exit is that of the expression apart from the call,
+    /// which includes the expressions entered inside it, and then that of the call's body.
+    #[inline(never)]
+    fn report_deferred(deferred: Vec<Deferred>, res: Result<&Res<Arc<Value>>, &Err>) {
+        let mut body = res.cloned().map_err(Err::clone);
+        let mut last = body.clone();
+        for event in deferred.into_iter().rev() {
+            match event {
+                Deferred::Exit { work, span, extras } => {
+                    last = match &body {
+                        Ok(body) => Ok(Res {
+                            result: body.result.clone(),
+                            work: work + body.work,
+                            span: span + body.span,
+                            extras: extras.then(body.extras.clone()),
+                        }),
+                        Err(e) => Err(e.clone().after(work, span, &extras)),
+                    };
+                    trace::exit(&last);
+                }
+                Deferred::Ret => {
+                    trace::ret(&last);
+                    body = last.clone();
+                }
             }
+        }
+    }
 
-            (_, _, _) => Err(Err::InvalidBinaryOpArgs {
-                op,
-                lhs: lhs.clone(),
-                rhs: rhs.clone(),
+    /// Applies a function value, or a nullary constructor, to an argument.  The returned cost does
+    /// not include that of the application itself.
+    pub fn apply(func: &Arc<Value>, arg: Arc<Value>) -> EResult<Arc<Value>> {
+        match &**func {
+            Value::Lambda { .. } => Self::tail_calls(Tail::Call {
+                func: func.clone(),
+                arg,
+                work: 0,
+                span: 0,
+                extras: Extras::default(),
+                held: 0,
+                deferred: vec![],
+            }),
+            Value::Closure { .. } => vm::apply(func, arg),
+            Value::Ctor { ctor, inner: None } => Ok(Res {
+                result: Arc::new(Value::Ctor {
+                    ctor: ctor.clone(),
+                    inner: Some(arg),
+                }),
+                work: 0,
+                span: 0,
+                extras: Space::cells(Allocs::CTOR).into(),
+            }),
+            Value::Native { func, args } => {
+                let mut args = args.clone();
+                args.push(arg);
+
+                if args.len() < func.arity() {
+                    return Ok(Res {
+                        result: Arc::new(Value::Native {
+                            func: func.clone(),
+                            args,
+                        }),
+                        work: 0,
+                        span: 0,
+                        extras: Space::cells(Allocs::CLOSURE).into(),
+                    });
+                }
+
+                let (work, span) = func.cost(&args);
+                let result = func.call(&args)?;
+                Ok(Res {
+                    extras: Extras::new(
+                        Space::of(&result),
+                        Counters::default(),
+                        Dag::native(work, span),
+                    ),
+                    result,
+                    work,
+                    span,
+                })
+            }
+            _ => Err(Err::InvalidAppArgs {
+                inner: func.clone(),
             }),
         }
     }
 
+    /// Returns the space of building a tuple of the results of `exprs`, which are evaluated in
+    /// `space`.  The unit is not allocated.
+    fn tuple_space(exprs: &[Expr], extras: Extras) -> Extras {
+        if exprs.is_empty() {
+            extras
+        } else {
+            extras.then(Space::cells(Allocs::TUPLE).into())
+        }
+    }
+
+    /// Evaluates the count of a tick.  Like the bounds of cost contracts, it is a specification
+    /// rather than part of the program, so its cost is not counted.
+    fn tick_count(&self, count: &Expr) -> Result<u64, Err> {
+        let count = self.eval_expr(count)?.result;
+        match &*count {
+            Value::Integer(n) if *n >= 0 => Ok(*n as u64),
+            _ => Err(Err::InvalidTickCount { count }),
+        }
+    }
+
+    /// Evaluates an expression in tail position.
+    fn eval_tail_pos(&self, expr: &Expr) -> Result<Tail, Err> {
+        if !trace::enabled() {
+            return self.eval_tail(expr);
+        }
+        self.eval_tail_traced(expr)
+    }
+
+    /// Evaluates an expression in tail position, reporting it to the observer.  If a call is left
+    /// pending, the exit of the expression is deferred until the call returns.
+    #[inline(never)]
+    fn eval_tail_traced(&self, expr: &Expr) -> Result<Tail, Err> {
+        trace::enter(expr, self)?;
+        let res = match self.eval_tail(expr) {
+            Ok(Tail::Call {
+                func,
+                arg,
+                work,
+                span,
+                extras,
+                held,
+                mut deferred,
+            }) => {
+                deferred.push(Deferred::Exit {
+                    work,
+                    span,
+                    extras: extras.clone(),
+                });
+                return Ok(Tail::Call {
+                    func,
+                    arg,
+                    work,
+                    span,
+                    extras,
+                    held,
+                    deferred,
+                });
+            }
+            Ok(Tail::Done(res)) => Ok(res),
+            Err(e) => Err(e),
+        };
+        trace::exit(&res);
+        res.map(Tail::Done)
+    }
+
+    /// Evaluates an expression, leaving an application in tail position pending.
+    fn eval_tail(&self, expr: &Expr) -> Result<Tail, Err> {
+        match expr {
+            Expr::Case { inner, arms, .. } => self.eval_case(inner, arms),
+            Expr::Ite { cond, lhs, rhs } => self.eval_ite(cond, lhs, rhs),
+            Expr::App { lhs, rhs, .. } => self.eval_app(lhs, rhs),
+            Expr::Let { binds, expr } => self.eval_let(binds, expr),
+            Expr::Tick { name, count, inner } => self.eval_tick(name, count, inner),
+            _ => Ok(Tail::Done(self.eval_expr_inner(expr)?)),
+        }
+    }
+
+    /// Evaluates an expression.
     pub fn eval_expr(&self, expr: &Expr) -> EResult<Arc<Value>> {
+        if !trace::enabled() {
+            return self.eval_expr_inner(expr);
+        }
+        self.eval_expr_traced(expr)
+    }
+
+    /// Evaluates an expression, reporting it to the observer.  Kept out of `eval_expr`, which
+    /// recurses once per nested expression, so as not to enlarge its stack frame.
+    #[inline(never)]
+    fn eval_expr_traced(&self, expr: &Expr) -> EResult<Arc<Value>> {
+        trace::enter(expr, self)?;
+        let res = self.eval_expr_inner(expr);
+        trace::exit(&res);
+        res
+    }
+
+    /// Evaluates an expression without reporting it.  Compound expressions are evaluated by methods
+    /// of their own, kept out of line, so that the stack frame of each nested evaluation holds only
+    /// what its construct needs; deep recursion in a program is deep recursion here.
+    fn eval_expr_inner(&self, expr: &Expr) -> EResult<Arc<Value>> {
         match expr {
             Expr::Var(var) => Ok(Res {
                 result: self.eval_var(var)?,
                 work: 1,
                 span: 1,
+                extras: Extras::steps(1),
+            }),
+            Expr::Resolved { depth, index, .. } => Ok(Res {
+                result: self.lookup(*depth, *index),
+                work: 1,
+                span: 1,
+                extras: Extras::steps(1),
             }),
             Expr::Value(value) => Ok(Res {
                 result: self.eval_value(value)?,
                 work: 1,
                 span: 1,
+                extras: Extras::new(Space::of_literal(value), Counters::default(), Dag::steps(1)),
             }),
-            Expr::UnaryOp { op, inner } => {
-                let inner = self.eval_expr(inner)?;
-                let res = Self::eval_unary_op(*op, &inner.result)?;
+            Expr::UnaryOp { op, inner } => self.eval_unary(*op, inner),
+            Expr::BinaryOp { op, lhs, rhs } => self.eval_binary(*op, lhs, rhs),
+            Expr::SeqTuple(exprs) => self.eval_seq_tuple(exprs),
+            Expr::ParTuple(exprs) => self.eval_par_tuple(exprs),
+            Expr::Case { .. }
+            | Expr::Ite { .. }
+            | Expr::App { .. }
+            | Expr::Let { .. }
+            | Expr::Tick { .. } => Self::tail_calls(self.eval_tail(expr)?),
+            Expr::Raise { inner, span } => self.eval_raise(inner, *span),
+            Expr::Assert { inner, span } => self.eval_assert(inner, *span),
+            Expr::WithCost {
+                bounds,
+                inner,
+                span: location,
+            } => self.eval_with_cost(bounds, inner, *location),
+            Expr::Handle { inner, arms } => self.eval_handle(inner, arms),
+            Expr::AndAlso { lhs, rhs } => self.eval_short_circuit(false, lhs, rhs),
+            Expr::OrElse { lhs, rhs } => self.eval_short_circuit(true, lhs, rhs),
+            Expr::ParLet { binds, expr } => self.eval_par_let(binds, expr),
+        }
+    }
 
-                Ok(Res {
-                    result: Arc::new(res),
-                    work: inner.work + 1,
-                    span: inner.span + 1,
-                })
-            }
-            Expr::BinaryOp { op, lhs, rhs } => {
-                let lhs = self.eval_expr(lhs)?;
-                let rhs = self.eval_expr(rhs)?;
-                let res = Self::eval_binary_op(*op, &lhs.result, &rhs.result)?;
+    /// Evaluates a case, leaving an application in tail position of the matching arm pending.
+    #[inline(never)]
+    fn eval_case(&self, inner: &Expr, arms: &[Arm]) -> Result<Tail, Err> {
+        let inner = self.eval_expr(inner)?;
+        let value = inner.result.clone();
+        match self.eval_arms(arms, inner)? {
+            Some(tail) => Ok(tail),
+            None => Err(Err::CaseNoMatch {
+                inner: value,
+                patterns: arms.iter().map(|arm| arm.pattern.clone()).collect(),
+            }),
+        }
+    }
 
-                Ok(Res {
-                    result: Arc::new(res),
-                    work: lhs.work + rhs.work + 1,
-                    span: cmp::max(lhs.span, rhs.span) + 1,
-                })
-            }
-            Expr::SeqPair { lhs, rhs } => {
-                let lhs = self.eval_expr(lhs)?;
-                let rhs = self.eval_expr(rhs)?;
+    /// Evaluates a conditional, leaving an application in tail position of the branch taken pending.
+    #[inline(never)]
+    fn eval_ite(&self, cond: &Expr, lhs: &Expr, rhs: &Expr) -> Result<Tail, Err> {
+        let cond = self.eval_expr(cond)?;
+        let cond_result = cond
+            .result
+            .coerce_bool()
+            .ok_or(Err::InvalidIteCond { cond: cond.result })?;
 
-                Ok(Res {
-                    result: Arc::new(Value::Pair {
-                        lhs: lhs.result,
-                        rhs: rhs.result,
-                    }),
-                    work: lhs.work + rhs.work + 1,
-                    span: lhs.work + rhs.work + 1,
-                })
-            }
-            Expr::ParPair { lhs, rhs } => {
-                let lhs = self.eval_expr(lhs)?;
-                let rhs = self.eval_expr(rhs)?;
+        let body = if cond_result { lhs } else { rhs };
+        let (work, span) = (cond.work + 1, cond.span + 1);
+        let extras = cond.extras.then_steps(1);
+        let body = self
+            .eval_tail_pos(body)
+            .map_err(|e| e.after(work, span, &extras))?;
+        Ok(body.add(work, span, extras))
+    }
 
-                Ok(Res {
-                    result: Arc::new(Value::Pair {
-                        lhs: lhs.result,
-                        rhs: rhs.result,
-                    }),
-                    work: lhs.work + rhs.work + 1,
-                    span: cmp::max(lhs.work, rhs.work) + 1,
-                })
-            }
-            Expr::Case { inner, arms } => {
-                let inner = self.eval_expr(inner)?;
+    /// Evaluates the function and the argument of an application, leaving the call pending.
+    #[inline(never)]
+    fn eval_app(&self, lhs: &Expr, rhs: &Expr) -> Result<Tail, Err> {
+        let lhs = self.eval_expr(lhs)?;
+        let rhs = self
+            .eval_expr(rhs)
+            .map_err(|e| e.beside(lhs.work, lhs.span, &lhs.extras))?;
 
-                for (pattern, expr) in arms.iter() {
-                    if let Ok(env_piece) = self.eval_pattern(pattern, &inner.result) {
-                        let env = self.clone().insert(env_piece);
-                        let inner = env.eval_expr(expr)?;
+        Ok(Tail::Call {
+            func: lhs.result,
+            arg: rhs.result,
+            work: lhs.work + rhs.work + 1,
+            span: cmp::max(lhs.span, rhs.span) + 1,
+            extras: lhs
+                .extras
+                .map2(rhs.extras, Space::then, Counters::par, Dag::par)
+                .then_steps(1),
+            held: 0,
+            deferred: vec![],
+        })
+    }
 
-                        return Ok(Res {
-                            result: inner.result,
-                            work: inner.work + 1,
-                            span: inner.span + 1,
-                        });
-                    }
-                }
+    /// Evaluates a let, leaving an application in tail position of its body pending.
+    #[inline(never)]
+    fn eval_let(&self, binds: &[Bind], expr: &Expr) -> Result<Tail, Err> {
+        let mut env = self.clone();
+        let mut work = 0;
+        let mut span = 0;
+        let mut extras = Extras::default();
+        for bind in binds.iter() {
+            let res = env
+                .eval_expr(&bind.expr)
+                .map_err(|e| e.after(work, span, &extras))?;
+            let env_piece = env.eval_pattern(&bind.pattern, &res.result)?;
+            env = env.insert(env_piece);
+            work += res.work;
+            span += res.span;
+            extras = extras.then(res.extras);
+        }
 
-                Err(Err::CaseNoMatch {
-                    inner: inner.result,
-                    patterns: arms.iter().map(|(p, _)| p.clone()).collect(),
-                })
-            }
-            Expr::Ite { cond, lhs, rhs } => {
-                let cond = self.eval_expr(cond)?;
-                let cond_result = cond
-                    .result
-                    .coerce_bool()
-                    .ok_or(Err::InvalidIteCond { cond: cond.result })?;
+        let extras = extras.then_steps(1);
+        let body = env
+            .eval_tail_pos(expr)
+            .map_err(|e| e.after(work + 1, span + 1, &extras))?;
+        Ok(body.add(work + 1, span + 1, extras))
+    }
 
-                let body = if cond_result { lhs } else { rhs };
-                let body = self.eval_expr(body)?;
+    /// Evaluates a tick, leaving an application in tail position of `inner` pending.
+    #[inline(never)]
+    fn eval_tick(&self, name: &str, count: &Expr, inner: &Expr) -> Result<Tail, Err> {
+        let count = self.tick_count(count)?;
+        let extras = Extras::from(Counters::default().tick(name, count));
+        let body = self
+            .eval_tail_pos(inner)
+            .map_err(|e| e.after(0, 0, &extras))?;
+        Ok(body.add(0, 0, extras))
+    }
 
-                Ok(Res {
-                    result: body.result,
-                    work: cond.work + body.work + 1,
-                    span: cond.span + body.span + 1,
-                })
-            }
-            Expr::App { lhs, rhs } => {
-                let lhs = self.eval_expr(lhs)?;
-                let rhs = self.eval_expr(rhs)?;
+    /// Evaluates a unary operation.
+    #[inline(never)]
+    fn eval_unary(&self, op: UnaryOp, inner: &Expr) -> EResult<Arc<Value>> {
+        let inner = self.eval_expr(inner)?;
+        let res = Self::eval_unary_op(op, &inner.result)?;
 
-                let (lhs_pattern, lhs_expr, lhs_env) = match &*lhs.result {
-                    Value::Lambda { pattern, expr, env } => (pattern, expr, env),
-                    _ => Err(Err::InvalidAppArgs { inner: lhs.result })?,
-                };
+        // A projection keeps the tuple if the component may refer to it.
+        let extras = if res.holds_cells() {
+            inner.extras.close()
+        } else {
+            inner.extras.discard()
+        };
+
+        Ok(Res {
+            result: Arc::new(res),
+            work: inner.work + 1,
+            span: inner.span + 1,
+            extras: extras.then_steps(1),
+        })
+    }
 
-                let env_piece = self.eval_pattern(lhs_pattern, &rhs.result)?;
-                let env = lhs_env.clone().insert(env_piece);
-                let app = env.eval_expr(lhs_expr)?;
+    /// Evaluates a binary operation, whose operands are evaluated in parallel.
+    #[inline(never)]
+    fn eval_binary(&self, op: BinaryOp, lhs: &Expr, rhs: &Expr) -> EResult<Arc<Value>> {
+        let lhs = self.eval_expr(lhs)?;
+        let rhs = self
+            .eval_expr(rhs)
+            .map_err(|e| e.beside(lhs.work, lhs.span, &lhs.extras))?;
+        let (res, steps) = Self::eval_binary_op(op, &lhs.result, &rhs.result)?;
 
-                Ok(Res {
-                    result: app.result,
-                    work: lhs.work + rhs.work + app.work + 1,
-                    span: cmp::max(lhs.span, rhs.span) + app.span + 1,
-                })
+        Ok(Res {
+            result: Arc::new(res),
+            work: lhs.work + rhs.work + steps,
+            span: cmp::max(lhs.span, rhs.span) + steps,
+            extras: lhs
+                .extras
+                .map2(rhs.extras, Space::then, Counters::par, Dag::par)
+                .discard()
+                .then_steps(steps),
+        })
+    }
+
+    /// Evaluates the components of a tuple one after another.
+    #[inline(never)]
+    fn eval_seq_tuple(&self, exprs: &[Expr]) -> EResult<Arc<Value>> {
+        let mut values = Vec::with_capacity(exprs.len());
+        let mut work = 0;
+        let mut span = 0;
+        let mut extras = Extras::default();
+        for expr in exprs.iter() {
+            let res = self
+                .eval_expr(expr)
+                .map_err(|e| e.after(work, span, &extras))?;
+            values.push(res.result);
+            work += res.work;
+            span += res.span;
+            extras = extras.then(res.extras);
+        }
+
+        Ok(Res {
+            result: Arc::new(Value::Tuple(values)),
+            work: work + 1,
+            span: span + 1,
+            extras: Self::tuple_space(exprs, extras).then_steps(1),
+        })
+    }
+
+    /// Evaluates the components of a tuple in parallel.
+    #[inline(never)]
+    fn eval_par_tuple(&self, exprs: &[Expr]) -> EResult<Arc<Value>> {
+        let results = par_map(exprs, |expr| self.eval_expr(expr));
+
+        // Reports the error of the first failed component, regardless of the schedule, so
+        // that the leftmost of several raised exceptions propagates with the cost of the
+        // components to its left.
+        let mut values = Vec::with_capacity(exprs.len());
+        let mut work = 0;
+        let mut span = 0;
+        let mut extras = Extras::default();
+        for res in results {
+            let res = res.map_err(|e| e.beside(work, span, &extras))?;
+            values.push(res.result);
+            work += res.work;
+            span = cmp::max(span, res.span);
+            extras = extras.par(res.extras);
+        }
+
+        Ok(Res {
+            result: Arc::new(Value::Tuple(values)),
+            work: work + 1,
+            span: span + 1,
+            extras: Self::tuple_space(exprs, extras).then_steps(1),
+        })
+    }
+
+    /// Evaluates a raise at `location`.
+    #[inline(never)]
+    fn eval_raise(&self, inner: &Expr, location: Span) -> EResult<Arc<Value>> {
+        let inner = self.eval_expr(inner)?;
+        if !inner.result.is_exception() {
+            return Err(Err::NotAnException { location });
+        }
+        Err(Err::Raise {
+            value: inner.result,
+            location,
+            work: inner.work + 1,
+            span: inner.span + 1,
+            extras: inner.extras.close().then_steps(1),
+        })
+    }
+
+    /// Evaluates an assertion at `location`.
+    #[inline(never)]
+    fn eval_assert(&self, inner: &Expr, location: Span) -> EResult<Arc<Value>> {
+        let inner = self.eval_expr(inner)?;
+        let cond = inner.result.coerce_bool().ok_or(Err::InvalidAssertion {
+            cond: inner.result.clone(),
+        })?;
+        if !cond {
+            return Err(Err::Raise {
+                value: Arc::new(Value::assertion_failed()),
+                location,
+                work: inner.work + 1,
+                span: inner.span + 1,
+                extras: inner.extras.close().then_steps(1),
+            });
+        }
+
+        Ok(Res {
+            result: Arc::new(Value::unit()),
+            work: inner.work + 1,
+            span: inner.span + 1,
+            extras: inner.extras.discard().then_steps(1),
+        })
+    }
+
+    /// Evaluates `inner`, checking that its cost is within the bounds of the contract at `location`.
+    #[inline(never)]
+    fn eval_with_cost(
+        &self,
+        bounds: &[(CostMeasure, Expr)],
+        inner: &Expr,
+        location: Span,
+    ) -> EResult<Arc<Value>> {
+        // The bounds are specifications rather than part of the program, so neither their
+        // cost nor that of checking them is counted.
+        let bounds = bounds
+            .iter()
+            .map(|(measure, bound)| Ok((*measure, self.eval_expr(bound)?.result)))
+            .collect::<Result<Vec<_>, Err>>()?;
+        let inner = self.eval_expr(inner)?;
+
+        for (measure, bound) in bounds {
+            let cost = match measure {
+                CostMeasure::Work => inner.work,
+                CostMeasure::Span => inner.span,
+            };
+            let within = match &*bound {
+                Value::Integer(bound) => (cost as i128) <= (*bound as i128),
+                Value::Float(bound) => (cost as f64) <= *bound,
+                _ => return Err(Err::InvalidCostBound { bound }),
+            };
+            if !within {
+                return Err(Err::Raise {
+                    value: Arc::new(Value::cost_contract_violated(measure, cost, &bound)),
+                    location,
+                    work: inner.work,
+                    span: inner.span,
+                    extras: inner.extras.close(),
+                });
             }
-            Expr::Let { binds, expr } => {
-                let mut env_piece = HashMap::new();
-                let mut work = 0;
-                let mut span = 0;
-                for bind in binds.iter() {
-                    let res = self.eval_expr(&bind.expr)?;
-                    env_piece.insert(bind.var.clone(), res.result);
-                    work += res.work;
-                    span += res.span;
-                }
+        }
 
-                let env = self.clone().insert(env_piece);
-                let res = env.eval_expr(expr)?;
+        Ok(Res {
+            extras: inner.extras.close(),
+            ..inner
+        })
+    }
 
-                Ok(Res {
-                    result: res.result,
-                    work: work + res.work + 1,
-                    span: span + res.span + 1,
-                })
+    /// Evaluates a conjunction or a disjunction, which `decisive` decides: `true` decides a
+    /// disjunction, and `false` a conjunction.
+    #[inline(never)]
+    fn eval_short_circuit(&self, decisive: bool, lhs: &Expr, rhs: &Expr) -> EResult<Arc<Value>> {
+        let lhs = self.eval_expr(lhs)?;
+        let lhs_result = lhs
+            .result
+            .coerce_bool()
+            .ok_or(Err::InvalidShortCircuitArg {
+                arg: lhs.result.clone(),
+            })?;
+        if lhs_result == decisive {
+            return Ok(Res {
+                result: lhs.result,
+                work: lhs.work + 1,
+                span: lhs.span + 1,
+                extras: lhs.extras.close().then_steps(1),
+            });
+        }
+
+        let (work, span, extras) = (lhs.work + 1, lhs.span + 1, lhs.extras);
+        let rhs = self
+            .eval_expr(rhs)
+            .map_err(|e| e.after(work, span, &extras.clone().then_steps(1)))?;
+        rhs.result
+            .coerce_bool()
+            .ok_or(Err::InvalidShortCircuitArg {
+                arg: rhs.result.clone(),
+            })?;
+
+        Ok(Res {
+            extras: extras.scope(rhs.extras, &rhs.result).then_steps(1),
+            result: rhs.result,
+            work: work + rhs.work,
+            span: span + rhs.span,
+        })
+    }
+
+    /// Evaluates a let whose bindings are evaluated in parallel.
+    #[inline(never)]
+    fn eval_par_let(&self, binds: &[Bind], expr: &Expr) -> EResult<Arc<Value>> {
+        let results = par_map(binds, |bind| {
+            let res = self.eval_expr(&bind.expr)?;
+            let env_piece = self.eval_pattern(&bind.pattern, &res.result)?;
+            Ok(Res {
+                result: env_piece,
+                work: res.work,
+                span: res.span,
+                extras: res.extras,
+            })
+        });
+
+        // Reports the error of the first failed binding, regardless of the schedule, so that
+        // the leftmost of several raised exceptions propagates with the cost of the bindings
+        // to its left.
+        let mut env = self.clone();
+        let mut work = 0;
+        let mut span = 0;
+        let mut extras = Extras::default();
+        for res in results {
+            let res = res.map_err(|e: Err| e.beside(work, span, &extras))?;
+            env = env.insert(res.result);
+            work += res.work;
+            span = cmp::max(span, res.span);
+            extras = extras.par(res.extras);
+        }
+
+        let res = env
+            .eval_expr(expr)
+            .map_err(|e| e.after(work + 1, span + 1, &extras.clone().then_steps(1)))?;
+
+        Ok(Res {
+            extras: extras.scope(res.extras, &res.result).then_steps(1),
+            result: res.result,
+            work: work + res.work + 1,
+            span: span + res.span + 1,
+        })
+    }
+
+    /// Evaluates `inner`, handling the exceptions it raises with the arms.
+    #[inline(never)]
+    fn eval_handle(&self, inner: &Expr, arms: &[Arm]) -> EResult<Arc<Value>> {
+        match self.eval_expr(inner) {
+            Ok(inner) => Ok(Res {
+                result: inner.result,
+                work: inner.work + 1,
+                span: inner.span + 1,
+                extras: inner.extras.close().then_steps(1),
+            }),
+            Err(Err::Raise {
+                value,
+                location,
+                work,
+                span,
+                extras,
+            }) => {
+                let raised = Res {
+                    result: value.clone(),
+                    work,
+                    span,
+                    extras: extras.clone(),
+                };
+                match self.eval_arms(arms, raised)? {
+                    Some(tail) => Self::tail_calls(tail),
+                    None => Err(Err::Raise {
+                        value,
+                        location,
+                        work,
+                        span,
+                        extras,
+                    }),
+                }
             }
+            Err(e) => Err(e),
         }
     }
 }
@@ -0,0 +1,1404 @@
+//! Substitution-based small-step evaluator.
+//!
+//! The book defines the semantics of SPARC by substitution, which this evaluator follows as a
+//! cross-check of `Env::eval_expr`.  A program is reduced one redex at a time, and the values
+//! bound by patterns are substituted for the variables, so that each intermediate program can be
+//! printed.  The program is kept decomposed into the expression under reduction and its evaluation
+//! context, a stack of frames that record the values of the evaluated subexpressions together with
+//! their cost.  The cost is accounted by the same rules as `Env::eval_expr`, so both evaluators
+//! agree on values, work/span and ticks.  Parallel subexpressions are reduced from left to right.
+//!
+//! Only closed values are ever substituted, so substitution cannot capture variables: it suffices
+//! to stop at the binders that shadow the substituted ones.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Arc;
+
+use crate::semantics::{self, Counters, EResult, Env, Err, Res, Value};
+use crate::syntax::{
+    Arm, BinaryOp, Bind, CostMeasure, Expr, Pattern, Span, UnaryOp, Value as SynValue, Var,
+};
+
+/// A substitution of closed values for variables.
+type Subst = HashMap<Var, SynValue>;
+
+/// Returns the substitution without the given variables, which are bound in its scope.
+fn shadow(subst: &Subst, vars: impl IntoIterator<Item = Var>) -> Subst {
+    let mut subst = subst.clone();
+    for var in vars {
+        subst.remove(&var);
+    }
+    subst
+}
+
+fn subst_value(value: &SynValue, subst: &Subst) -> SynValue {
+    match value {
+        SynValue::Tuple(values) => {
+            SynValue::Tuple(values.iter().map(|v| subst_value(v, subst)).collect())
+        }
+        SynValue::Lambda {
+            name,
+            pattern,
+            expr,
+            ..
+        } => {
+            let subst = shadow(subst, name.iter().cloned().chain(pattern.vars()));
+            SynValue::Lambda {
+                name: name.clone(),
+                pattern: pattern.clone(),
+                expr: Arc::new(subst_expr(expr, &subst)),
+                captures: None,
+            }
+        }
+        // Applied constructors and native functions arise only from evaluation, so they are
+        // closed.
+        _ => value.clone(),
+    }
+}
+
+fn subst_arms(arms: &[Arm], subst: &Subst) -> Vec<Arm> {
+    arms.iter()
+        .map(|arm| {
+            let subst = shadow(subst, arm.pattern.vars());
+            Arm {
+                pattern: arm.pattern.clone(),
+                guard: arm
+                    .guard
+                    .as_ref()
+                    .map(|guard| Box::new(subst_expr(guard, &subst))),
+                expr: Box::new(subst_expr(&arm.expr, &subst)),
+            }
+        })
+        .collect()
+}
+
+/// Substitutes the values for their variables in the expression.
+fn subst_expr(expr: &Expr, subst: &Subst) -> Expr {
+    if subst.is_empty() {
+        return expr.clone();
+    }
+
+    let boxed = |expr: &Expr| Box::new(subst_expr(expr, subst));
+    match expr {
+        // Substitutes resolved variables by name, leaving them unresolved.
+        Expr::Var(var) | Expr::Resolved { var, .. } => match subst.get(var) {
+            Some(value) => Expr::Value(Box::new(value.clone())),
+            None => Expr::Var(var.clone()),
+        },
+        Expr::Value(value) => Expr::Value(Box::new(subst_value(value, subst))),
+        Expr::UnaryOp { op, inner } => Expr::UnaryOp {
+            op: *op,
+            inner: boxed(inner),
+        },
+        Expr::BinaryOp { op, lhs, rhs } => Expr::BinaryOp {
+            op: *op,
+            lhs: boxed(lhs),
+            rhs: boxed(rhs),
+        },
+        Expr::SeqTuple(exprs) => {
+            Expr::SeqTuple(exprs.iter().map(|e| subst_expr(e, subst)).collect())
+        }
+        Expr::ParTuple(exprs) => {
+            Expr::ParTuple(exprs.iter().map(|e| subst_expr(e, subst)).collect())
+        }
+        Expr::Case { inner, arms, span } => Expr::Case {
+            inner: boxed(inner),
+            arms: subst_arms(arms, subst),
+            span: *span,
+        },
+        Expr::Ite { cond, lhs, rhs } => Expr::Ite {
+            cond: boxed(cond),
+            lhs: boxed(lhs),
+            rhs: boxed(rhs),
+        },
+        Expr::AndAlso { lhs, rhs } => Expr::AndAlso {
+            lhs: boxed(lhs),
+            rhs: boxed(rhs),
+        },
+        Expr::OrElse { lhs, rhs } => Expr::OrElse {
+            lhs: boxed(lhs),
+            rhs: boxed(rhs),
+        },
+        Expr::Raise { inner, span } => Expr::Raise {
+            inner: boxed(inner),
+            span: *span,
+        },
+        Expr::Handle { inner, arms } => Expr::Handle {
+            inner: boxed(inner),
+            arms: subst_arms(arms, subst),
+        },
+        Expr::Assert { inner, span } => Expr::Assert {
+            inner: boxed(inner),
+            span: *span,
+        },
+        Expr::WithCost {
+            bounds,
+            inner,
+            span,
+        } => Expr::WithCost {
+            bounds: bounds
+                .iter()
+                .map(|(measure, bound)| (*measure, subst_expr(bound, subst)))
+                .collect(),
+            inner: boxed(inner),
+            span: *span,
+        },
+        Expr::Tick { name, count, inner } => Expr::Tick {
+            name: name.clone(),
+            count: boxed(count),
+            inner: boxed(inner),
+        },
+        Expr::App { lhs, rhs, span } => Expr::App {
+            lhs: boxed(lhs),
+            rhs: boxed(rhs),
+            span: *span,
+        },
+        Expr::Let { binds, expr } => {
+            let mut subst = subst.clone();
+            let mut result = Vec::with_capacity(binds.len());
+            for bind in binds {
+                result.push(Bind {
+                    pattern: bind.pattern.clone(),
+                    expr: Box::new(subst_expr(&bind.expr, &subst)),
+                });
+                subst = shadow(&subst, bind.pattern.vars());
+            }
+            Expr::Let {
+                binds: result,
+                expr: Box::new(subst_expr(expr, &subst)),
+            }
+        }
+        Expr::ParLet { binds, expr } => {
+            let inner_subst = shadow(subst, binds.iter().flat_map(|bind| bind.pattern.vars()));
+            Expr::ParLet {
+                binds: binds
+                    .iter()
+                    .map(|bind| Bind {
+                        pattern: bind.pattern.clone(),
+                        expr: boxed(&bind.expr),
+                    })
+                    .collect(),
+                expr: Box::new(subst_expr(expr, &inner_subst)),
+            }
+        }
+    }
+}
+
+/// Converts a runtime value to a closed value in the syntax, substituting the environments of
+/// closures into their bodies.
+fn quote(value: &Value) -> SynValue {
+    match value {
+        Value::Integer(i) => SynValue::Integer(*i),
+        Value::Float(x) => SynValue::Float(*x),
+        Value::Boolean(b) => SynValue::Boolean(*b),
+        Value::String(s) => SynValue::String(s.clone()),
+        Value::Char(c) => SynValue::Char(*c),
+        Value::Tuple(values) => SynValue::Tuple(values.iter().map(|v| quote(v)).collect()),
+        Value::Ctor { ctor, inner } => SynValue::Ctor {
+            ctor: ctor.clone(),
+            inner: inner.as_ref().map(|inner| Arc::new(quote(inner))),
+        },
+        Value::Lambda {
+            name,
+            pattern,
+            expr,
+            env,
+        } => {
+            let bound = name
+                .iter()
+                .cloned()
+                .chain(pattern.vars())
+                .collect::<Vec<_>>();
+            let subst = free_subst(expr, env, &bound);
+            SynValue::Lambda {
+                name: name.clone(),
+                pattern: pattern.clone(),
+                expr: Arc::new(subst_expr(expr, &subst)),
+                captures: None,
+            }
+        }
+        Value::Closure { func, captured } => {
+            let subst = func
+                .captures
+                .iter()
+                .zip(captured.iter())
+                .map(|(var, value)| (var.clone(), quote(value)))
+                .collect();
+            SynValue::Lambda {
+                name: func.name.clone(),
+                pattern: func.pattern.clone(),
+                expr: Arc::new(subst_expr(&func.expr, &subst)),
+                captures: None,
+            }
+        }
+        Value::Native { func, args } => SynValue::Native {
+            func: func.clone(),
+            args: args.iter().map(|arg| quote(arg)).collect(),
+        },
+    }
+}
+
+/// Returns the substitution of the values bound in `env` for the free variables of `expr`, except
+/// those in `bound`.  Unbound variables are left free, and reported when they are evaluated.
+fn free_subst(expr: &Expr, env: &Env, bound: &[Var]) -> Subst {
+    expr.free_vars()
+        .into_iter()
+        .filter(|var| !bound.contains(var))
+        .filter_map(|var| {
+            let value = env.eval_var(&var).ok()?;
+            Some((var, quote(&value)))
+        })
+        .collect()
+}
+
+/// Converts a closed value in the syntax to a runtime value.
+fn reify(value: &SynValue) -> Result<Arc<Value>, Err> {
+    Env::new().eval_value(value)
+}
+
+/// Matches the value against the pattern, returning the substitution for the pattern's variables.
+/// The value is matched as it is rather than reified, so that the substitution shares its parts.
+fn bind(pattern: &Pattern, value: &SynValue) -> Result<Subst, Err> {
+    let mut subst = Subst::new();
+    bind_inner(pattern, value, &mut subst)?;
+    Ok(subst)
+}
+
+fn bind_inner(pattern: &Pattern, value: &SynValue, subst: &mut Subst) -> Result<(), Err> {
+    match (pattern, value) {
+        (Pattern::Wildcard, _) => {}
+        (Pattern::Var(var), _) => {
+            subst.insert(var.clone(), value.clone());
+        }
+        (Pattern::Integer(i), SynValue::Integer(j)) if i == j => {}
+        (Pattern::Boolean(b), SynValue::Boolean(c)) if b == c => {}
+        (Pattern::String(s), SynValue::String(t)) if s == t => {}
+        (Pattern::Char(c), SynValue::Char(d)) if c == d => {}
+        (Pattern::Tuple(patterns), SynValue::Tuple(values)) if patterns.len() == values.len() => {
+            for (pattern, value) in patterns.iter().zip(values) {
+                bind_inner(pattern, value, subst)?;
+            }
+        }
+        (
+            Pattern::Ctor {
+                ctor: ctor_pattern,
+                inner: inner_pattern,
+            },
+            SynValue::Ctor {
+                ctor: ctor_value,
+                inner: inner_value,
+            },
+        ) => {
+            if ctor_pattern != ctor_value {
+                return Err(Err::CtorNotMatched {
+                    ctor_pattern: ctor_pattern.clone(),
+                    ctor_value: ctor_value.clone(),
+                });
+            }
+            match (inner_pattern, inner_value) {
+                (None, None) => {}
+                (Some(inner_pattern), Some(inner_value)) => {
+                    bind_inner(inner_pattern, inner_value, subst)?
+                }
+                _ => {
+                    return Err(Err::PatternNotMatched {
+                        pattern: pattern.clone(),
+                        value: reify(value)?,
+                    })
+                }
+            }
+        }
+        (Pattern::As { var, inner }, _) => {
+            subst.insert(var.clone(), value.clone());
+            bind_inner(inner, value, subst)?;
+        }
+        (Pattern::Or { lhs, rhs }, _) => match bind(lhs, value) {
+            Ok(bound) => subst.extend(bound),
+            Result::Err(_) => bind_inner(rhs, value, subst)?,
+        },
+        _ => {
+            return Err(Err::PatternNotMatched {
+                pattern: pattern.clone(),
+                value: reify(value)?,
+            })
+        }
+    }
+    Ok(())
+}
+
+fn coerce_bool(value: &SynValue) -> Option<bool> {
+    match value {
+        SynValue::Boolean(b) => Some(*b),
+        _ => None,
+    }
+}
+
+/// Returns whether the value is a declared exception, which may be raised.
+fn is_exception(value: &SynValue) -> bool {
+    match value {
+        SynValue::Ctor { ctor, inner } => semantics::is_exception(ctor, inner.is_some()),
+        _ => false,
+    }
+}
+
+/// A value together with the cost of evaluating it.
+#[derive(Debug)]
+struct Evaluated {
+    result: SynValue,
+    work: u64,
+    span: u64,
+    counters: Counters,
+}
+
+/// Where the arms being matched come from.
+#[derive(Debug)]
+enum ArmsKind {
+    Case {
+        patterns: Vec<Arc<Pattern>>,
+    },
+    /// A handler of an exception raised at `location`, with the given cost.
+    Handle {
+        location: Span,
+        work: u64,
+        span: u64,
+        counters: Counters,
+    },
+}
+
+/// The remaining arms of a case or handler to be matched against a value, with the cost so far.
+#[derive(Debug)]
+struct Arms {
+    value: SynValue,
+    arms: Vec<Arm>,
+    work: u64,
+    span: u64,
+    counters: Counters,
+    kind: ArmsKind,
+}
+
+impl Arms {
+    /// Returns the expression that matches the value against the arms.
+    fn expr(&self) -> Expr {
+        let value = Box::new(Expr::Value(Box::new(self.value.clone())));
+        match &self.kind {
+            ArmsKind::Case { .. } => Expr::Case {
+                inner: value,
+                arms: self.arms.clone(),
+                span: Span { start: 0, end: 0 },
+            },
+            ArmsKind::Handle { location, .. } => Expr::Handle {
+                inner: Box::new(Expr::Raise {
+                    inner: value,
+                    span: *location,
+                }),
+                arms: self.arms.clone(),
+            },
+        }
+    }
+}
+
+/// What is being reduced.
+#[derive(Debug)]
+enum Control {
+    /// An expression to be evaluated.
+    Eval(Expr),
+    /// The value of the expression, to be returned to the innermost frame.
+    Return(Evaluated),
+    /// A value to be matched against arms.
+    Match(Arms),
+    /// An exception propagating to the innermost handler, with the cost of the evaluation it
+    /// abandons so far.
+    Unwind {
+        value: SynValue,
+        location: Span,
+        work: u64,
+        span: u64,
+        counters: Counters,
+    },
+}
+
+/// A frame of an evaluation context, i.e. an expression with a hole.
+#[derive(Debug)]
+enum Frame {
+    UnaryOp(UnaryOp),
+    BinaryOpLhs {
+        op: BinaryOp,
+        rhs: Expr,
+    },
+    BinaryOpRhs {
+        op: BinaryOp,
+        lhs: Evaluated,
+    },
+    /// The hole is the component after those `done`.
+    Tuple {
+        par: bool,
+        exprs: Vec<Expr>,
+        done: Vec<Evaluated>,
+    },
+    Case {
+        arms: Vec<Arm>,
+        span: Span,
+    },
+    /// The hole is the guard of the arm before `arms`, whose body is `body`.
+    Guard {
+        arms: Arms,
+        body: Expr,
+    },
+    Ite {
+        lhs: Expr,
+        rhs: Expr,
+    },
+    ShortCircuitLhs {
+        or: bool,
+        rhs: Expr,
+    },
+    ShortCircuitRhs {
+        or: bool,
+        lhs: Evaluated,
+    },
+    Raise {
+        span: Span,
+    },
+    Handle {
+        arms: Vec<Arm>,
+    },
+    Assert {
+        span: Span,
+    },
+    /// The hole is the bound after those `done`.
+    WithCostBound {
+        bounds: Vec<(CostMeasure, Expr)>,
+        done: Vec<SynValue>,
+        inner: Expr,
+        span: Span,
+    },
+    WithCost {
+        bounds: Vec<(CostMeasure, SynValue)>,
+        span: Span,
+    },
+    /// The hole is the count.
+    Tick {
+        name: Var,
+        inner: Expr,
+    },
+    AppLhs {
+        rhs: Expr,
+        span: Span,
+    },
+    AppRhs {
+        lhs: Evaluated,
+        span: Span,
+    },
+    /// The hole is the expression to which a redex was reduced, whose cost is added to that of
+    /// the redex.
+    Cost {
+        work: u64,
+        span: u64,
+        counters: Counters,
+    },
+    /// The hole is the expression of the first binding, with the cost of the previous ones.
+    Let {
+        pattern: Arc<Pattern>,
+        binds: Vec<Bind>,
+        expr: Expr,
+        work: u64,
+        span: u64,
+        counters: Counters,
+    },
+    /// The hole is the expression of the binding after those `done`.
+    ParLet {
+        binds: Vec<Bind>,
+        done: Vec<Evaluated>,
+        expr: Expr,
+    },
+}
+
+fn value_expr(value: &SynValue) -> Expr {
+    Expr::Value(Box::new(value.clone()))
+}
+
+impl Frame {
+    /// Returns the cost of an exception raised in the hole, of cost `work`, `span` and `counters`,
+    /// with that of what the frame evaluated before the hole, as `Env::eval_expr` counts it.
+    fn unwind(&self, work: u64, span: u64, counters: Counters) -> (u64, u64, Counters) {
+        let par = |done: &[Evaluated]| {
+            let work = work + done.iter().map(|res| res.work).sum::<u64>();
+            let span = done.iter().map(|res| res.span).fold(span, cmp::max);
+            let counters = done
+                .iter()
+                .fold(counters.clone(), |acc, res| res.counters.clone().par(acc));
+            (work, span, counters)
+        };
+        match self {
+            Frame::BinaryOpRhs { lhs, .. } | Frame::AppRhs { lhs, .. } => {
+                par(std::slice::from_ref(lhs))
+            }
+            Frame::Tuple {
+                par: true, done, ..
+            }
+            | Frame::ParLet { done, .. } => par(done),
+            Frame::Tuple {
+                par: false, done, ..
+            } => (
+                work + done.iter().map(|res| res.work).sum::<u64>(),
+                span + done.iter().map(|res| res.span).sum::<u64>(),
+                done.iter()
+                    .rev()
+                    .fold(counters, |acc, res| res.counters.clone().then(acc)),
+            ),
+            Frame::ShortCircuitRhs { lhs, .. } => (
+                work + lhs.work + 1,
+                span + lhs.span + 1,
+                lhs.counters.clone().then(counters),
+            ),
+            Frame::Guard { arms, .. } => (
+                work + arms.work,
+                span + arms.span,
+                arms.counters.clone().then(counters),
+            ),
+            Frame::Cost {
+                work: w,
+                span: s,
+                counters: c,
+            }
+            | Frame::Let {
+                work: w,
+                span: s,
+                counters: c,
+                ..
+            } => (work + w, span + s, c.clone().then(counters)),
+            _ => (work, span, counters),
+        }
+    }
+
+    /// Fills the hole of the frame.
+    fn plug(&self, hole: Expr) -> Expr {
+        let hole = Box::new(hole);
+        match self {
+            Frame::UnaryOp(op) => Expr::UnaryOp {
+                op: *op,
+                inner: hole,
+            },
+            Frame::BinaryOpLhs { op, rhs } => Expr::BinaryOp {
+                op: *op,
+                lhs: hole,
+                rhs: Box::new(rhs.clone()),
+            },
+            Frame::BinaryOpRhs { op, lhs } => Expr::BinaryOp {
+                op: *op,
+                lhs: Box::new(value_expr(&lhs.result)),
+                rhs: hole,
+            },
+            Frame::Tuple { par, exprs, done } => {
+                let mut components = done
+                    .iter()
+                    .map(|res| value_expr(&res.result))
+                    .collect::<Vec<_>>();
+                components.push(*hole);
+                components.extend_from_slice(&exprs[done.len() + 1..]);
+                if *par {
+                    Expr::ParTuple(components)
+                } else {
+                    Expr::SeqTuple(components)
+                }
+            }
+            Frame::Case { arms, span } => Expr::Case {
+                inner: hole,
+                arms: arms.clone(),
+                span: *span,
+            },
+            Frame::Guard { arms, body } => Expr::Ite {
+                cond: hole,
+                lhs: Box::new(body.clone()),
+                rhs: Box::new(arms.expr()),
+            },
+            Frame::Ite { lhs, rhs } => Expr::Ite {
+                cond: hole,
+                lhs: Box::new(lhs.clone()),
+                rhs: Box::new(rhs.clone()),
+            },
+            Frame::ShortCircuitLhs { or, rhs } => {
+                let rhs = Box::new(rhs.clone());
+                if *or {
+                    Expr::OrElse { lhs: hole, rhs }
+                } else {
+                    Expr::AndAlso { lhs: hole, rhs }
+                }
+            }
+            Frame::ShortCircuitRhs { or, lhs } => {
+                let lhs = Box::new(value_expr(&lhs.result));
+                if *or {
+                    Expr::OrElse { lhs, rhs: hole }
+                } else {
+                    Expr::AndAlso { lhs, rhs: hole }
+                }
+            }
+            Frame::Raise { span } => Expr::Raise {
+                inner: hole,
+                span: *span,
+            },
+            Frame::Handle { arms } => Expr::Handle {
+                inner: hole,
+                arms: arms.clone(),
+            },
+            Frame::Assert { span } => Expr::Assert {
+                inner: hole,
+                span: *span,
+            },
+            Frame::WithCostBound {
+                bounds,
+                done,
+                inner,
+                span,
+            } => {
+                let mut result = bounds
+                    .iter()
+                    .zip(done.iter())
+                    .map(|((measure, _), bound)| (*measure, value_expr(bound)))
+                    .collect::<Vec<_>>();
+                result.push((bounds[done.len()].0, *hole));
+                result.extend_from_slice(&bounds[done.len() + 1..]);
+                Expr::WithCost {
+                    bounds: result,
+                    inner: Box::new(inner.clone()),
+                    span: *span,
+                }
+            }
+            Frame::WithCost { bounds, span } => Expr::WithCost {
+                bounds: bounds
+                    .iter()
+                    .map(|(measure, bound)| (*measure, value_expr(bound)))
+                    .collect(),
+                inner: hole,
+                span: *span,
+            },
+            Frame::Tick { name, inner } => Expr::Tick {
+                name: name.clone(),
+                count: hole,
+                inner: Box::new(inner.clone()),
+            },
+            Frame::AppLhs { rhs, span } => Expr::App {
+                lhs: hole,
+                rhs: Box::new(rhs.clone()),
+                span: *span,
+            },
+            Frame::AppRhs { lhs, span } => Expr::App {
+                lhs: Box::new(value_expr(&lhs.result)),
+                rhs: hole,
+                span: *span,
+            },
+            Frame::Cost { .. } => *hole,
+            Frame::Let {
+                pattern,
+                binds,
+                expr,
+                ..
+            } => {
+                let mut result = vec![Bind {
+                    pattern: pattern.clone(),
+                    expr: hole,
+                }];
+                result.extend_from_slice(binds);
+                Expr::Let {
+                    binds: result,
+                    expr: Box::new(expr.clone()),
+                }
+            }
+            Frame::ParLet { binds, done, expr } => {
+                let mut result = binds
+                    .iter()
+                    .zip(done.iter())
+                    .map(|(bind, res)| Bind {
+                        pattern: bind.pattern.clone(),
+                        expr: Box::new(value_expr(&res.result)),
+                    })
+                    .collect::<Vec<_>>();
+                result.push(Bind {
+                    pattern: binds[done.len()].pattern.clone(),
+                    expr: hole,
+                });
+                result.extend_from_slice(&binds[done.len() + 1..]);
+                Expr::ParLet {
+                    binds: result,
+                    expr: Box::new(expr.clone()),
+                }
+            }
+        }
+    }
+}
+
+/// The state of a reduction.
+#[derive(Debug)]
+struct Machine {
+    control: Control,
+    stack: Vec<Frame>,
+}
+
+impl Machine {
+    /// Returns the program being reduced.
+    fn program(&self) -> Expr {
+        let hole = match &self.control {
+            Control::Eval(expr) => expr.clone(),
+            Control::Return(res) => value_expr(&res.result),
+            Control::Match(arms) => arms.expr(),
+            Control::Unwind {
+                value, location, ..
+            } => Expr::Raise {
+                inner: Box::new(value_expr(value)),
+                span: *location,
+            },
+        };
+        self.stack
+            .iter()
+            .rev()
+            .fold(hole, |hole, frame| frame.plug(hole))
+    }
+
+    /// Evaluates `expr` in the context `frame`.
+    fn push(&mut self, frame: Frame, expr: Expr) {
+        self.stack.push(frame);
+        self.control = Control::Eval(expr);
+    }
+
+    /// Returns a value with its cost.
+    fn ret(&mut self, result: SynValue, work: u64, span: u64, counters: Counters) {
+        self.control = Control::Return(Evaluated {
+            result,
+            work,
+            span,
+            counters,
+        });
+    }
+
+    /// Applies a function to an argument.  The cost of the application is added to `work`, `span`
+    /// and `counters`.
+    fn apply(
+        &mut self,
+        func: SynValue,
+        arg: SynValue,
+        work: u64,
+        span: u64,
+        counters: Counters,
+    ) -> Result<(), Err> {
+        match func {
+            SynValue::Lambda {
+                ref name,
+                ref pattern,
+                ref expr,
+                ..
+            } => {
+                let mut subst = bind(pattern, &arg)?;
+                if let Some(name) = name {
+                    subst.insert(name.clone(), func.clone());
+                }
+                let body = subst_expr(expr, &subst);
+                self.push(
+                    Frame::Cost {
+                        work,
+                        span,
+                        counters,
+                    },
+                    body,
+                );
+            }
+            SynValue::Ctor {
+                ref ctor,
+                inner: None,
+            } => self.ret(
+                SynValue::Ctor {
+                    ctor: ctor.clone(),
+                    inner: Some(Arc::new(arg)),
+                },
+                work,
+                span,
+                counters,
+            ),
+            SynValue::Native { ref func, ref args } => {
+                let (func, mut args) = (func.clone(), args.clone());
+                args.push(arg);
+                if args.len() < func.arity() {
+                    self.ret(SynValue::Native { func, args }, work, span, counters);
+                } else {
+                    let args = args.iter().map(reify).collect::<Result<Vec<_>, _>>()?;
+                    let (call_work, call_span) = func.cost(&args);
+                    let result = quote(&*func.call(&args)?);
+                    self.ret(result, work + call_work, span + call_span, counters);
+                }
+            }
+            _ => {
+                return Err(Err::InvalidAppArgs {
+                    inner: reify(&func)?,
+                })
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes the first arm whose pattern matches the value, evaluating its guard if any.
+    fn match_arms(&mut self, mut arms: Arms) -> Result<(), Err> {
+        if arms.arms.is_empty() {
+            match arms.kind {
+                ArmsKind::Case { patterns } => {
+                    return Err(Err::CaseNoMatch {
+                        inner: reify(&arms.value)?,
+                        patterns,
+                    })
+                }
+                ArmsKind::Handle {
+                    location,
+                    work,
+                    span,
+                    counters,
+                } => {
+                    self.control = Control::Unwind {
+                        value: arms.value,
+                        location,
+                        work,
+                        span,
+                        counters,
+                    };
+                    return Ok(());
+                }
+            }
+        }
+
+        let arm = arms.arms.remove(0);
+        let subst = match bind(&arm.pattern, &arms.value) {
+            Ok(subst) => subst,
+            Err(_) => {
+                self.control = Control::Match(arms);
+                return Ok(());
+            }
+        };
+        let body = subst_expr(&arm.expr, &subst);
+        match &arm.guard {
+            Some(guard) => self.push(Frame::Guard { arms, body }, subst_expr(guard, &subst)),
+            None => self.push(
+                Frame::Cost {
+                    work: arms.work + 1,
+                    span: arms.span + 1,
+                    counters: arms.counters,
+                },
+                body,
+            ),
+        }
+        Ok(())
+    }
+
+    fn eval(&mut self, expr: Expr) -> Result<(), Err> {
+        match expr {
+            Expr::Var(var) | Expr::Resolved { var, .. } => return Err(Err::EnvNotFound { var }),
+            Expr::Value(value) => self.ret(*value, 1, 1, Counters::default()),
+            Expr::UnaryOp { op, inner } => self.push(Frame::UnaryOp(op), *inner),
+            Expr::BinaryOp { op, lhs, rhs } => {
+                self.push(Frame::BinaryOpLhs { op, rhs: *rhs }, *lhs)
+            }
+            Expr::SeqTuple(exprs) | Expr::ParTuple(exprs) if exprs.is_empty() => {
+                self.ret(SynValue::Tuple(vec![]), 1, 1, Counters::default())
+            }
+            Expr::SeqTuple(exprs) => {
+                let first = exprs[0].clone();
+                let frame = Frame::Tuple {
+                    par: false,
+                    exprs,
+                    done: vec![],
+                };
+                self.push(frame, first);
+            }
+            Expr::ParTuple(exprs) => {
+                let first = exprs[0].clone();
+                let frame = Frame::Tuple {
+                    par: true,
+                    exprs,
+                    done: vec![],
+                };
+                self.push(frame, first);
+            }
+            Expr::Case { inner, arms, span } => self.push(Frame::Case { arms, span }, *inner),
+            Expr::Ite { cond, lhs, rhs } => self.push(
+                Frame::Ite {
+                    lhs: *lhs,
+                    rhs: *rhs,
+                },
+                *cond,
+            ),
+            Expr::AndAlso { lhs, rhs } => self.push(
+                Frame::ShortCircuitLhs {
+                    or: false,
+                    rhs: *rhs,
+                },
+                *lhs,
+            ),
+            Expr::OrElse { lhs, rhs } => self.push(
+                Frame::ShortCircuitLhs {
+                    or: true,
+                    rhs: *rhs,
+                },
+                *lhs,
+            ),
+            Expr::Raise { inner, span } => self.push(Frame::Raise { span }, *inner),
+            Expr::Handle { inner, arms } => self.push(Frame::Handle { arms }, *inner),
+            Expr::Assert { inner, span } => self.push(Frame::Assert { span }, *inner),
+            Expr::WithCost {
+                bounds,
+                inner,
+                span,
+            } => {
+                let first = bounds[0].1.clone();
+                let frame = Frame::WithCostBound {
+                    bounds,
+                    done: vec![],
+                    inner: *inner,
+                    span,
+                };
+                self.push(frame, first);
+            }
+            Expr::Tick { name, count, inner } => self.push(
+                Frame::Tick {
+                    name,
+                    inner: *inner,
+                },
+                *count,
+            ),
+            Expr::App { lhs, rhs, span } => self.push(Frame::AppLhs { rhs: *rhs, span }, *lhs),
+            Expr::Let { mut binds, expr } => {
+                let bind = binds.remove(0);
+                let frame = Frame::Let {
+                    pattern: bind.pattern,
+                    binds,
+                    expr: *expr,
+                    work: 0,
+                    span: 0,
+                    counters: Counters::default(),
+                };
+                self.push(frame, *bind.expr);
+            }
+            Expr::ParLet { binds, expr } => {
+                let first = (*binds[0].expr).clone();
+                let frame = Frame::ParLet {
+                    binds,
+                    done: vec![],
+                    expr: *expr,
+                };
+                self.push(frame, first);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a value to the frame.
+    fn ret_to(&mut self, frame: Frame, res: Evaluated) -> Result<(), Err> {
+        match frame {
+            Frame::UnaryOp(op) => {
+                let result = Env::eval_unary_op(op, &*reify(&res.result)?)?;
+                self.ret(quote(&result), res.work + 1, res.span + 1, res.counters);
+            }
+            Frame::BinaryOpLhs { op, rhs } => self.push(Frame::BinaryOpRhs { op, lhs: res }, rhs),
+            Frame::BinaryOpRhs { op, lhs } => {
+                let (result, steps) =
+                    Env::eval_binary_op(op, &*reify(&lhs.result)?, &*reify(&res.result)?)?;
+                self.ret(
+                    quote(&result),
+                    lhs.work + res.work + steps,
+                    cmp::max(lhs.span, res.span) + steps,
+                    lhs.counters.par(res.counters),
+                );
+            }
+            Frame::Tuple {
+                par,
+                exprs,
+                mut done,
+            } => {
+                done.push(res);
+                if done.len() < exprs.len() {
+                    let next = exprs[done.len()].clone();
+                    self.push(Frame::Tuple { par, exprs, done }, next);
+                } else {
+                    let work = done.iter().map(|res| res.work).sum::<u64>();
+                    let span = if par {
+                        done.iter().map(|res| res.span).max().unwrap_or(0)
+                    } else {
+                        done.iter().map(|res| res.span).sum::<u64>()
+                    };
+                    let mut counters = Counters::default();
+                    let mut values = Vec::with_capacity(done.len());
+                    for res in done {
+                        counters = if par {
+                            counters.par(res.counters)
+                        } else {
+                            counters.then(res.counters)
+                        };
+                        values.push(res.result);
+                    }
+                    self.ret(SynValue::Tuple(values), work + 1, span + 1, counters);
+                }
+            }
+            Frame::Case { arms, .. } => {
+                self.control = Control::Match(Arms {
+                    value: res.result,
+                    kind: ArmsKind::Case {
+                        patterns: arms.iter().map(|arm| arm.pattern.clone()).collect(),
+                    },
+                    arms,
+                    work: res.work,
+                    span: res.span,
+                    counters: res.counters,
+                });
+            }
+            Frame::Guard { mut arms, body } => {
+                let cond = coerce_bool(&res.result).ok_or(Err::InvalidGuard {
+                    cond: reify(&res.result)?,
+                })?;
+                arms.work += res.work;
+                arms.span += res.span;
+                arms.counters = mem::take(&mut arms.counters).then(res.counters);
+                if cond {
+                    let frame = Frame::Cost {
+                        work: arms.work + 1,
+                        span: arms.span + 1,
+                        counters: arms.counters,
+                    };
+                    self.push(frame, body);
+                } else {
+                    self.control = Control::Match(arms);
+                }
+            }
+            Frame::Ite { lhs, rhs } => {
+                let cond = coerce_bool(&res.result).ok_or(Err::InvalidIteCond {
+                    cond: reify(&res.result)?,
+                })?;
+                let frame = Frame::Cost {
+                    work: res.work + 1,
+                    span: res.span + 1,
+                    counters: res.counters,
+                };
+                self.push(frame, if cond { lhs } else { rhs });
+            }
+            Frame::ShortCircuitLhs { or, rhs } => {
+                let lhs_result = coerce_bool(&res.result).ok_or(Err::InvalidShortCircuitArg {
+                    arg: reify(&res.result)?,
+                })?;
+                if lhs_result == or {
+                    self.ret(res.result, res.work + 1, res.span + 1, res.counters);
+                } else {
+                    self.push(Frame::ShortCircuitRhs { or, lhs: res }, rhs);
+                }
+            }
+            Frame::ShortCircuitRhs { lhs, .. } => {
+                coerce_bool(&res.result).ok_or(Err::InvalidShortCircuitArg {
+                    arg: reify(&res.result)?,
+                })?;
+                self.ret(
+                    res.result,
+                    lhs.work + res.work + 1,
+                    lhs.span + res.span + 1,
+                    lhs.counters.then(res.counters),
+                );
+            }
+            Frame::Raise { span } => {
+                if !is_exception(&res.result) {
+                    return Err(Err::NotAnException { location: span });
+                }
+                self.control = Control::Unwind {
+                    value: res.result,
+                    location: span,
+                    work: res.work + 1,
+                    span: res.span + 1,
+                    counters: res.counters,
+                };
+            }
+            Frame::Handle { .. } => self.ret(res.result, res.work + 1, res.span + 1, res.counters),
+            Frame::Assert { span } => {
+                let cond = coerce_bool(&res.result).ok_or(Err::InvalidAssertion {
+                    cond: reify(&res.result)?,
+                })?;
+                if cond {
+                    self.ret(
+                        SynValue::Tuple(vec![]),
+                        res.work + 1,
+                        res.span + 1,
+                        res.counters,
+                    );
+                } else {
+                    self.control = Control::Unwind {
+                        value: quote(&Value::assertion_failed()),
+                        location: span,
+                        work: res.work + 1,
+                        span: res.span + 1,
+                        counters: res.counters,
+                    };
+                }
+            }
+            Frame::WithCostBound {
+                bounds,
+                mut done,
+                inner,
+                span,
+            } => {
+                // The bounds are specifications rather than part of the program, so their cost is
+                // dropped.
+                done.push(res.result);
+                if done.len() < bounds.len() {
+                    let next = bounds[done.len()].1.clone();
+                    let frame = Frame::WithCostBound {
+                        bounds,
+                        done,
+                        inner,
+                        span,
+                    };
+                    self.push(frame, next);
+                } else {
+                    let bounds = bounds
+                        .into_iter()
+                        .zip(done)
+                        .map(|((measure, _), bound)| (measure, bound))
+                        .collect();
+                    self.push(Frame::WithCost { bounds, span }, inner);
+                }
+            }
+            Frame::WithCost { bounds, span } => {
+                for (measure, bound) in bounds {
+                    let cost = match measure {
+                        CostMeasure::Work => res.work,
+                        CostMeasure::Span => res.span,
+                    };
+                    let within = match &bound {
+                        SynValue::Integer(bound) => (cost as i128) <= (*bound as i128),
+                        SynValue::Float(bound) => (cost as f64) <= *bound,
+                        _ => {
+                            return Err(Err::InvalidCostBound {
+                                bound: reify(&bound)?,
+                            })
+                        }
+                    };
+                    if !within {
+                        let value = Value::cost_contract_violated(measure, cost, &*reify(&bound)?);
+                        self.control = Control::Unwind {
+                            value: quote(&value),
+                            location: span,
+                            work: res.work,
+                            span: res.span,
+                            counters: res.counters,
+                        };
+                        return Ok(());
+                    }
+                }
+                self.control = Control::Return(res);
+            }
+            // The cost of the count is dropped, as `Env::eval_expr` does.
+            Frame::Tick { name, inner } => match res.result {
+                SynValue::Integer(count) if count >= 0 => {
+                    let frame = Frame::Cost {
+                        work: 0,
+                        span: 0,
+                        counters: Counters::default().tick(&name, count as u64),
+                    };
+                    self.push(frame, inner);
+                }
+                count => {
+                    return Err(Err::InvalidTickCount {
+                        count: reify(&count)?,
+                    })
+                }
+            },
+            Frame::AppLhs { rhs, span } => self.push(Frame::AppRhs { lhs: res, span }, rhs),
+            Frame::AppRhs { lhs, .. } => self.apply(
+                lhs.result,
+                res.result,
+                lhs.work + res.work + 1,
+                cmp::max(lhs.span, res.span) + 1,
+                lhs.counters.par(res.counters),
+            )?,
+            Frame::Cost {
+                work,
+                span,
+                counters,
+            } => self.ret(
+                res.result,
+                res.work + work,
+                res.span + span,
+                counters.then(res.counters),
+            ),
+            Frame::Let {
+                pattern,
+                binds,
+                expr,
+                work,
+                span,
+                counters,
+            } => {
+                let subst = bind(&pattern, &res.result)?;
+                let work = work + res.work;
+                let span = span + res.span;
+                let counters = counters.then(res.counters);
+                // Substituting into the rest of the let respects the shadowing by its bindings.
+                let (mut binds, expr) = match subst_expr(
+                    &Expr::Let {
+                        binds,
+                        expr: Box::new(expr),
+                    },
+                    &subst,
+                ) {
+                    Expr::Let { binds, expr } => (binds, *expr),
+                    _ => unreachable!(),
+                };
+                if binds.is_empty() {
+                    self.push(
+                        Frame::Cost {
+                            work: work + 1,
+                            span: span + 1,
+                            counters,
+                        },
+                        expr,
+                    );
+                } else {
+                    let bind = binds.remove(0);
+                    let frame = Frame::Let {
+                        pattern: bind.pattern,
+                        binds,
+                        expr,
+                        work,
+                        span,
+                        counters,
+                    };
+                    self.push(frame, *bind.expr);
+                }
+            }
+            Frame::ParLet {
+                binds,
+                mut done,
+                expr,
+            } => {
+                done.push(res);
+                if done.len() < binds.len() {
+                    let next = (*binds[done.len()].expr).clone();
+                    self.push(Frame::ParLet { binds, done, expr }, next);
+                } else {
+                    let mut subst = Subst::new();
+                    for (bind, res) in binds.iter().zip(done.iter()) {
+                        subst.extend(self::bind(&bind.pattern, &res.result)?);
+                    }
+                    let work = done.iter().map(|res| res.work).sum::<u64>();
+                    let span = done.iter().map(|res| res.span).max().unwrap_or(0);
+                    let counters = done
+                        .into_iter()
+                        .fold(Counters::default(), |acc, res| acc.par(res.counters));
+                    self.push(
+                        Frame::Cost {
+                            work: work + 1,
+                            span: span + 1,
+                            counters,
+                        },
+                        subst_expr(&expr, &subst),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes a step of the reduction.  Returns the result once the program is reduced to a value.
+    fn step(&mut self) -> Result<Option<Evaluated>, Err> {
+        let control = std::mem::replace(&mut self.control, Control::Eval(Expr::SeqTuple(vec![])));
+        match control {
+            Control::Eval(expr) => self.eval(expr)?,
+            Control::Return(res) => match self.stack.pop() {
+                Some(frame) => self.ret_to(frame, res)?,
+                None => return Ok(Some(res)),
+            },
+            Control::Match(arms) => self.match_arms(arms)?,
+            Control::Unwind {
+                value,
+                location,
+                mut work,
+                mut span,
+                mut counters,
+            } => loop {
+                match self.stack.pop() {
+                    Some(Frame::Handle { arms }) => {
+                        self.control = Control::Match(Arms {
+                            value,
+                            arms,
+                            work,
+                            span,
+                            counters: counters.clone(),
+                            kind: ArmsKind::Handle {
+                                location,
+                                work,
+                                span,
+                                counters,
+                            },
+                        });
+                        break;
+                    }
+                    Some(frame) => (work, span, counters) = frame.unwind(work, span, counters),
+                    None => {
+                        return Err(Err::Raise {
+                            value: reify(&value)?,
+                            location,
+                            work,
+                            span,
+                            extras: counters.into(),
+                        })
+                    }
+                }
+            },
+        }
+        Ok(None)
+    }
+
+    /// Runs the reduction to the end, calling `on_step` with each distinct intermediate program.
+    fn run(mut self, mut on_step: Option<&mut dyn FnMut(&Expr)>) -> EResult<Arc<Value>> {
+        let mut last = None;
+        loop {
+            if let Some(on_step) = &mut on_step {
+                let program = self.program();
+                let text = program.to_string();
+                if last.as_ref() != Some(&text) {
+                    on_step(&program);
+                    last = Some(text);
+                }
+            }
+
+            if let Some(res) = self.step()? {
+                return Ok(Res {
+                    result: reify(&res.result)?,
+                    work: res.work,
+                    span: res.span,
+                    extras: res.counters.into(),
+                });
+            }
+        }
+    }
+}
+
+/// Evaluates an expression by reduction, substituting the values bound in `env` for its free
+/// variables.  Calls `on_step`, if given, with each intermediate program.
+pub(crate) fn eval_expr(
+    env: &Env,
+    expr: &Expr,
+    on_step: Option<&mut dyn FnMut(&Expr)>,
+) -> EResult<Arc<Value>> {
+    let machine = Machine {
+        control: Control::Eval(subst_expr(expr, &free_subst(expr, env, &[]))),
+        stack: vec![],
+    };
+    machine.run(on_step)
+}
+
+/// Applies a function value to an argument by reduction.  As with `Env::apply`, the returned cost
+/// does not include that of the application itself.
+pub(crate) fn apply(
+    func: &Arc<Value>,
+    arg: Arc<Value>,
+    on_step: Option<&mut dyn FnMut(&Expr)>,
+) -> EResult<Arc<Value>> {
+    let mut machine = Machine {
+        control: Control::Eval(Expr::SeqTuple(vec![])),
+        stack: vec![],
+    };
+    machine.apply(quote(func), quote(&arg), 0, 0, Counters::default())?;
+    machine.run(on_step)
+}
@@ -1,35 +1,175 @@
-use std::rc::Rc;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::native::NativeFn;
 
 pub type Var = String;
 pub type Ctor = String;
 
+/// A span of bytes `start..end` in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
 #[derive(Debug, Clone)]
 pub enum Pattern {
+    Wildcard,
     Var(Var),
-    Pair {
-        lhs: Box<Pattern>,
-        rhs: Box<Pattern>,
-    },
+    Integer(i64),
+    Boolean(bool),
+    String(String),
+    Char(char),
+    Tuple(Vec<Pattern>),
     Ctor {
         ctor: Ctor,
+        inner: Option<Box<Pattern>>,
+    },
+    As {
+        var: Var,
         inner: Box<Pattern>,
     },
+    Or {
+        lhs: Box<Pattern>,
+        rhs: Box<Pattern>,
+    },
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Var(var) => write!(f, "{}", var),
+            Pattern::Integer(i) => write!(f, "{}", i),
+            Pattern::Boolean(b) => write!(f, "{}", b),
+            Pattern::String(s) => write!(f, "{:?}", s),
+            Pattern::Char(c) => write!(f, "#{:?}", c.to_string()),
+            Pattern::Tuple(patterns) => {
+                write!(f, "(")?;
+                for (i, pattern) in patterns.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", pattern)?;
+                }
+                write!(f, ")")
+            }
+            Pattern::Ctor { ctor, inner: None } => write!(f, "{}", ctor),
+            Pattern::Ctor {
+                ctor,
+                inner: Some(inner),
+            } => match &**inner {
+                Pattern::Ctor { inner: Some(_), .. } | Pattern::As { .. } | Pattern::Or { .. } => {
+                    write!(f, "{} ({})", ctor, inner)
+                }
+                _ => write!(f, "{} {}", ctor, inner),
+            },
+            Pattern::As { var, inner } => match &**inner {
+                Pattern::Or { .. } => write!(f, "{} as ({})", var, inner),
+                _ => write!(f, "{} as {}", var, inner),
+            },
+            Pattern::Or { lhs, rhs } => match &**rhs {
+                Pattern::Or { .. } => write!(f, "{} | ({})", lhs, rhs),
+                _ => write!(f, "{} | {}", lhs, rhs),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatternError {
+    /// A variable is bound more than once.
+    NonLinear(Var),
+    /// The alternatives of an or-pattern bind different variables.
+    OrMismatch,
+}
+
+impl Pattern {
+    /// Returns the variables bound by the pattern, checking that each of them is bound once and
+    /// that both alternatives of each or-pattern bind the same variables.
+    pub fn binders(&self) -> Result<Vec<Var>, PatternError> {
+        let mut vars = vec![];
+        self.binders_inner(&mut vars)?;
+        Ok(vars)
+    }
+
+    /// Returns the variables bound by the pattern, which is assumed to be well-formed, in the order
+    /// the values bound to them are laid out in a frame of the environment.
+    pub fn vars(&self) -> Vec<Var> {
+        let mut vars = vec![];
+        let _ = self.binders_inner(&mut vars);
+        vars
+    }
+
+    fn binders_inner(&self, vars: &mut Vec<Var>) -> Result<(), PatternError> {
+        fn bind(var: &Var, vars: &mut Vec<Var>) -> Result<(), PatternError> {
+            if vars.contains(var) {
+                return Err(PatternError::NonLinear(var.clone()));
+            }
+            vars.push(var.clone());
+            Ok(())
+        }
+
+        match self {
+            Pattern::Wildcard
+            | Pattern::Integer(_)
+            | Pattern::Boolean(_)
+            | Pattern::String(_)
+            | Pattern::Char(_)
+            | Pattern::Ctor { inner: None, .. } => Ok(()),
+            Pattern::Var(var) => bind(var, vars),
+            Pattern::Tuple(patterns) => {
+                for pattern in patterns {
+                    pattern.binders_inner(vars)?;
+                }
+                Ok(())
+            }
+            Pattern::Ctor {
+                inner: Some(inner), ..
+            } => inner.binders_inner(vars),
+            Pattern::As { var, inner } => {
+                bind(var, vars)?;
+                inner.binders_inner(vars)
+            }
+            // The variables are ordered as they are in `lhs`.
+            Pattern::Or { lhs, rhs } => {
+                let lhs = lhs.binders()?;
+                let mut sorted = lhs.clone();
+                let mut rhs = rhs.binders()?;
+                sorted.sort();
+                rhs.sort();
+                if sorted != rhs {
+                    return Err(PatternError::OrMismatch);
+                }
+                for var in lhs.iter() {
+                    bind(var, vars)?;
+                }
+                Ok(())
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum UnaryOp {
     Not,
     Neg,
+    /// The projection of the `i`-th component of a tuple, counting from 1.
+    Proj(usize),
 }
 
 impl UnaryOp {
     pub fn lift(self) -> Value {
         Value::Lambda {
-            pattern: Rc::new(Pattern::Var("x".into())),
-            expr: Rc::new(Expr::UnaryOp {
+            name: None,
+            pattern: Arc::new(Pattern::Var("x".into())),
+            expr: Arc::new(Expr::UnaryOp {
                 op: self,
                 inner: Box::new(Expr::Var("x".into())),
             }),
+            captures: None,
         }
     }
 }
@@ -44,49 +184,94 @@ pub enum BinaryOp {
     Minus,
     Times,
     Over,
+    /// Integer division rounding towards negative infinity.
+    Div,
+    /// The remainder of `Div`, which has the sign of the divisor.
+    Mod,
+    /// Integer division rounding towards zero.
+    Quot,
+    /// The remainder of `Quot`, which has the sign of the dividend.
+    Rem,
 
     Equal,
+    NotEqual,
     Less,
     Le,
+    Greater,
+    Ge,
 }
 
 impl BinaryOp {
     pub fn lift(self) -> Value {
         Value::Lambda {
-            pattern: Rc::new(Pattern::Var("x".into())),
-            expr: Rc::new(Expr::Value(Box::new(Value::Lambda {
-                pattern: Rc::new(Pattern::Var("y".into())),
-                expr: Rc::new(Expr::BinaryOp {
+            name: None,
+            pattern: Arc::new(Pattern::Var("x".into())),
+            expr: Arc::new(Expr::Value(Box::new(Value::Lambda {
+                name: None,
+                pattern: Arc::new(Pattern::Var("y".into())),
+                expr: Arc::new(Expr::BinaryOp {
                     op: self,
                     lhs: Box::new(Expr::Var("x".into())),
                     rhs: Box::new(Expr::Var("y".into())),
                 }),
+                captures: None,
             }))),
+            captures: None,
         }
     }
 }
 
+/// Values in programs.  Constructors, applied to values or not, and anonymous lambdas are written
+/// in the source; recursive functions and native functions arise when values are substituted into
+/// programs during evaluation.
 #[derive(Debug, Clone)]
 pub enum Value {
     Integer(i64),
+    Float(f64),
     Boolean(bool),
-    Pair {
-        lhs: Box<Value>,
-        rhs: Box<Value>,
-    },
+    String(String),
+    Char(char),
+    Tuple(Vec<Value>),
+    /// An applied constructor.  The argument is shared, so that a long value of a datatype is not
+    /// copied when it is substituted.
     Ctor {
         ctor: Ctor,
-        inner: Box<Value>,
+        inner: Option<Arc<Value>>,
     },
+    /// A lambda, which refers to itself by `name` if it is a recursive function.  `captures` is set
+    /// if its body is resolved.
     Lambda {
-        pattern: Rc<Pattern>,
-        expr: Rc<Expr>,
+        name: Option<Var>,
+        pattern: Arc<Pattern>,
+        expr: Arc<Expr>,
+        captures: Option<Arc<Captures>>,
+    },
+    /// A native function applied to fewer arguments than its arity.
+    Native {
+        func: Arc<dyn NativeFn>,
+        args: Vec<Value>,
     },
 }
 
+/// The free variables of a lambda whose body is resolved.  Its closure captures their values in a
+/// frame of its own, below that of its applications.
+#[derive(Debug, Clone)]
+pub struct Captures {
+    pub vars: Arc<[Var]>,
+    /// The `(depth, index)` of each variable in the environment where the closure is created.
+    pub addrs: Vec<(usize, usize)>,
+}
+
 #[derive(Debug, Clone)]
 pub enum Expr {
     Var(Var),
+    /// A variable resolved to the `index`-th value of the `depth`-th innermost frame of the
+    /// environment.
+    Resolved {
+        var: Var,
+        depth: usize,
+        index: usize,
+    },
     Value(Box<Value>),
     UnaryOp {
         op: UnaryOp,
@@ -97,35 +282,650 @@ pub enum Expr {
         lhs: Box<Expr>,
         rhs: Box<Expr>,
     },
-    SeqPair {
+    /// A tuple whose components are evaluated in sequence.
+    SeqTuple(Vec<Expr>),
+    /// A tuple whose components are evaluated in parallel.
+    ParTuple(Vec<Expr>),
+    Case {
+        inner: Box<Expr>,
+        arms: Vec<Arm>,
+        span: Span,
+    },
+    Ite {
+        cond: Box<Expr>,
         lhs: Box<Expr>,
         rhs: Box<Expr>,
     },
-    ParPair {
+    /// The conjunction that evaluates `rhs` only if `lhs` is `true`.
+    AndAlso {
         lhs: Box<Expr>,
         rhs: Box<Expr>,
     },
-    Case {
-        inner: Box<Expr>,
-        arms: Vec<(Rc<Pattern>, Box<Expr>)>,
-    },
-    Ite {
-        cond: Box<Expr>,
+    /// The disjunction that evaluates `rhs` only if `lhs` is `false`.
+    OrElse {
         lhs: Box<Expr>,
         rhs: Box<Expr>,
     },
+    /// Raises the value of `inner` as an exception.  `span` is the location of the expression.
+    Raise {
+        inner: Box<Expr>,
+        span: Span,
+    },
+    /// Evaluates `inner`, handling the exceptions it raises with the first matching arm.
+    Handle {
+        inner: Box<Expr>,
+        arms: Vec<Arm>,
+    },
+    /// Checks that `inner` evaluates to `true`.  `span` is the location of the expression.  A
+    /// violated assertion raises the builtin exception `AssertionFailed`.
+    Assert {
+        inner: Box<Expr>,
+        span: Span,
+    },
+    /// Evaluates `inner`, checking that its cost is within the bounds.  `span` is the location of
+    /// the expression.  A violated bound raises the builtin exception `CostContractViolated`,
+    /// with a description of the violation.
+    WithCost {
+        bounds: Vec<(CostMeasure, Expr)>,
+        inner: Box<Expr>,
+        span: Span,
+    },
+    /// Charges `count` to the counter `name`, and evaluates `inner`.
+    Tick {
+        name: Var,
+        count: Box<Expr>,
+        inner: Box<Expr>,
+    },
+    /// Applies `lhs` to `rhs`.  `span` is the location of the expression.
     App {
         lhs: Box<Expr>,
         rhs: Box<Expr>,
+        span: Span,
     },
     Let {
         binds: Vec<Bind>,
         expr: Box<Expr>,
     },
+    /// A let whose bindings are evaluated in parallel, in the environment outside the let.
+    ParLet {
+        binds: Vec<Bind>,
+        expr: Box<Expr>,
+    },
+}
+
+impl Expr {
+    /// Calls `f` on the expression and, recursively, on its subexpressions, including the bodies
+    /// of lambdas.
+    pub fn walk<'e>(&'e self, f: &mut impl FnMut(&'e Expr)) {
+        f(self);
+        match self {
+            Expr::Var(_) | Expr::Resolved { .. } => {}
+            Expr::Value(value) => value.walk(f),
+            Expr::UnaryOp { inner, .. }
+            | Expr::Raise { inner, .. }
+            | Expr::Assert { inner, .. } => inner.walk(f),
+            Expr::WithCost { bounds, inner, .. } => {
+                for (_, bound) in bounds {
+                    bound.walk(f);
+                }
+                inner.walk(f);
+            }
+            Expr::Tick { count, inner, .. } => {
+                count.walk(f);
+                inner.walk(f);
+            }
+            Expr::BinaryOp { lhs, rhs, .. }
+            | Expr::AndAlso { lhs, rhs }
+            | Expr::OrElse { lhs, rhs }
+            | Expr::App { lhs, rhs, .. } => {
+                lhs.walk(f);
+                rhs.walk(f);
+            }
+            Expr::SeqTuple(exprs) | Expr::ParTuple(exprs) => {
+                for expr in exprs {
+                    expr.walk(f);
+                }
+            }
+            Expr::Case { inner, arms, .. } | Expr::Handle { inner, arms } => {
+                inner.walk(f);
+                for arm in arms {
+                    if let Some(guard) = &arm.guard {
+                        guard.walk(f);
+                    }
+                    arm.expr.walk(f);
+                }
+            }
+            Expr::Ite { cond, lhs, rhs } => {
+                cond.walk(f);
+                lhs.walk(f);
+                rhs.walk(f);
+            }
+            Expr::Let { binds, expr } | Expr::ParLet { binds, expr } => {
+                for bind in binds {
+                    bind.expr.walk(f);
+                }
+                expr.walk(f);
+            }
+        }
+    }
+
+    /// Returns the location of the expression, if it is recorded.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Expr::App { span, .. }
+            | Expr::Case { span, .. }
+            | Expr::Raise { span, .. }
+            | Expr::Assert { span, .. }
+            | Expr::WithCost { span, .. } => Some(*span),
+            _ => None,
+        }
+    }
+
+    /// Returns the variables occurring free in the expression.
+    pub fn free_vars(&self) -> HashSet<Var> {
+        let mut free = HashSet::new();
+        self.free_vars_inner(&mut vec![], &mut free);
+        free
+    }
+
+    /// Adds the variables occurring free in the expression, except those in `bound`, to `free`.
+    fn free_vars_inner(&self, bound: &mut Vec<Var>, free: &mut HashSet<Var>) {
+        match self {
+            Expr::Var(var) | Expr::Resolved { var, .. } => {
+                if !bound.contains(var) {
+                    free.insert(var.clone());
+                }
+            }
+            Expr::Value(value) => value.free_vars_inner(bound, free),
+            Expr::UnaryOp { inner, .. } => inner.free_vars_inner(bound, free),
+            Expr::BinaryOp { lhs, rhs, .. }
+            | Expr::AndAlso { lhs, rhs }
+            | Expr::OrElse { lhs, rhs }
+            | Expr::App { lhs, rhs, .. } => {
+                lhs.free_vars_inner(bound, free);
+                rhs.free_vars_inner(bound, free);
+            }
+            Expr::SeqTuple(exprs) | Expr::ParTuple(exprs) => {
+                for expr in exprs {
+                    expr.free_vars_inner(bound, free);
+                }
+            }
+            Expr::Raise { inner, .. } | Expr::Assert { inner, .. } => {
+                inner.free_vars_inner(bound, free)
+            }
+            Expr::WithCost { bounds, inner, .. } => {
+                for (_, expr) in bounds {
+                    expr.free_vars_inner(bound, free);
+                }
+                inner.free_vars_inner(bound, free);
+            }
+            Expr::Tick { count, inner, .. } => {
+                count.free_vars_inner(bound, free);
+                inner.free_vars_inner(bound, free);
+            }
+            Expr::Case { inner, arms, .. } | Expr::Handle { inner, arms } => {
+                inner.free_vars_inner(bound, free);
+                for arm in arms {
+                    let len = bound.len();
+                    bound.extend(arm.pattern.vars());
+                    if let Some(guard) = &arm.guard {
+                        guard.free_vars_inner(bound, free);
+                    }
+                    arm.expr.free_vars_inner(bound, free);
+                    bound.truncate(len);
+                }
+            }
+            Expr::Ite { cond, lhs, rhs } => {
+                cond.free_vars_inner(bound, free);
+                lhs.free_vars_inner(bound, free);
+                rhs.free_vars_inner(bound, free);
+            }
+            Expr::Let { binds, expr } => {
+                let len = bound.len();
+                for bind in binds {
+                    bind.expr.free_vars_inner(bound, free);
+                    bound.extend(bind.pattern.vars());
+                }
+                expr.free_vars_inner(bound, free);
+                bound.truncate(len);
+            }
+            Expr::ParLet { binds, expr } => {
+                let len = bound.len();
+                for bind in binds {
+                    bind.expr.free_vars_inner(bound, free);
+                }
+                for bind in binds {
+                    bound.extend(bind.pattern.vars());
+                }
+                expr.free_vars_inner(bound, free);
+                bound.truncate(len);
+            }
+        }
+    }
+}
+
+/// An operator lifted to a lambda, which is written as the operator.
+pub(crate) enum Lifted<'v> {
+    Unary(UnaryOp),
+    Binary(BinaryOp),
+    /// A binary operator applied to its left operand, to which a lifted operator's application
+    /// reduces.
+    Section(BinaryOp, &'v Expr),
 }
 
+impl Value {
+    /// Returns the operator, if the value is the lambda that an operator is lifted to, or that its
+    /// application reduces to.  Operators occur in expressions only in these lambdas, so no other
+    /// lambda is taken for one.
+    pub(crate) fn lifted(&self) -> Option<Lifted<'_>> {
+        let is_var = |expr: &Expr, name: &Var| match expr {
+            Expr::Var(var) | Expr::Resolved { var, .. } => var == name,
+            _ => false,
+        };
+        let (param, expr) = match self {
+            Value::Lambda {
+                name: None,
+                pattern,
+                expr,
+                ..
+            } => match &**pattern {
+                Pattern::Var(param) => (param, expr),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        match &**expr {
+            Expr::UnaryOp { op, inner } if is_var(inner, param) => Some(Lifted::Unary(*op)),
+            Expr::BinaryOp { op, lhs, rhs } if is_var(rhs, param) => {
+                Some(Lifted::Section(*op, lhs))
+            }
+            Expr::Value(value) => match value.lifted()? {
+                Lifted::Section(op, lhs) if is_var(lhs, param) => Some(Lifted::Binary(op)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn walk<'e>(&'e self, f: &mut impl FnMut(&'e Expr)) {
+        match self {
+            Value::Tuple(values) | Value::Native { args: values, .. } => {
+                for value in values {
+                    value.walk(f);
+                }
+            }
+            Value::Ctor {
+                inner: Some(inner), ..
+            } => inner.walk(f),
+            Value::Lambda { expr, .. } => expr.walk(f),
+            Value::Integer(_)
+            | Value::Float(_)
+            | Value::Boolean(_)
+            | Value::String(_)
+            | Value::Char(_)
+            | Value::Ctor { inner: None, .. } => {}
+        }
+    }
+
+    fn free_vars_inner(&self, bound: &mut Vec<Var>, free: &mut HashSet<Var>) {
+        match self {
+            Value::Tuple(values) | Value::Native { args: values, .. } => {
+                for value in values {
+                    value.free_vars_inner(bound, free);
+                }
+            }
+            Value::Ctor {
+                inner: Some(inner), ..
+            } => inner.free_vars_inner(bound, free),
+            Value::Lambda {
+                name,
+                pattern,
+                expr,
+                ..
+            } => {
+                let len = bound.len();
+                bound.extend(name.clone());
+                bound.extend(pattern.vars());
+                expr.free_vars_inner(bound, free);
+                bound.truncate(len);
+            }
+            Value::Integer(_)
+            | Value::Float(_)
+            | Value::Boolean(_)
+            | Value::String(_)
+            | Value::Char(_)
+            | Value::Ctor { inner: None, .. } => {}
+        }
+    }
+}
+
+/// Drops the values the value holds one at a time, rather than recursively, so that dropping a long
+/// list does not overflow the stack.
+impl Drop for Value {
+    fn drop(&mut self) {
+        fn take_components(value: &mut Value, values: &mut Vec<Value>) {
+            match value {
+                Value::Tuple(components)
+                | Value::Native {
+                    args: components, ..
+                } => values.append(components),
+                Value::Ctor { inner, .. } => {
+                    values.extend(inner.take().and_then(|inner| Arc::try_unwrap(inner).ok()))
+                }
+                _ => {}
+            }
+        }
+
+        let mut values = vec![];
+        take_components(self, &mut values);
+        while let Some(mut value) = values.pop() {
+            take_components(&mut value, &mut values);
+        }
+    }
+}
+
+/// Writes the items separated by `sep`.
+fn fmt_sep<T: fmt::Display>(f: &mut fmt::Formatter, items: &[T], sep: &str) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, "{}", sep)?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnaryOp::Not => write!(f, "!"),
+            UnaryOp::Neg => write!(f, "~"),
+            UnaryOp::Proj(i) => write!(f, "#{}", i),
+        }
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            BinaryOp::Or => "|",
+            BinaryOp::And => "&",
+            BinaryOp::Xor => "^",
+            BinaryOp::Plus => "+",
+            BinaryOp::Minus => "-",
+            BinaryOp::Times => "*",
+            BinaryOp::Over => "/",
+            BinaryOp::Div => "div",
+            BinaryOp::Mod => "mod",
+            BinaryOp::Quot => "quot",
+            BinaryOp::Rem => "rem",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "<>",
+            BinaryOp::Less => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::Ge => ">=",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(lifted) = self.lifted() {
+            return match lifted {
+                Lifted::Unary(op) => write!(f, "{}", op),
+                // `(*` would start a comment.
+                Lifted::Binary(BinaryOp::Times) => write!(f, "( * )"),
+                Lifted::Binary(op) => write!(f, "{}", op),
+                Lifted::Section(BinaryOp::Times, lhs) => write!(f, "( * {})", lhs),
+                Lifted::Section(op, lhs) => write!(f, "({} {})", op, lhs),
+            };
+        }
+
+        match self {
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Char(c) => write!(f, "#{:?}", c.to_string()),
+            Value::Tuple(values) => {
+                write!(f, "(")?;
+                fmt_sep(f, values, ", ")?;
+                write!(f, ")")
+            }
+            Value::Ctor { ctor, inner: None } => write!(f, "{}", ctor),
+            Value::Ctor {
+                ctor,
+                inner: Some(inner),
+            } => write!(f, "({} {})", ctor, inner),
+            // A recursive function is written as its name, which it is bound to where it is
+            // declared.
+            Value::Lambda {
+                name: Some(name), ..
+            } => write!(f, "{}", name),
+            Value::Lambda {
+                name: None,
+                pattern,
+                expr,
+                ..
+            } => write!(f, "(fun {} -> {})", pattern, expr),
+            Value::Native { func, args } => {
+                for _ in args {
+                    write!(f, "(")?;
+                }
+                write!(f, "{}", func.name())?;
+                for arg in args {
+                    write!(f, " {})", arg)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Arm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.pattern)?;
+        if let Some(guard) = &self.guard {
+            write!(f, " if {}", guard)?;
+        }
+        write!(f, " => {},", self.expr)
+    }
+}
+
+impl fmt::Display for Bind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} = {},", self.pattern, self.expr)
+    }
+}
+
+/// Expressions are written in the concrete syntax, on a single line.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Var(var) | Expr::Resolved { var, .. } => write!(f, "{}", var),
+            Expr::Value(value) => write!(f, "{}", value),
+            Expr::UnaryOp { op, inner } => write!(f, "({} {})", op, inner),
+            // `(*` would start a comment.
+            Expr::BinaryOp {
+                op: BinaryOp::Times,
+                lhs,
+                rhs,
+            } => write!(f, "(( * {}) {})", lhs, rhs),
+            Expr::BinaryOp { op, lhs, rhs } => write!(f, "(({} {}) {})", op, lhs, rhs),
+            Expr::SeqTuple(exprs) => {
+                write!(f, "(")?;
+                fmt_sep(f, exprs, "; ")?;
+                write!(f, ")")
+            }
+            Expr::ParTuple(exprs) => {
+                write!(f, "(")?;
+                fmt_sep(f, exprs, " || ")?;
+                write!(f, ")")
+            }
+            Expr::Case { inner, arms, .. } => {
+                write!(f, "case {} {{ ", inner)?;
+                fmt_sep(f, arms, " ")?;
+                write!(f, " }}")
+            }
+            Expr::Ite { cond, lhs, rhs } => {
+                write!(f, "if {} {{ {} }} else {{ {} }}", cond, lhs, rhs)
+            }
+            Expr::AndAlso { lhs, rhs } => write!(f, "({} andalso {})", lhs, rhs),
+            Expr::OrElse { lhs, rhs } => write!(f, "({} orelse {})", lhs, rhs),
+            Expr::Raise { inner, .. } => write!(f, "raise {}", inner),
+            Expr::Handle { inner, arms } => {
+                write!(f, "({} handle {{ ", inner)?;
+                fmt_sep(f, arms, " ")?;
+                write!(f, " }})")
+            }
+            Expr::Assert { inner, .. } => write!(f, "assert {}", inner),
+            Expr::WithCost { bounds, inner, .. } => {
+                write!(f, "withcost (")?;
+                for (i, (measure, bound)) in bounds.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{} <= {}", measure, bound)?;
+                }
+                write!(f, ") {}", inner)
+            }
+            Expr::Tick { name, count, inner } => write!(f, "tick {} {} {}", name, count, inner),
+            Expr::App { lhs, rhs, .. } => match &**lhs {
+                Expr::Value(value)
+                    if matches!(value.lifted(), Some(Lifted::Binary(BinaryOp::Times))) =>
+                {
+                    write!(f, "( * {})", rhs)
+                }
+                _ => write!(f, "({} {})", lhs, rhs),
+            },
+            Expr::Let { binds, expr } => {
+                write!(f, "let ")?;
+                fmt_sep(f, binds, " ")?;
+                write!(f, " in {}", expr)
+            }
+            Expr::ParLet { binds, expr } => {
+                write!(f, "let par ")?;
+                fmt_sep(f, binds, " ")?;
+                write!(f, " in {}", expr)
+            }
+        }
+    }
+}
+
+/// The measures of cost bounded by cost contracts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CostMeasure {
+    Work,
+    Span,
+}
+
+impl fmt::Display for CostMeasure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CostMeasure::Work => write!(f, "work"),
+            CostMeasure::Span => write!(f, "span"),
+        }
+    }
+}
+
+/// An arm of a case expression.  The arm is taken if the pattern matches and the guard, if any,
+/// evaluates to `true`.
+#[derive(Debug, Clone)]
+pub struct Arm {
+    pub pattern: Arc<Pattern>,
+    pub guard: Option<Box<Expr>>,
+    pub expr: Box<Expr>,
+}
+
+/// A binding of a let expression, which sees the bindings before it.
 #[derive(Debug, Clone)]
 pub struct Bind {
-    pub var: Var,
+    pub pattern: Arc<Pattern>,
     pub expr: Box<Expr>,
 }
+
+#[derive(Debug, Clone)]
+pub enum Type {
+    Var(Var),
+    Con { name: Var, args: Vec<Type> },
+    Tuple(Vec<Type>),
+    Arrow { lhs: Box<Type>, rhs: Box<Type> },
+}
+
+impl Type {
+    /// Formats the type, parenthesized if it binds looser than `prec`.
+    fn fmt_prec(&self, f: &mut fmt::Formatter, prec: u8) -> fmt::Result {
+        if self.prec() < prec {
+            return write!(f, "({})", self);
+        }
+
+        match self {
+            Type::Var(var) => write!(f, "{}", var),
+            Type::Con { name, args } => {
+                for arg in args {
+                    arg.fmt_prec(f, 2)?;
+                    write!(f, " ")?;
+                }
+                write!(f, "{}", name)
+            }
+            Type::Tuple(tys) => {
+                for (i, ty) in tys.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " * ")?;
+                    }
+                    ty.fmt_prec(f, 2)?;
+                }
+                Ok(())
+            }
+            Type::Arrow { lhs, rhs } => {
+                lhs.fmt_prec(f, 1)?;
+                write!(f, " -> ")?;
+                rhs.fmt_prec(f, 0)
+            }
+        }
+    }
+
+    fn prec(&self) -> u8 {
+        match self {
+            Type::Var(_) | Type::Con { .. } => 2,
+            Type::Tuple(_) => 1,
+            Type::Arrow { .. } => 0,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_prec(f, 0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Decl {
+    Val {
+        var: Var,
+        expr: Box<Expr>,
+    },
+    Fun {
+        name: Var,
+        pattern: Arc<Pattern>,
+        expr: Arc<Expr>,
+    },
+    Datatype {
+        name: Var,
+        params: Vec<Var>,
+        ctors: Vec<(Ctor, Option<Type>)>,
+    },
+    Exception {
+        ctor: Ctor,
+        ty: Option<Type>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub decls: Vec<Decl>,
+    pub expr: Option<Box<Expr>>,
+}
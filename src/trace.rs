@@ -0,0 +1,212 @@
+//! Tracing of evaluations.
+//!
+//! The evaluator reports its steps to the observer of the current thread, if any.  The observer is
+//! thread-local, so parallel expressions are evaluated sequentially while observed; their costs are
+//! unaffected.
+//!
+//! The tracer is the observer that prints to stderr each expression entered, with the bindings of
+//! its free variables, each pattern tried in a case or handler, and the value and cost each
+//! expression returns.  Events are indented by the depth of the evaluation.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::semantics::{EResult, Env, Err, Value};
+use crate::syntax::{Expr, Pattern};
+
+/// The maximum number of characters of an expression or value that are printed.
+const WIDTH: usize = 72;
+
+/// Options of tracing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceOptions {
+    /// Events nested deeper than this are not printed.
+    pub max_depth: Option<usize>,
+    /// Whether to print the bindings of the free variables of each entered expression.
+    pub show_env: bool,
+}
+
+impl Default for TraceOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            show_env: true,
+        }
+    }
+}
+
+/// Observes the steps of evaluations on the current thread.
+pub(crate) trait Observer {
+    /// Called before `expr` is evaluated in `env`.  Returning an error aborts the evaluation.
+    fn enter(&mut self, expr: &Expr, env: &Env) -> Result<(), Err>;
+
+    /// Called after the most recently entered expression is evaluated.
+    fn exit(&mut self, res: &EResult<Arc<Value>>);
+
+    /// Called after `pattern` is tried against `value`.
+    fn pattern(&mut self, _pattern: &Pattern, _value: &Arc<Value>, _matched: bool) {}
+
+    /// Called before the body of the lambda `func` is evaluated for the argument `arg`.
+    fn call(&mut self, _func: &Arc<Value>, _arg: &Arc<Value>) {}
+
+    /// Called after the body of the most recently called lambda is evaluated.
+    fn ret(&mut self, _res: &EResult<Arc<Value>>) {}
+}
+
+thread_local! {
+    static OBSERVER: RefCell<Option<Box<dyn Observer>>> = const { RefCell::new(None) };
+}
+
+/// Runs `f`, reporting the evaluations in it to `observer` if given.
+pub(crate) fn with<T>(observer: Option<Box<dyn Observer>>, f: impl FnOnce() -> T) -> T {
+    let saved = OBSERVER.with(|cell| cell.replace(observer));
+    let result = f();
+    OBSERVER.with(|cell| *cell.borrow_mut() = saved);
+    result
+}
+
+/// Returns whether evaluations are observed.
+pub(crate) fn enabled() -> bool {
+    OBSERVER.with(|observer| observer.borrow().is_some())
+}
+
+fn notify(f: impl FnOnce(&mut dyn Observer)) {
+    OBSERVER.with(|observer| {
+        if let Some(observer) = &mut *observer.borrow_mut() {
+            f(&mut **observer);
+        }
+    })
+}
+
+/// Reports that `expr` is entered in `env`.
+pub(crate) fn enter(expr: &Expr, env: &Env) -> Result<(), Err> {
+    OBSERVER.with(|observer| match &mut *observer.borrow_mut() {
+        Some(observer) => observer.enter(expr, env),
+        None => Ok(()),
+    })
+}
+
+/// Reports the result of the most recently entered expression.
+pub(crate) fn exit(res: &EResult<Arc<Value>>) {
+    notify(|observer| observer.exit(res))
+}
+
+/// Reports that `pattern` is tried against `value`.
+pub(crate) fn pattern(pattern: &Pattern, value: &Arc<Value>, matched: bool) {
+    notify(|observer| observer.pattern(pattern, value, matched))
+}
+
+/// Reports that the body of the lambda `func` is entered for the argument `arg`.
+pub(crate) fn call(func: &Arc<Value>, arg: &Arc<Value>) {
+    notify(|observer| observer.call(func, arg))
+}
+
+/// Reports the result of the most recently called lambda.
+pub(crate) fn ret(res: &EResult<Arc<Value>>) {
+    notify(|observer| observer.ret(res))
+}
+
+/// Prints the evaluation steps to stderr.
+#[derive(Debug)]
+pub(crate) struct Tracer {
+    options: TraceOptions,
+    depth: usize,
+}
+
+impl Tracer {
+    pub(crate) fn new(options: TraceOptions) -> Self {
+        Self { options, depth: 0 }
+    }
+
+    /// Prints a line at the current depth, if it is shown, and then adjusts the depth by `delta`.
+    fn print(&mut self, line: impl FnOnce() -> String, delta: isize) {
+        if delta < 0 {
+            self.depth -= 1;
+        }
+        if self
+            .options
+            .max_depth
+            .is_none_or(|max_depth| self.depth <= max_depth)
+        {
+            eprintln!("{:indent$}{}", "", line(), indent = 2 * self.depth);
+        }
+        if delta > 0 {
+            self.depth += 1;
+        }
+    }
+}
+
+/// Formats `item`, eliding what exceeds the width.
+pub(crate) fn abbreviate(item: impl fmt::Display) -> String {
+    let s = item.to_string();
+    match s.char_indices().nth(WIDTH) {
+        Some((end, _)) => format!("{}...", &s[..end]),
+        None => s,
+    }
+}
+
+impl Observer for Tracer {
+    fn enter(&mut self, expr: &Expr, env: &Env) -> Result<(), Err> {
+        let show_env = self.options.show_env;
+        self.print(
+            || {
+                let mut line = format!("-> {}", abbreviate(expr));
+                if show_env {
+                    let mut vars = expr.free_vars().into_iter().collect::<Vec<_>>();
+                    vars.sort();
+                    let bindings = vars
+                        .into_iter()
+                        .filter_map(|var| {
+                            let value = env.eval_var(&var).ok()?;
+                            Some(format!("{} = {}", var, abbreviate(value)))
+                        })
+                        .collect::<Vec<_>>();
+                    if !bindings.is_empty() {
+                        line += &format!("  [{}]", bindings.join(", "));
+                    }
+                }
+                line
+            },
+            1,
+        );
+        Ok(())
+    }
+
+    fn pattern(&mut self, pattern: &Pattern, value: &Arc<Value>, matched: bool) {
+        self.print(
+            || {
+                format!(
+                    "{} {} {}",
+                    pattern,
+                    if matched { "matches" } else { "does not match" },
+                    abbreviate(value)
+                )
+            },
+            0,
+        );
+    }
+
+    fn exit(&mut self, res: &EResult<Arc<Value>>) {
+        self.print(
+            || match res {
+                Ok(res) => format!(
+                    "<- {} (work: {}, span: {})",
+                    abbreviate(&res.result),
+                    res.work,
+                    res.span
+                ),
+                Err(Err::Raise {
+                    value, work, span, ..
+                }) => format!(
+                    "<- raise {} (work: {}, span: {})",
+                    abbreviate(value),
+                    work,
+                    span
+                ),
+                Err(e) => format!("<- error {}", abbreviate(format!("{:?}", e))),
+            },
+            -1,
+        );
+    }
+}
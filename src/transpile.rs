@@ -0,0 +1,1315 @@
+//! Compilation of programs to Rust.
+//!
+//! A program becomes a standalone Rust source file, which depends only on rayon.  Its expressions
+//! become Rust expressions evaluated in a thread pool, with the components of parallel tuples and
+//! the bindings of parallel lets forked by `rayon::join`.  The compiled program prints its result
+//! and the wall-clock time of its evaluation, to be compared with the span the interpreter
+//! reports.
+//!
+//! Programs are not type-checked, so values keep a dynamic representation: each datatype becomes
+//! an enum whose constructors carry their arguments as values, the declared exceptions become the
+//! enum `Exn`, tuples become vectors, and functions become boxed closures.  Exceptions propagate
+//! as the errors of `Result`s, and failed assertions raise the builtin exception `AssertionFailed`.
+//! Ill-typed operations and unmatched values panic.  Cost contracts are not checked and ticks are
+//! not counted, since the compiled program does not count steps, so the builtin exception
+//! `CostContractViolated` is declared but never raised.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::semantics::{ASSERTION_FAILED, COST_CONTRACT_VIOLATED};
+use crate::syntax::{Arm, BinaryOp, Bind, Ctor, Decl, Expr, Pattern, Program, UnaryOp, Value, Var};
+
+/// Errors in compiling a program.
+#[derive(Debug, Clone)]
+pub enum CompileError {
+    /// A variable is neither bound nor a builtin function.
+    Unbound(String),
+    /// A constructor is declared neither in a datatype nor as an exception.
+    Undeclared(String),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::Unbound(var) => write!(f, "unbound variable {}", var),
+            CompileError::Undeclared(ctor) => write!(f, "undeclared constructor {}", ctor),
+        }
+    }
+}
+
+/// The builtin functions, which are defined in the `builtins` module of the prelude.
+const BUILTINS: &[&str] = &[
+    "concat",
+    "size",
+    "sub",
+    "substring",
+    "explode",
+    "implode",
+    "str",
+    "ord",
+    "chr",
+    "toString",
+    "real",
+    "floor",
+    "ceil",
+    "round",
+    "trunc",
+    "log",
+    "sqrt",
+    "print",
+];
+
+/// The enum of the declared exceptions.
+const EXN: &str = "Exn";
+
+/// The part of a compiled program that does not depend on the program.
+const PRELUDE: &str = r#"
+type R = Result<Value, Value>;
+
+/// A function.
+struct Func(Box<dyn Fn(Value) -> R + Send + Sync>);
+
+impl PartialEq for Func {
+    fn eq(&self, _: &Func) -> bool {
+        panic!("functions cannot be compared")
+    }
+}
+
+impl Value {
+    fn unit() -> Value {
+        Value::Tuple(Arc::new(vec![]))
+    }
+
+    fn tuple(values: Vec<Value>) -> Value {
+        Value::Tuple(Arc::new(values))
+    }
+
+    fn str(s: &str) -> Value {
+        Value::Str(Arc::from(s))
+    }
+
+    fn fun(f: impl Fn(Value) -> R + Send + Sync + 'static) -> Value {
+        Value::Fun(Arc::new(Func(Box::new(f))))
+    }
+}
+
+/// Returns a recursive function, which is passed itself.
+fn fix(f: impl Fn(&Value, Value) -> R + Send + Sync + 'static) -> Value {
+    Value::Fun(Arc::new_cyclic(|this: &Weak<Func>| {
+        let this = this.clone();
+        Func(Box::new(move |arg| {
+            f(&Value::Fun(this.upgrade().unwrap()), arg)
+        }))
+    }))
+}
+
+fn fmt_ctor(f: &mut fmt::Formatter, ctor: &str, inner: &Value) -> fmt::Result {
+    if inner.is_applied() {
+        write!(f, "{} ({})", ctor, inner)
+    } else {
+        write!(f, "{} {}", ctor, inner)
+    }
+}
+
+fn apply(func: Value, arg: Value) -> R {
+    match func {
+        Value::Fun(func) => (func.0)(arg),
+        func => panic!("{} is not a function", func),
+    }
+}
+
+fn test(cond: Value) -> bool {
+    match cond {
+        Value::Bool(b) => b,
+        cond => panic!("{} is not a boolean", cond),
+    }
+}
+
+type Task<'a> = Box<dyn FnOnce() -> R + Send + 'a>;
+
+/// Runs the tasks in parallel by `rayon::join`, propagating the exception of the leftmost failed
+/// task.
+fn par(mut tasks: Vec<Task>) -> Result<Vec<Value>, Value> {
+    if tasks.len() == 1 {
+        return Ok(vec![(tasks.pop().unwrap())()?]);
+    }
+    let rhs = tasks.split_off(tasks.len() / 2);
+    let (lhs, rhs) = rayon::join(|| par(tasks), || par(rhs));
+    let mut values = lhs?;
+    values.extend(rhs?);
+    Ok(values)
+}
+
+fn invalid(op: &str, lhs: &Value, rhs: &Value) -> ! {
+    panic!("invalid arguments of {}: {} and {}", op, lhs, rhs)
+}
+
+fn not(inner: Value) -> Value {
+    match inner {
+        Value::Bool(b) => Value::Bool(!b),
+        inner => panic!("invalid argument of !: {}", inner),
+    }
+}
+
+fn neg(inner: Value) -> Value {
+    match inner {
+        Value::Int(i) => Value::Int(i.wrapping_neg()),
+        Value::Float(x) => Value::Float(-x),
+        inner => panic!("invalid argument of ~: {}", inner),
+    }
+}
+
+fn proj(inner: Value, i: usize) -> Value {
+    match &inner {
+        Value::Tuple(values) if i >= 1 && i <= values.len() => values[i - 1].clone(),
+        _ => panic!("invalid argument of #{}: {}", i, inner),
+    }
+}
+
+fn or(lhs: Value, rhs: Value) -> Value {
+    match (&lhs, &rhs) {
+        (Value::Bool(l), Value::Bool(r)) => Value::Bool(*l || *r),
+        _ => invalid("|", &lhs, &rhs),
+    }
+}
+
+fn and(lhs: Value, rhs: Value) -> Value {
+    match (&lhs, &rhs) {
+        (Value::Bool(l), Value::Bool(r)) => Value::Bool(*l && *r),
+        _ => invalid("&", &lhs, &rhs),
+    }
+}
+
+fn xor(lhs: Value, rhs: Value) -> Value {
+    match (&lhs, &rhs) {
+        (Value::Bool(l), Value::Bool(r)) => Value::Bool(*l ^ *r),
+        _ => invalid("^", &lhs, &rhs),
+    }
+}
+
+fn plus(lhs: Value, rhs: Value) -> Value {
+    match (&lhs, &rhs) {
+        (Value::Int(l), Value::Int(r)) => Value::Int(l.wrapping_add(*r)),
+        (Value::Float(l), Value::Float(r)) => Value::Float(l + r),
+        _ => invalid("+", &lhs, &rhs),
+    }
+}
+
+fn minus(lhs: Value, rhs: Value) -> Value {
+    match (&lhs, &rhs) {
+        (Value::Int(l), Value::Int(r)) => Value::Int(l.wrapping_sub(*r)),
+        (Value::Float(l), Value::Float(r)) => Value::Float(l - r),
+        _ => invalid("-", &lhs, &rhs),
+    }
+}
+
+fn times(lhs: Value, rhs: Value) -> Value {
+    match (&lhs, &rhs) {
+        (Value::Int(l), Value::Int(r)) => Value::Int(l.wrapping_mul(*r)),
+        (Value::Float(l), Value::Float(r)) => Value::Float(l * r),
+        _ => invalid("*", &lhs, &rhs),
+    }
+}
+
+/// Returns the integer operands of a division, which panics if the divisor is zero.
+fn divide(op: &str, lhs: &Value, rhs: &Value) -> (i64, i64) {
+    match (lhs, rhs) {
+        (Value::Int(_), Value::Int(0)) => panic!("division by zero"),
+        (Value::Int(l), Value::Int(r)) => (*l, *r),
+        _ => invalid(op, lhs, rhs),
+    }
+}
+
+fn over(lhs: Value, rhs: Value) -> Value {
+    match (&lhs, &rhs) {
+        (Value::Float(l), Value::Float(r)) => Value::Float(l / r),
+        _ => {
+            let (l, r) = divide("/", &lhs, &rhs);
+            Value::Int(l.wrapping_div(r))
+        }
+    }
+}
+
+fn div(lhs: Value, rhs: Value) -> Value {
+    let (l, r) = divide("div", &lhs, &rhs);
+    let (quot, rem) = (l.wrapping_div(r), l.wrapping_rem(r));
+    Value::Int(if rem != 0 && (rem < 0) != (r < 0) { quot - 1 } else { quot })
+}
+
+fn modulo(lhs: Value, rhs: Value) -> Value {
+    let (l, r) = divide("mod", &lhs, &rhs);
+    let rem = l.wrapping_rem(r);
+    Value::Int(if rem != 0 && (rem < 0) != (r < 0) { rem + r } else { rem })
+}
+
+fn quot(lhs: Value, rhs: Value) -> Value {
+    let (l, r) = divide("quot", &lhs, &rhs);
+    Value::Int(l.wrapping_div(r))
+}
+
+fn rem(lhs: Value, rhs: Value) -> Value {
+    let (l, r) = divide("rem", &lhs, &rhs);
+    Value::Int(l.wrapping_rem(r))
+}
+
+fn equal(lhs: Value, rhs: Value) -> Value {
+    Value::Bool(lhs == rhs)
+}
+
+fn not_equal(lhs: Value, rhs: Value) -> Value {
+    Value::Bool(lhs != rhs)
+}
+
+fn compare(op: &str, lhs: &Value, rhs: &Value) -> Option<Ordering> {
+    match (lhs, rhs) {
+        (Value::Int(l), Value::Int(r)) => l.partial_cmp(r),
+        (Value::Float(l), Value::Float(r)) => l.partial_cmp(r),
+        (Value::Bool(l), Value::Bool(r)) => l.partial_cmp(r),
+        (Value::Char(l), Value::Char(r)) => l.partial_cmp(r),
+        (Value::Str(l), Value::Str(r)) => l.partial_cmp(r),
+        _ => invalid(op, lhs, rhs),
+    }
+}
+
+fn less(lhs: Value, rhs: Value) -> Value {
+    Value::Bool(compare("<", &lhs, &rhs).is_some_and(Ordering::is_lt))
+}
+
+fn le(lhs: Value, rhs: Value) -> Value {
+    Value::Bool(compare("<=", &lhs, &rhs).is_some_and(Ordering::is_le))
+}
+
+fn greater(lhs: Value, rhs: Value) -> Value {
+    Value::Bool(compare(">", &lhs, &rhs).is_some_and(Ordering::is_gt))
+}
+
+fn ge(lhs: Value, rhs: Value) -> Value {
+    Value::Bool(compare(">=", &lhs, &rhs).is_some_and(Ordering::is_ge))
+}
+
+/// The builtin functions, curried.
+mod builtins {
+    use super::*;
+    use std::io::{self, Write};
+
+    fn fun1(f: fn(Value) -> Value) -> Value {
+        Value::fun(move |a| Ok(f(a)))
+    }
+
+    fn fun2(f: fn(Value, Value) -> Value) -> Value {
+        Value::fun(move |a| Ok(Value::fun(move |b| Ok(f(a.clone(), b)))))
+    }
+
+    fn fun3(f: fn(Value, Value, Value) -> Value) -> Value {
+        Value::fun(move |a| {
+            Ok(Value::fun(move |b| {
+                let a = a.clone();
+                Ok(Value::fun(move |c| Ok(f(a.clone(), b.clone(), c))))
+            }))
+        })
+    }
+
+    fn string(name: &str, value: &Value) -> Arc<str> {
+        match value {
+            Value::Str(s) => s.clone(),
+            _ => panic!("{}: expected a string, got {}", name, value),
+        }
+    }
+
+    fn int(name: &str, value: &Value) -> i64 {
+        match value {
+            Value::Int(i) => *i,
+            _ => panic!("{}: expected an integer, got {}", name, value),
+        }
+    }
+
+    fn float(name: &str, value: &Value) -> f64 {
+        match value {
+            Value::Float(x) => *x,
+            _ => panic!("{}: expected a float, got {}", name, value),
+        }
+    }
+
+    fn char(name: &str, value: &Value) -> char {
+        match value {
+            Value::Char(c) => *c,
+            _ => panic!("{}: expected a character, got {}", name, value),
+        }
+    }
+
+    /// Returns the characters `start..start + len` of `s`.
+    fn slice(name: &str, s: &str, start: i64, len: i64) -> String {
+        let size = s.chars().count() as i64;
+        if start < 0 || len < 0 || start + len > size {
+            panic!(
+                "{}: range {}..{} out of bounds for size {}",
+                name,
+                start,
+                start + len,
+                size
+            );
+        }
+        s.chars().skip(start as usize).take(len as usize).collect()
+    }
+
+    /// Converts a float to an integer with `round`, panicking if the result is out of range.
+    fn to_int(name: &str, value: &Value, round: fn(f64) -> f64) -> Value {
+        let x = float(name, value);
+        let rounded = round(x);
+        if rounded.is_nan() || rounded < -(2f64.powi(63)) || rounded >= 2f64.powi(63) {
+            panic!("{}: {:?} is out of range", name, x);
+        }
+        Value::Int(rounded as i64)
+    }
+
+    pub fn concat() -> Value {
+        fun2(|a, b| {
+            let (a, b) = (string("concat", &a), string("concat", &b));
+            Value::str(&format!("{}{}", a, b))
+        })
+    }
+
+    pub fn size() -> Value {
+        fun1(|s| Value::Int(string("size", &s).chars().count() as i64))
+    }
+
+    pub fn sub() -> Value {
+        fun2(|s, i| {
+            let c = slice("sub", &string("sub", &s), int("sub", &i), 1);
+            Value::Char(c.chars().next().unwrap())
+        })
+    }
+
+    pub fn substring() -> Value {
+        fun3(|s, start, len| {
+            let s = string("substring", &s);
+            let (start, len) = (int("substring", &start), int("substring", &len));
+            Value::str(&slice("substring", &s, start, len))
+        })
+    }
+
+    pub fn explode() -> Value {
+        fun1(|s| {
+            let chars = string("explode", &s).chars().collect::<Vec<_>>();
+            chars
+                .into_iter()
+                .rev()
+                .fold(nil(), |tail, c| cons(Value::Char(c), tail))
+        })
+    }
+
+    pub fn implode() -> Value {
+        fun1(|l| {
+            let mut s = String::new();
+            let mut l = l;
+            while let Some((head, tail)) = uncons(&l) {
+                s.push(char("implode", &head));
+                l = tail;
+            }
+            Value::str(&s)
+        })
+    }
+
+    pub fn str() -> Value {
+        fun1(|c| Value::str(&char("str", &c).to_string()))
+    }
+
+    pub fn ord() -> Value {
+        fun1(|c| Value::Int(char("ord", &c) as i64))
+    }
+
+    pub fn chr() -> Value {
+        fun1(|i| {
+            let i = int("chr", &i);
+            let c = u32::try_from(i).ok().and_then(char::from_u32);
+            Value::Char(c.unwrap_or_else(|| panic!("chr: {} is not a character code", i)))
+        })
+    }
+
+    pub fn toString() -> Value {
+        fun1(|x| match x {
+            Value::Int(_) | Value::Float(_) => Value::str(&x.to_string()),
+            _ => panic!("toString: expected a number, got {}", x),
+        })
+    }
+
+    pub fn real() -> Value {
+        fun1(|i| Value::Float(int("real", &i) as f64))
+    }
+
+    pub fn floor() -> Value {
+        fun1(|x| to_int("floor", &x, f64::floor))
+    }
+
+    pub fn ceil() -> Value {
+        fun1(|x| to_int("ceil", &x, f64::ceil))
+    }
+
+    pub fn round() -> Value {
+        fun1(|x| to_int("round", &x, f64::round))
+    }
+
+    pub fn trunc() -> Value {
+        fun1(|x| to_int("trunc", &x, f64::trunc))
+    }
+
+    pub fn log() -> Value {
+        fun1(|x| match x {
+            Value::Int(n) if n > 0 => Value::Int(64 - (n - 1).leading_zeros() as i64),
+            Value::Float(x) => Value::Float(x.log2()),
+            _ => panic!("log: expected a positive integer or a float, got {}", x),
+        })
+    }
+
+    pub fn sqrt() -> Value {
+        fun1(|x| Value::Float(float("sqrt", &x).sqrt()))
+    }
+
+    pub fn print() -> Value {
+        fun1(|s| {
+            print!("{}", string("print", &s));
+            io::stdout().flush().ok();
+            Value::unit()
+        })
+    }
+}
+
+/// The stack size of the threads evaluating the program.
+const STACK_SIZE: usize = 256 << 20;
+
+fn main() {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .stack_size(STACK_SIZE)
+        .build()
+        .unwrap();
+    let start = Instant::now();
+    let result = pool.install(program);
+    let elapsed = start.elapsed();
+    match result {
+        Ok(value) => println!("Result: {}", value),
+        Err(exn) => {
+            eprintln!("Uncaught exception {}", exn);
+            process::exit(1);
+        }
+    }
+    println!("Time: {:?} ({} threads)", elapsed, pool.current_num_threads());
+}
+"#;
+
+/// Returns the name of a variable in Rust.
+fn var_name(var: &str) -> String {
+    format!("v_{}", var.replace('\'', "__"))
+}
+
+/// Returns the name of a constructor in Rust.
+fn ctor_name(ctor: &str) -> String {
+    match ctor {
+        "Self" => "Self_".to_string(),
+        _ => ctor.replace('\'', "__"),
+    }
+}
+
+/// Returns the tuple of the expressions, which has a trailing comma so that singletons are tuples.
+fn tuple(exprs: impl IntoIterator<Item = String>) -> String {
+    let exprs = exprs.into_iter().collect::<Vec<_>>();
+    if exprs.is_empty() {
+        return "()".to_string();
+    }
+    format!("({},)", exprs.join(", "))
+}
+
+/// Returns the operator of a lambda that is a lifted unary operator.
+fn lifted_unary(value: &Value) -> Option<UnaryOp> {
+    match value {
+        Value::Lambda {
+            name: None,
+            pattern,
+            expr,
+            ..
+        } => match (&**pattern, &**expr) {
+            (Pattern::Var(x), Expr::UnaryOp { op, inner }) => match &**inner {
+                Expr::Var(y) if x == y => Some(*op),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns the operator of a lambda that is a lifted binary operator.
+fn lifted_binary(value: &Value) -> Option<BinaryOp> {
+    let (x, inner) = match value {
+        Value::Lambda {
+            name: None,
+            pattern,
+            expr,
+            ..
+        } => match (&**pattern, &**expr) {
+            (Pattern::Var(x), Expr::Value(inner)) => (x, inner),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    match &**inner {
+        Value::Lambda {
+            name: None,
+            pattern,
+            expr,
+            ..
+        } => match (&**pattern, &**expr) {
+            (Pattern::Var(y), Expr::BinaryOp { op, lhs, rhs }) if x != y => {
+                match (&**lhs, &**rhs) {
+                    (Expr::Var(l), Expr::Var(r)) if l == x && r == y => Some(*op),
+                    _ => None,
+                }
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn binary_op(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Or => "or",
+        BinaryOp::And => "and",
+        BinaryOp::Xor => "xor",
+        BinaryOp::Plus => "plus",
+        BinaryOp::Minus => "minus",
+        BinaryOp::Times => "times",
+        BinaryOp::Over => "over",
+        BinaryOp::Div => "div",
+        BinaryOp::Mod => "modulo",
+        BinaryOp::Quot => "quot",
+        BinaryOp::Rem => "rem",
+        BinaryOp::Equal => "equal",
+        BinaryOp::NotEqual => "not_equal",
+        BinaryOp::Less => "less",
+        BinaryOp::Le => "le",
+        BinaryOp::Greater => "greater",
+        BinaryOp::Ge => "ge",
+    }
+}
+
+fn unary_op(op: UnaryOp, inner: String) -> String {
+    match op {
+        UnaryOp::Not => format!("not({})", inner),
+        UnaryOp::Neg => format!("neg({})", inner),
+        UnaryOp::Proj(i) => format!("proj({}, {})", inner, i),
+    }
+}
+
+/// The continuation of a matcher, given the scrutinee of each bound variable.
+type Success<'a> = dyn Fn(&[(Var, String)]) -> String + 'a;
+
+#[derive(Debug, Default)]
+struct Transpiler {
+    /// Maps each constructor to the enum of its datatype, and whether it takes an argument.
+    ctors: HashMap<Ctor, (String, bool)>,
+    /// The number of generated names.
+    names: usize,
+}
+
+impl Transpiler {
+    /// Returns a fresh name.
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.names += 1;
+        format!("{}{}", prefix, self.names)
+    }
+
+    fn ctor(&self, ctor: &Ctor) -> Result<(String, bool), CompileError> {
+        self.ctors
+            .get(ctor)
+            .cloned()
+            .ok_or_else(|| CompileError::Undeclared(ctor.clone()))
+    }
+
+    /// Returns the code that matches each scrutinee in `todo`, the last first, against its
+    /// pattern, and then runs `success` with the scrutinee of each bound variable.  The
+    /// scrutinees are expressions of type `&Value`.  The code falls through if the match fails.
+    fn matcher(
+        &mut self,
+        mut todo: Vec<(&Pattern, String)>,
+        mut binds: Vec<(Var, String)>,
+        success: &Success,
+    ) -> Result<String, CompileError> {
+        let (pattern, s) = match todo.pop() {
+            Some(next) => next,
+            None => return Ok(success(&binds)),
+        };
+        let code = match pattern {
+            Pattern::Wildcard => self.matcher(todo, binds, success)?,
+            Pattern::Var(var) => {
+                binds.push((var.clone(), s));
+                self.matcher(todo, binds, success)?
+            }
+            Pattern::Integer(i) => format!(
+                "if let Value::Int({}) = {} {{ {} }}",
+                i,
+                s,
+                self.matcher(todo, binds, success)?
+            ),
+            Pattern::Boolean(b) => format!(
+                "if let Value::Bool({}) = {} {{ {} }}",
+                b,
+                s,
+                self.matcher(todo, binds, success)?
+            ),
+            Pattern::Char(c) => format!(
+                "if let Value::Char({:?}) = {} {{ {} }}",
+                c,
+                s,
+                self.matcher(todo, binds, success)?
+            ),
+            Pattern::String(string) => {
+                let t = self.fresh("t");
+                format!(
+                    "if let Value::Str({}) = {} {{ if &**{} == {:?} {{ {} }} }}",
+                    t,
+                    s,
+                    t,
+                    string,
+                    self.matcher(todo, binds, success)?
+                )
+            }
+            Pattern::Tuple(patterns) => {
+                let t = self.fresh("t");
+                for (i, pattern) in patterns.iter().enumerate().rev() {
+                    todo.push((pattern, format!("(&{}[{}])", t, i)));
+                }
+                format!(
+                    "if let Value::Tuple({}) = {} {{ if {}.len() == {} {{ {} }} }}",
+                    t,
+                    s,
+                    t,
+                    patterns.len(),
+                    self.matcher(todo, binds, success)?
+                )
+            }
+            Pattern::Ctor { ctor, inner } => {
+                let (ty, arg) = self.ctor(ctor)?;
+                let d = self.fresh("d");
+                let variant = match (inner, arg) {
+                    (None, false) => ctor_name(ctor),
+                    (Some(inner), true) => {
+                        let c = self.fresh("c");
+                        todo.push((inner, c.clone()));
+                        format!("{}({})", ctor_name(ctor), c)
+                    }
+                    // The constructor is applied to a wrong number of arguments, so nothing
+                    // matches.
+                    _ => return Ok(String::new()),
+                };
+                format!(
+                    "if let Value::{}({}) = {} {{ if let {}::{} = &**{} {{ {} }} }}",
+                    ty,
+                    d,
+                    s,
+                    ty,
+                    variant,
+                    d,
+                    self.matcher(todo, binds, success)?
+                )
+            }
+            Pattern::As { var, inner } => {
+                binds.push((var.clone(), s.clone()));
+                todo.push((inner, s));
+                self.matcher(todo, binds, success)?
+            }
+            // Tries `lhs`, and then `rhs` if the rest of the match fails.
+            Pattern::Or { lhs, rhs } => {
+                let mut lhs_todo = todo.clone();
+                lhs_todo.push((lhs, s.clone()));
+                todo.push((rhs, s));
+                let lhs = self.matcher(lhs_todo, binds.clone(), success)?;
+                lhs + &self.matcher(todo, binds, success)?
+            }
+        };
+        Ok(code)
+    }
+
+    /// Returns an expression of type `Option<_>` with the tuple of the values bound to the
+    /// variables of the pattern, if the value of `scrutinee` matches it.
+    fn bindings(&mut self, pattern: &Pattern, scrutinee: &str) -> Result<String, CompileError> {
+        let label = self.fresh("'m");
+        let vars = pattern.vars();
+        let success = |binds: &[(Var, String)]| {
+            let values = vars.iter().map(|var| {
+                let (_, s) = binds.iter().rev().find(|(v, _)| v == var).unwrap();
+                format!("{}.clone()", s)
+            });
+            format!("break {} Some({});", label, tuple(values))
+        };
+        let code = self.matcher(
+            vec![(pattern, format!("(&{})", scrutinee))],
+            vec![],
+            &success,
+        )?;
+        Ok(format!("({}: {{ {} None }})", label, code))
+    }
+
+    /// Returns the statements binding the variables of the pattern to the value of `scrutinee`,
+    /// a variable, which panic if it does not match.
+    fn bind(&mut self, pattern: &Pattern, scrutinee: &str) -> Result<String, CompileError> {
+        if let Pattern::Var(var) = pattern {
+            return Ok(format!("let {} = {};", var_name(var), scrutinee));
+        }
+        let vars = pattern.vars().into_iter().map(|var| var_name(&var));
+        Ok(format!(
+            "let Some({}) = {} else {{ panic!(\"{{}} does not match the pattern\", {}) }};",
+            tuple(vars),
+            self.bindings(pattern, scrutinee)?,
+            scrutinee
+        ))
+    }
+
+    /// Returns the statements evaluating the bindings of a let in sequence.
+    fn binds(&mut self, binds: &[Bind]) -> Result<String, CompileError> {
+        let mut code = String::new();
+        for bind in binds {
+            let s = self.fresh("s");
+            code += &format!("let {} = {};\n", s, self.expr(&bind.expr)?);
+            code += &self.bind(&bind.pattern, &s)?;
+            code += "\n";
+        }
+        Ok(code)
+    }
+
+    /// Returns a block evaluating the first arm that matches the value of `scrutinee`, a variable,
+    /// or else `otherwise`.
+    fn arms(
+        &mut self,
+        arms: &[Arm],
+        scrutinee: &str,
+        otherwise: &str,
+    ) -> Result<String, CompileError> {
+        let label = self.fresh("'a");
+        let mut code = format!("{}: {{\n", label);
+        for arm in arms {
+            let vars = arm.pattern.vars().into_iter().map(|var| var_name(&var));
+            // Parenthesized, as a block after a labeled break reads like a labeled loop.
+            let body = format!("break {} ({});", label, self.expr(&arm.expr)?);
+            let body = match &arm.guard {
+                Some(guard) => format!("if test({}) {{ {} }}", self.expr(guard)?, body),
+                None => body,
+            };
+            code += &format!(
+                "if let Some({}) = {} {{\n{}\n}}\n",
+                tuple(vars),
+                self.bindings(&arm.pattern, scrutinee)?,
+                body
+            );
+        }
+        code += otherwise;
+        code += "\n}";
+        Ok(code)
+    }
+
+    /// Returns the statements cloning the free variables of a function, so that its closure can
+    /// capture them.
+    fn captures(&self, expr: &Expr, bound: &[Var]) -> String {
+        let mut free = expr
+            .free_vars()
+            .into_iter()
+            .filter(|var| !bound.contains(var))
+            .collect::<Vec<_>>();
+        free.sort();
+        free.iter()
+            .map(|var| format!("let {0} = {0}.clone();\n", var_name(var)))
+            .collect()
+    }
+
+    /// Returns the parameter and the body of the closure of a function.
+    fn function(
+        &mut self,
+        pattern: &Pattern,
+        expr: &Expr,
+    ) -> Result<(String, String), CompileError> {
+        let body = self.expr(expr)?;
+        if let Pattern::Var(var) = pattern {
+            return Ok((var_name(var), format!("Ok({})", body)));
+        }
+        let arg = self.fresh("arg");
+        let bind = self.bind(pattern, &arg)?;
+        Ok((arg, format!("{}\nOk({})", bind, body)))
+    }
+
+    fn value(&mut self, value: &Value) -> Result<String, CompileError> {
+        let code = match value {
+            Value::Integer(i) => format!("Value::Int({})", i),
+            Value::Float(x) if x.is_finite() => format!("Value::Float({:?})", x),
+            Value::Float(x) => format!("Value::Float(f64::from_bits({:#x}))", x.to_bits()),
+            Value::Boolean(b) => format!("Value::Bool({})", b),
+            Value::String(s) => format!("Value::str({:?})", s),
+            Value::Char(c) => format!("Value::Char({:?})", c),
+            Value::Tuple(values) => {
+                let values = values
+                    .iter()
+                    .map(|value| self.value(value))
+                    .collect::<Result<Vec<_>, _>>()?;
+                format!("Value::tuple(vec![{}])", values.join(", "))
+            }
+            Value::Ctor { ctor, inner } => {
+                let (ty, arg) = self.ctor(ctor)?;
+                match (inner, arg) {
+                    (Some(inner), _) => {
+                        let inner = self.value(inner)?;
+                        self.construct(&ty, ctor, inner)
+                    }
+                    (None, false) => {
+                        format!("Value::{}(Arc::new({}::{}))", ty, ty, ctor_name(ctor))
+                    }
+                    (None, true) => format!(
+                        "Value::fun(|arg| Ok({}))",
+                        self.construct(&ty, ctor, "arg".to_string())
+                    ),
+                }
+            }
+            Value::Lambda {
+                name,
+                pattern,
+                expr,
+                ..
+            } => {
+                let bound = name
+                    .iter()
+                    .cloned()
+                    .chain(pattern.vars())
+                    .collect::<Vec<_>>();
+                let captures = self.captures(expr, &bound);
+                let (param, body) = self.function(pattern, expr)?;
+                let closure = match name {
+                    Some(name) => format!(
+                        "fix(move |{}: &Value, {}: Value| -> R {{\n{}\n}})",
+                        var_name(name),
+                        param,
+                        body
+                    ),
+                    None => format!("Value::fun(move |{}: Value| -> R {{\n{}\n}})", param, body),
+                };
+                format!("{{\n{}{}\n}}", captures, closure)
+            }
+            Value::Native { .. } => unreachable!("native functions arise only from evaluation"),
+        };
+        Ok(code)
+    }
+
+    /// Returns the value of an applied constructor.
+    fn construct(&self, ty: &str, ctor: &Ctor, inner: String) -> String {
+        format!(
+            "Value::{}(Arc::new({}::{}({})))",
+            ty,
+            ty,
+            ctor_name(ctor),
+            inner
+        )
+    }
+
+    /// Returns tasks evaluating the expressions in parallel.
+    fn tasks<'e>(&mut self, exprs: impl Iterator<Item = &'e Expr>) -> Result<String, CompileError> {
+        let tasks = exprs
+            .map(|expr| {
+                Ok(format!(
+                    "Box::new(|| -> R {{ Ok({}) }}) as Task",
+                    self.expr(expr)?
+                ))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(format!("par(vec![{}])?", tasks.join(",\n")))
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<String, CompileError> {
+        let code = match expr {
+            Expr::Var(var) | Expr::Resolved { var, .. } => format!("{}.clone()", var_name(var)),
+            Expr::Value(value) => self.value(value)?,
+            Expr::UnaryOp { op, inner } => unary_op(*op, self.expr(inner)?),
+            Expr::BinaryOp { op, lhs, rhs } => {
+                format!(
+                    "{}({}, {})",
+                    binary_op(*op),
+                    self.expr(lhs)?,
+                    self.expr(rhs)?
+                )
+            }
+            Expr::SeqTuple(exprs) => {
+                let exprs = exprs
+                    .iter()
+                    .map(|expr| self.expr(expr))
+                    .collect::<Result<Vec<_>, _>>()?;
+                format!("Value::tuple(vec![{}])", exprs.join(", "))
+            }
+            Expr::ParTuple(exprs) if exprs.is_empty() => "Value::unit()".to_string(),
+            Expr::ParTuple(exprs) => format!("Value::tuple({})", self.tasks(exprs.iter())?),
+            Expr::Case { inner, arms, .. } => {
+                let s = self.fresh("s");
+                let inner = self.expr(inner)?;
+                let otherwise = format!("panic!(\"no arm matches {{}}\", {})", s);
+                let arms = self.arms(arms, &s, &otherwise)?;
+                format!("{{\nlet {} = {};\n{}\n}}", s, inner, arms)
+            }
+            Expr::Ite { cond, lhs, rhs } => format!(
+                "if test({}) {{\n{}\n}} else {{\n{}\n}}",
+                self.expr(cond)?,
+                self.expr(lhs)?,
+                self.expr(rhs)?
+            ),
+            Expr::AndAlso { lhs, rhs } => format!(
+                "Value::Bool(test({}) && test({}))",
+                self.expr(lhs)?,
+                self.expr(rhs)?
+            ),
+            Expr::OrElse { lhs, rhs } => format!(
+                "Value::Bool(test({}) || test({}))",
+                self.expr(lhs)?,
+                self.expr(rhs)?
+            ),
+            Expr::Raise { inner, .. } => format!("return Err({})", self.expr(inner)?),
+            Expr::Handle { inner, arms } => {
+                let s = self.fresh("s");
+                let inner = self.expr(inner)?;
+                let otherwise = format!("return Err({})", s);
+                format!(
+                    "match (|| -> R {{ Ok({}) }})() {{\nOk(value) => value,\nErr({}) => {}\n}}",
+                    inner,
+                    s,
+                    self.arms(arms, &s, &otherwise)?
+                )
+            }
+            Expr::Assert { inner, .. } => format!(
+                "{{\nif !test({}) {{ return Err({}) }}\nValue::unit()\n}}",
+                self.expr(inner)?,
+                self.value(&Value::Ctor {
+                    ctor: ASSERTION_FAILED.into(),
+                    inner: None,
+                })?
+            ),
+            Expr::WithCost { inner, .. } | Expr::Tick { inner, .. } => self.expr(inner)?,
+            Expr::App { lhs, rhs, .. } => match &**lhs {
+                // Applies operators and constructors directly.
+                Expr::App {
+                    lhs: op, rhs: arg, ..
+                } => match &**op {
+                    Expr::Value(op) if lifted_binary(op).is_some() => format!(
+                        "{}({}, {})",
+                        binary_op(lifted_binary(op).unwrap()),
+                        self.expr(arg)?,
+                        self.expr(rhs)?
+                    ),
+                    _ => format!("apply({}, {})?", self.expr(lhs)?, self.expr(rhs)?),
+                },
+                Expr::Value(op) if lifted_unary(op).is_some() => {
+                    unary_op(lifted_unary(op).unwrap(), self.expr(rhs)?)
+                }
+                Expr::Value(value) => match &**value {
+                    Value::Ctor { ctor, inner: None } if self.ctor(ctor)?.1 => {
+                        let (ty, _) = self.ctor(ctor)?;
+                        let inner = self.expr(rhs)?;
+                        self.construct(&ty, ctor, inner)
+                    }
+                    _ => format!("apply({}, {})?", self.expr(lhs)?, self.expr(rhs)?),
+                },
+                _ => format!("apply({}, {})?", self.expr(lhs)?, self.expr(rhs)?),
+            },
+            Expr::Let { binds, expr } => {
+                format!("{{\n{}{}\n}}", self.binds(binds)?, self.expr(expr)?)
+            }
+            Expr::ParLet { binds, expr } => {
+                let values = self.fresh("values");
+                let mut code = format!(
+                    "{{\nlet mut {} = {}.into_iter();\n",
+                    values,
+                    self.tasks(binds.iter().map(|bind| &*bind.expr))?
+                );
+                for bind in binds {
+                    let s = self.fresh("s");
+                    code += &format!("let {} = {}.next().unwrap();\n", s, values);
+                    code += &self.bind(&bind.pattern, &s)?;
+                    code += "\n";
+                }
+                format!("{}{}\n}}", code, self.expr(expr)?)
+            }
+        };
+        Ok(code)
+    }
+
+    /// Returns the declarations of the values, with an enum for each datatype.
+    fn types(&mut self, program: &Program) -> String {
+        let mut datatypes = vec![];
+        // The builtin exceptions, of which only failed assertions are raised.
+        let mut exns = vec![
+            (ASSERTION_FAILED.to_string(), false),
+            (COST_CONTRACT_VIOLATED.to_string(), true),
+        ];
+        for (ctor, arg) in exns.iter() {
+            self.ctors.insert(ctor.clone(), (EXN.to_string(), *arg));
+        }
+        for decl in program.decls.iter() {
+            match decl {
+                Decl::Datatype { name, ctors, .. } => {
+                    let ty = format!("T_{}", name.replace('\'', "__"));
+                    let ctors = ctors
+                        .iter()
+                        .map(|(ctor, arg)| (ctor.clone(), arg.is_some()))
+                        .collect::<Vec<_>>();
+                    for (ctor, arg) in ctors.iter() {
+                        self.ctors.insert(ctor.clone(), (ty.clone(), *arg));
+                    }
+                    datatypes.push((ty, ctors));
+                }
+                Decl::Exception { ctor, ty } => {
+                    self.ctors
+                        .insert(ctor.clone(), (EXN.to_string(), ty.is_some()));
+                    exns.push((ctor.clone(), ty.is_some()));
+                }
+                Decl::Val { .. } | Decl::Fun { .. } => {}
+            }
+        }
+        datatypes.push((EXN.to_string(), exns));
+
+        let mut code = String::new();
+        code += "#[derive(Clone, PartialEq)]\nenum Value {\nInt(i64),\nFloat(f64),\nBool(bool),\n";
+        code += "Str(Arc<str>),\nChar(char),\nTuple(Arc<Vec<Value>>),\nFun(Arc<Func>),\n";
+        for (ty, _) in datatypes.iter() {
+            code += &format!("{0}(Arc<{0}>),\n", ty);
+        }
+        code += "}\n\n";
+
+        code += "impl Value {\n/// Returns whether the value is an applied constructor.\n";
+        code += "fn is_applied(&self) -> bool {\nmatch self {\n";
+        for (ty, _) in datatypes.iter() {
+            code += &format!("Value::{}(inner) => inner.is_applied(),\n", ty);
+        }
+        code += "_ => false,\n}\n}\n}\n\n";
+
+        code += "impl fmt::Display for Value {\n";
+        code += "fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {\nmatch self {\n";
+        code += "Value::Int(i) => write!(f, \"{}\", i),\n";
+        code += "Value::Float(x) => write!(f, \"{:?}\", x),\n";
+        code += "Value::Bool(b) => write!(f, \"{}\", b),\n";
+        code += "Value::Str(s) => write!(f, \"{:?}\", s),\n";
+        code += "Value::Char(c) => write!(f, \"#{:?}\", c.to_string()),\n";
+        code += "Value::Tuple(values) => {\nwrite!(f, \"(\")?;\n";
+        code += "for (i, value) in values.iter().enumerate() {\n";
+        code += "if i > 0 {\nwrite!(f, \", \")?;\n}\nwrite!(f, \"{}\", value)?;\n}\n";
+        code += "write!(f, \")\")\n}\n";
+        code += "Value::Fun(_) => write!(f, \"<fun>\"),\n";
+        for (ty, _) in datatypes.iter() {
+            code += &format!("Value::{}(inner) => inner.fmt(f),\n", ty);
+        }
+        code += "}\n}\n}\n";
+
+        for (ty, ctors) in datatypes.iter() {
+            code += &format!("\n#[derive(Clone, PartialEq)]\nenum {} {{\n", ty);
+            for (ctor, arg) in ctors.iter() {
+                let payload = if *arg { "(Value)" } else { "" };
+                code += &format!("{}{},\n", ctor_name(ctor), payload);
+            }
+            code += "}\n\n";
+
+            code += &format!(
+                "impl {} {{\nfn is_applied(&self) -> bool {{\nmatch self {{\n",
+                ty
+            );
+            for (ctor, arg) in ctors.iter() {
+                let (payload, applied) = if *arg { ("(_)", true) } else { ("", false) };
+                code += &format!("{}::{}{} => {},\n", ty, ctor_name(ctor), payload, applied);
+            }
+            code += "}\n}\n}\n\n";
+
+            code += &format!("impl fmt::Display for {} {{\n", ty);
+            code += "fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {\nmatch self {\n";
+            for (ctor, arg) in ctors.iter() {
+                code += &if *arg {
+                    format!(
+                        "{}::{}(inner) => fmt_ctor(f, {:?}, inner),\n",
+                        ty,
+                        ctor_name(ctor),
+                        ctor
+                    )
+                } else {
+                    format!("{}::{} => write!(f, {:?}),\n", ty, ctor_name(ctor), ctor)
+                };
+            }
+            code += "}\n}\n}\n";
+        }
+        code
+    }
+
+    /// Returns the functions building and taking apart the lists of `explode` and `implode`,
+    /// which are those of the constructors `Nil` and `Cons`.
+    fn lists(&self) -> String {
+        match (self.ctors.get("Nil"), self.ctors.get("Cons")) {
+            (Some((nil, false)), Some((cons, true))) => format!(
+                "fn nil() -> Value {{\nValue::{0}(Arc::new({0}::Nil))\n}}\n\n\
+                 fn cons(head: Value, tail: Value) -> Value {{\n\
+                 Value::{1}(Arc::new({1}::Cons(Value::tuple(vec![head, tail]))))\n}}\n\n\
+                 fn uncons(list: &Value) -> Option<(Value, Value)> {{\n\
+                 if let Value::{1}(list) = list {{\n\
+                 if let {1}::Cons(Value::Tuple(pair)) = &**list {{\n\
+                 if pair.len() == 2 {{\nreturn Some((pair[0].clone(), pair[1].clone()));\n}}\n\
+                 }}\n}}\nNone\n}}\n",
+                nil, cons
+            ),
+            _ => "fn nil() -> Value {\npanic!(\"lists need the constructors Nil and Cons\")\n}\n\n\
+                  fn cons(_: Value, _: Value) -> Value {\nnil()\n}\n\n\
+                  fn uncons(_: &Value) -> Option<(Value, Value)> {\nnil();\nNone\n}\n"
+                .to_string(),
+        }
+    }
+
+    /// Returns the function evaluating the program.
+    fn program(&mut self, program: &Program) -> Result<String, CompileError> {
+        // Binds the builtin functions the program uses, checking that the other variables are
+        // bound.
+        let mut bound = vec![];
+        let mut builtins = vec![];
+        let mut check = |free: Vec<Var>, bound: &[Var]| {
+            for var in free {
+                if bound.contains(&var) || builtins.contains(&var) {
+                    continue;
+                }
+                if !BUILTINS.contains(&var.as_str()) {
+                    return Err(CompileError::Unbound(var));
+                }
+                builtins.push(var);
+            }
+            Ok(())
+        };
+        for decl in program.decls.iter() {
+            match decl {
+                Decl::Val { var, expr } => {
+                    check(expr.free_vars().into_iter().collect(), &bound)?;
+                    bound.push(var.clone());
+                }
+                Decl::Fun {
+                    name,
+                    pattern,
+                    expr,
+                } => {
+                    bound.push(name.clone());
+                    let mut inner = bound.clone();
+                    inner.extend(pattern.vars());
+                    check(expr.free_vars().into_iter().collect(), &inner)?;
+                }
+                Decl::Datatype { .. } | Decl::Exception { .. } => {}
+            }
+        }
+        if let Some(expr) = &program.expr {
+            check(expr.free_vars().into_iter().collect(), &bound)?;
+        }
+        builtins.sort();
+
+        let mut code = "fn program() -> R {\n".to_string();
+        for builtin in builtins {
+            code += &format!("let {} = builtins::{}();\n", var_name(&builtin), builtin);
+        }
+        for decl in program.decls.iter() {
+            match decl {
+                Decl::Val { var, expr } => {
+                    code += &format!("let {} = {};\n", var_name(var), self.expr(expr)?);
+                }
+                Decl::Fun {
+                    name,
+                    pattern,
+                    expr,
+                } => {
+                    let lambda = Value::Lambda {
+                        name: Some(name.clone()),
+                        pattern: pattern.clone(),
+                        expr: expr.clone(),
+                        captures: None,
+                    };
+                    code += &format!("let {} = {};\n", var_name(name), self.value(&lambda)?);
+                }
+                Decl::Datatype { .. } | Decl::Exception { .. } => {}
+            }
+        }
+        match &program.expr {
+            Some(expr) => code += &format!("Ok({})\n}}\n", self.expr(expr)?),
+            None => code += "Ok(Value::unit())\n}\n",
+        }
+        Ok(code)
+    }
+}
+
+/// Indents the code by the nesting of its brackets.  The brackets opened on a line indent the
+/// lines up to where they are closed by one level.
+fn indent(code: &str) -> String {
+    let mut result = String::new();
+    // The levels of the lines in the unclosed brackets, which are those of the lines where they
+    // are opened plus one.
+    let mut levels: Vec<usize> = vec![];
+    let mut in_string = false;
+    for line in code.lines() {
+        let line = line.trim();
+        // The line is at the level of the brackets it does not start by closing.
+        let closed = line
+            .chars()
+            .take_while(|c| matches!(c, '}' | ')' | ']'))
+            .count();
+        let level = match levels.len().checked_sub(closed + 1) {
+            Some(i) => levels[i],
+            None => 0,
+        };
+        if !line.is_empty() {
+            result += &"    ".repeat(level);
+        }
+        result += line;
+        result += "\n";
+
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if in_string => {
+                    chars.next();
+                }
+                '"' => in_string = !in_string,
+                // Skips character literals, which may be quotes or brackets, but not labels.
+                '\'' if !in_string => {
+                    let rest = chars.clone().collect::<Vec<_>>();
+                    let len = if rest.first() == Some(&'\\') {
+                        rest.iter().skip(2).position(|c| *c == '\'').map(|i| i + 3)
+                    } else if rest.get(1) == Some(&'\'') {
+                        Some(2)
+                    } else {
+                        None
+                    };
+                    for _ in 0..len.unwrap_or(0) {
+                        chars.next();
+                    }
+                }
+                '{' | '(' | '[' if !in_string => levels.push(level + 1),
+                '}' | ')' | ']' if !in_string => {
+                    levels.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+    result
+}
+
+/// Compiles a program to a Rust source file.
+pub(crate) fn emit_rust(program: &Program) -> Result<String, CompileError> {
+    let mut transpiler = Transpiler::default();
+    let types = transpiler.types(program);
+    let lists = transpiler.lists();
+    let main = transpiler.program(program)?;
+
+    let mut code = String::new();
+    code += "//! Compiled from SPARC.\n\n";
+    code += "#![allow(non_camel_case_types, non_snake_case, irrefutable_let_patterns)]\n";
+    code += "#![allow(unused, unreachable_code, unreachable_patterns)]\n\n";
+    code +=
+        "use std::cmp::Ordering;\nuse std::convert::TryFrom;\nuse std::fmt;\nuse std::process;\n";
+    code += "use std::sync::{Arc, Weak};\nuse std::time::Instant;\n\n";
+    code += &indent(&types);
+    code += "\n";
+    code += &indent(&lists);
+    code += PRELUDE;
+    code += "\n";
+    code += &indent(&main);
+    Ok(code)
+}
@@ -0,0 +1,928 @@
+//! The virtual machine executing bytecode.
+//!
+//! The machine keeps the frames of the active functions in a single vector of slots, and the
+//! values of the expressions being evaluated, with their costs, on a stack.  Integers, floats,
+//! booleans, characters and the unit are kept unboxed there, so that computing with them
+//! allocates nothing, and tuples passed to functions binding their components are not built.
+//! Applications of compiled closures do not recurse on the native stack, and those in tail
+//! position replace the frame of the caller.  Functions created by the other evaluators are
+//! applied with `Env::apply`.
+//!
+//! Parallel expressions are evaluated sequentially, from left to right, which reports the same
+//! errors as the leftmost-first rule of `Env::eval_expr`.  The evaluation is not traced.
+
+use std::borrow::Cow;
+use std::cmp;
+use std::mem;
+use std::sync::Arc;
+
+use crate::bytecode::{self, Func, Instr, Slot, SlotPattern, Template};
+use crate::semantics::{Counters, EResult, Env, Err, Res, Value};
+use crate::syntax::{CostMeasure, Expr, Span};
+
+/// A value on the stack or in a slot.
+#[derive(Debug, Clone)]
+enum Operand {
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Char(char),
+    Unit,
+    /// Any other value.
+    Shared(Arc<Value>),
+}
+
+impl Operand {
+    /// Unboxes the value if it is a scalar, and allocates it otherwise.
+    fn new(value: Value) -> Self {
+        match value {
+            Value::Integer(i) => Operand::Integer(i),
+            Value::Float(f) => Operand::Float(f),
+            Value::Boolean(b) => Operand::Boolean(b),
+            Value::Char(c) => Operand::Char(c),
+            Value::Tuple(ref values) if values.is_empty() => Operand::Unit,
+            value => Operand::Shared(Arc::new(value)),
+        }
+    }
+
+    /// Unboxes the value if it is a scalar, and shares it otherwise.
+    fn load(value: &Arc<Value>) -> Self {
+        match &**value {
+            Value::Integer(i) => Operand::Integer(*i),
+            Value::Float(f) => Operand::Float(*f),
+            Value::Boolean(b) => Operand::Boolean(*b),
+            Value::Char(c) => Operand::Char(*c),
+            Value::Tuple(ref values) if values.is_empty() => Operand::Unit,
+            _ => Operand::Shared(value.clone()),
+        }
+    }
+
+    /// Returns the value, which is borrowed unless it is a scalar.
+    fn value(&self) -> Cow<'_, Value> {
+        match self {
+            Operand::Integer(i) => Cow::Owned(Value::Integer(*i)),
+            Operand::Float(f) => Cow::Owned(Value::Float(*f)),
+            Operand::Boolean(b) => Cow::Owned(Value::Boolean(*b)),
+            Operand::Char(c) => Cow::Owned(Value::Char(*c)),
+            Operand::Unit => Cow::Owned(Value::unit()),
+            Operand::Shared(value) => Cow::Borrowed(value),
+        }
+    }
+
+    /// Returns the value, allocating it if it is a scalar.
+    fn into_shared(self) -> Arc<Value> {
+        match self {
+            Operand::Shared(value) => value,
+            scalar => Arc::new(scalar.value().into_owned()),
+        }
+    }
+
+    fn coerce_bool(&self) -> Option<bool> {
+        match self {
+            Operand::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+/// An active function.
+#[derive(Debug)]
+struct Frame {
+    /// The closure, which is a `Value::Closure`.
+    closure: Arc<Value>,
+    func: Arc<Func>,
+    /// Where to resume when the function it calls returns.
+    pc: usize,
+    /// The index of its first slot.
+    base: usize,
+    /// The height of the stack when it was entered.
+    stack: usize,
+    /// The cost of the application, which is added to that of the function's body.
+    work: u64,
+    span: u64,
+    counters: Counters,
+}
+
+/// A value on the stack, with the cost of evaluating it.
+#[derive(Debug)]
+struct Entry {
+    result: Operand,
+    work: u64,
+    span: u64,
+    counters: Counters,
+    /// Whether it is evaluated in parallel with what is evaluated above it, rather than before.
+    par: bool,
+}
+
+/// An active handler.
+#[derive(Debug)]
+struct Handler {
+    frames: usize,
+    stack: usize,
+    caught: usize,
+    target: usize,
+}
+
+/// An exception caught by a handler whose arms are being tried.
+#[derive(Debug)]
+struct Caught {
+    location: Span,
+    work: u64,
+    span: u64,
+    counters: Counters,
+}
+
+#[derive(Debug)]
+struct Vm {
+    stack: Vec<Entry>,
+    /// The slots of the frames.  A slot holds the unit until its variable is bound.
+    slots: Vec<Operand>,
+    frames: Vec<Frame>,
+    handlers: Vec<Handler>,
+    caught: Vec<Caught>,
+    /// The bindings of the pattern being matched.
+    bindings: Vec<(usize, Operand)>,
+    /// The components of the tuple being built.
+    components: Vec<Operand>,
+}
+
+impl Vm {
+    fn new() -> Self {
+        Self {
+            stack: vec![],
+            slots: vec![],
+            frames: vec![],
+            handlers: vec![],
+            caught: vec![],
+            bindings: vec![],
+            components: vec![],
+        }
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().unwrap()
+    }
+
+    fn push(&mut self, result: Operand, work: u64, span: u64, counters: Counters) {
+        self.stack.push(Entry {
+            result,
+            work,
+            span,
+            counters,
+            par: false,
+        });
+    }
+
+    fn pop(&mut self) -> Entry {
+        self.stack.pop().unwrap()
+    }
+
+    fn top(&self) -> &Entry {
+        self.stack.last().unwrap()
+    }
+
+    fn marker(&mut self, work: u64, span: u64, counters: Counters) {
+        self.push(Operand::Unit, work, span, counters);
+    }
+
+    fn load(&self, slot: Slot) -> Operand {
+        let frame = self.frame();
+        match slot {
+            Slot::Local(i) => self.slots[frame.base + i].clone(),
+            Slot::Captured(i) => match &*frame.closure {
+                Value::Closure { captured, .. } => Operand::load(&captured[i]),
+                _ => unreachable!("frames are of closures"),
+            },
+            Slot::This => Operand::Shared(frame.closure.clone()),
+        }
+    }
+
+    fn instantiate(&self, template: &Template) -> Operand {
+        let shared = |template| self.instantiate(template).into_shared();
+        let value = match template {
+            Template::Const(value) => return Operand::load(value),
+            Template::Tuple(templates) => Value::Tuple(templates.iter().map(shared).collect()),
+            Template::Ctor { ctor, inner } => Value::Ctor {
+                ctor: ctor.clone(),
+                inner: Some(shared(inner)),
+            },
+            Template::Native { func, args } => Value::Native {
+                func: func.clone(),
+                args: args.iter().map(shared).collect(),
+            },
+            Template::Closure { func, captures } => Value::Closure {
+                func: func.clone(),
+                captured: captures
+                    .iter()
+                    .map(|slot| self.load(*slot).into_shared())
+                    .collect(),
+            },
+        };
+        Operand::Shared(Arc::new(value))
+    }
+
+    /// Matches the value, which is `shared` unless it is a scalar, against the pattern, collecting
+    /// the bindings.  Mirrors `Env::eval_pattern`.
+    fn match_pattern(
+        &mut self,
+        pattern: &SlotPattern,
+        value: &Value,
+        shared: Option<&Arc<Value>>,
+    ) -> Result<(), Err> {
+        let operand = || match shared {
+            Some(shared) => Operand::load(shared),
+            None => Operand::new(value.clone()),
+        };
+        match (pattern, value) {
+            (SlotPattern::Wildcard, _) => Ok(()),
+            (SlotPattern::Var { slot, .. }, _) => {
+                self.bindings.push((*slot, operand()));
+                Ok(())
+            }
+            (SlotPattern::Integer(i), Value::Integer(j)) if i == j => Ok(()),
+            (SlotPattern::Boolean(b), Value::Boolean(c)) if b == c => Ok(()),
+            (SlotPattern::String(s), Value::String(t)) if s == t => Ok(()),
+            (SlotPattern::Char(c), Value::Char(d)) if c == d => Ok(()),
+            (SlotPattern::Tuple(patterns), Value::Tuple(values))
+                if patterns.len() == values.len() =>
+            {
+                for (pattern, value) in patterns.iter().zip(values.iter()) {
+                    self.match_pattern(pattern, value, Some(value))?;
+                }
+                Ok(())
+            }
+            (
+                SlotPattern::Ctor {
+                    ctor: ctor_pattern,
+                    inner: inner_pattern,
+                },
+                Value::Ctor {
+                    ctor: ctor_value,
+                    inner: inner_value,
+                },
+            ) => {
+                if ctor_pattern != ctor_value {
+                    return Err(Err::CtorNotMatched {
+                        ctor_pattern: ctor_pattern.clone(),
+                        ctor_value: ctor_value.clone(),
+                    });
+                }
+                match (inner_pattern, inner_value) {
+                    (None, None) => Ok(()),
+                    (Some(inner_pattern), Some(inner_value)) => {
+                        self.match_pattern(inner_pattern, inner_value, Some(inner_value))
+                    }
+                    _ => Err(Err::PatternNotMatched {
+                        pattern: pattern.to_pattern(),
+                        value: operand().into_shared(),
+                    }),
+                }
+            }
+            (SlotPattern::As { slot, inner, .. }, _) => {
+                self.bindings.push((*slot, operand()));
+                self.match_pattern(inner, value, shared)
+            }
+            (SlotPattern::Or { lhs, rhs }, _) => {
+                let len = self.bindings.len();
+                match self.match_pattern(lhs, value, shared) {
+                    Ok(()) => Ok(()),
+                    Err(_) => {
+                        self.bindings.truncate(len);
+                        self.match_pattern(rhs, value, shared)
+                    }
+                }
+            }
+            _ => Err(Err::PatternNotMatched {
+                pattern: pattern.to_pattern(),
+                value: operand().into_shared(),
+            }),
+        }
+    }
+
+    /// Matches the value against the pattern, binding its variables in the current frame if it
+    /// matches.
+    fn bind(&mut self, pattern: &SlotPattern, value: &Operand) -> Result<(), Err> {
+        let base = self.frame().base;
+        if let SlotPattern::Var { slot, .. } = pattern {
+            self.slots[base + slot] = value.clone();
+            return Ok(());
+        }
+        self.bindings.clear();
+        match value {
+            Operand::Shared(shared) => self.match_pattern(pattern, shared, Some(shared))?,
+            scalar => self.match_pattern(pattern, &scalar.value(), None)?,
+        }
+        for (slot, value) in self.bindings.drain(..) {
+            self.slots[base + slot] = value;
+        }
+        Ok(())
+    }
+
+    /// Enters the closure, binding its parameter to the argument if it is a lambda.
+    fn enter(
+        &mut self,
+        closure: Arc<Value>,
+        arg: Option<Operand>,
+        work: u64,
+        span: u64,
+        counters: Counters,
+    ) -> Result<Arc<Func>, Err> {
+        let func = match &*closure {
+            Value::Closure { func, .. } => func.clone(),
+            _ => unreachable!("only closures are entered"),
+        };
+        let base = self.slots.len();
+        self.slots
+            .resize(base + func.code.names.len(), Operand::Unit);
+        self.frames.push(Frame {
+            closure,
+            func: func.clone(),
+            pc: 0,
+            base,
+            stack: self.stack.len(),
+            work,
+            span,
+            counters,
+        });
+
+        if let (Some(param), Some(arg)) = (&func.param, arg) {
+            self.bind(param, &arg)?;
+        }
+        Ok(func)
+    }
+
+    /// Returns the cost of an exception raised with cost `work`, `span` and `counters`, with that
+    /// of the values on the stack from `stack` up and of the applications of the frames from
+    /// `frames` up, which the exception abandons.
+    fn unwind(
+        &self,
+        stack: usize,
+        frames: usize,
+        mut work: u64,
+        mut span: u64,
+        mut counters: Counters,
+    ) -> (u64, u64, Counters) {
+        let mut frames = self.frames[frames..].iter().rev().peekable();
+        for (i, entry) in self.stack.iter().enumerate().skip(stack).rev() {
+            while let Some(frame) = frames.next_if(|frame| frame.stack > i) {
+                work += frame.work;
+                span += frame.span;
+                counters = frame.counters.clone().then(counters);
+            }
+            work += entry.work;
+            if entry.par {
+                span = cmp::max(span, entry.span);
+                counters = entry.counters.clone().par(counters);
+            } else {
+                span += entry.span;
+                counters = entry.counters.clone().then(counters);
+            }
+        }
+        for frame in frames {
+            work += frame.work;
+            span += frame.span;
+            counters = frame.counters.clone().then(counters);
+        }
+        (work, span, counters)
+    }
+
+    /// Transfers control to the innermost handler, if any, with the exception.  Returns the
+    /// function and the position of the handler's arms.
+    fn raise(
+        &mut self,
+        value: Operand,
+        location: Span,
+        work: u64,
+        span: u64,
+        counters: Counters,
+    ) -> Result<(Arc<Func>, usize), Err> {
+        let handler = match self.handlers.pop() {
+            Some(handler) => handler,
+            None => {
+                let (work, span, counters) = self.unwind(0, 0, work, span, counters);
+                return Err(Err::Raise {
+                    value: value.into_shared(),
+                    location,
+                    work,
+                    span,
+                    extras: counters.into(),
+                });
+            }
+        };
+
+        let (work, span, counters) =
+            self.unwind(handler.stack, handler.frames, work, span, counters);
+        self.frames.truncate(handler.frames);
+        let frame = self.frame();
+        let (func, len) = (frame.func.clone(), frame.base + frame.func.code.names.len());
+        self.slots.truncate(len);
+        self.stack.truncate(handler.stack);
+        self.caught.truncate(handler.caught);
+        self.caught.push(Caught {
+            location,
+            work,
+            span,
+            counters: counters.clone(),
+        });
+        self.push(value, work, span, counters);
+        Ok((func, handler.target))
+    }
+
+    /// Pops the `len` components of a tuple into `components`, and returns the cost of the tuple.
+    fn pop_components(&mut self, len: usize, par: bool) -> (u64, u64, Counters) {
+        let mut work = 0;
+        let mut span = 0;
+        let mut counters = Counters::default();
+        self.components.clear();
+        for res in self.stack.drain(self.stack.len() - len..) {
+            self.components.push(res.result);
+            work += res.work;
+            if par {
+                span = cmp::max(span, res.span);
+                counters = counters.par(res.counters);
+            } else {
+                span += res.span;
+                counters = counters.then(res.counters);
+            }
+        }
+        (work + 1, span + 1, counters)
+    }
+
+    /// Prepares the call of a closure with the cost of the application, where `pc` is the position
+    /// after it, and returns the cost of the frame to enter.  In tail position, the frame of the
+    /// caller is left, and the cost of the callee's frame includes that of the caller's and the
+    /// values on its stack, which would only be added to the result.
+    fn prepare_call(
+        &mut self,
+        tail: bool,
+        (work, span, counters): (u64, u64, Counters),
+        pc: usize,
+    ) -> (u64, u64, Counters) {
+        if !tail {
+            self.frames.last_mut().unwrap().pc = pc;
+            return (work, span, counters);
+        }
+
+        let frame = self.frames.pop().unwrap();
+        self.slots.truncate(frame.base);
+        let (mut left_work, mut left_span, mut left_counters) =
+            (frame.work, frame.span, frame.counters);
+        for entry in self.stack.drain(frame.stack..) {
+            debug_assert!(!entry.par, "calls in tail position are not forked");
+            left_work += entry.work;
+            left_span += entry.span;
+            left_counters = left_counters.then(entry.counters);
+        }
+        (
+            left_work + work,
+            left_span + span,
+            left_counters.then(counters),
+        )
+    }
+
+    /// Applies the function to the argument with the cost of the application, where `pc` is the
+    /// position after it.  Returns the function and position to continue at, if the function is
+    /// entered or raises an exception to a handler.  Constructors and native functions are applied
+    /// as `Env::apply` does.
+    fn call(
+        &mut self,
+        lhs: Arc<Value>,
+        arg: Operand,
+        tail: bool,
+        cost: (u64, u64, Counters),
+        pc: usize,
+    ) -> Result<Option<(Arc<Func>, usize)>, Err> {
+        if let Value::Closure { .. } = &*lhs {
+            let (work, span, counters) = self.prepare_call(tail, cost, pc);
+            let func = self.enter(lhs, Some(arg), work, span, counters)?;
+            return Ok(Some((func, 0)));
+        }
+
+        let (work, span, counters) = cost;
+        match &*lhs {
+            Value::Ctor { ctor, inner: None } => {
+                let value = Value::Ctor {
+                    ctor: ctor.clone(),
+                    inner: Some(arg.into_shared()),
+                };
+                self.push(Operand::Shared(Arc::new(value)), work, span, counters);
+                Ok(None)
+            }
+            Value::Native { func, args } => {
+                let mut args = args.clone();
+                args.push(arg.into_shared());
+                if args.len() < func.arity() {
+                    let value = Value::Native {
+                        func: func.clone(),
+                        args,
+                    };
+                    self.push(Operand::Shared(Arc::new(value)), work, span, counters);
+                } else {
+                    let (call_work, call_span) = func.cost(&args);
+                    let result = func.call(&args)?;
+                    self.push(
+                        Operand::load(&result),
+                        work + call_work,
+                        span + call_span,
+                        counters,
+                    );
+                }
+                Ok(None)
+            }
+            _ => match Env::apply(&lhs, arg.into_shared()) {
+                Ok(app) => {
+                    self.push(
+                        Operand::load(&app.result),
+                        work + app.work,
+                        span + app.span,
+                        counters.then(app.extras.counters().clone()),
+                    );
+                    Ok(None)
+                }
+                Err(Err::Raise {
+                    value,
+                    location,
+                    work: raised_work,
+                    span: raised_span,
+                    extras: raised_extras,
+                }) => self
+                    .raise(
+                        Operand::load(&value),
+                        location,
+                        work + raised_work,
+                        span + raised_span,
+                        counters.then(raised_extras.counters().clone()),
+                    )
+                    .map(Some),
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    /// Runs the closure, applied to the argument if it is a lambda, until it returns.
+    fn run(&mut self, closure: Arc<Value>, arg: Option<Arc<Value>>) -> EResult<Arc<Value>> {
+        let mut func = self.enter(
+            closure,
+            arg.as_ref().map(Operand::load),
+            0,
+            0,
+            Counters::default(),
+        )?;
+        let mut pc = 0;
+
+        loop {
+            let instr = &func.code.instrs[pc];
+            pc += 1;
+            match instr {
+                Instr::Load(slot) => {
+                    let value = self.load(*slot);
+                    self.push(value, 1, 1, Counters::default());
+                }
+                Instr::Unbound(var) => return Err(Err::EnvNotFound { var: var.clone() }),
+                Instr::Value(template) => {
+                    let value = self.instantiate(template);
+                    self.push(value, 1, 1, Counters::default());
+                }
+                Instr::UnaryOp(op) => {
+                    let inner = self.pop();
+                    let res = Env::eval_unary_op(*op, &inner.result.value())?;
+                    self.push(
+                        Operand::new(res),
+                        inner.work + 1,
+                        inner.span + 1,
+                        inner.counters,
+                    );
+                }
+                Instr::BinaryOp(op) => {
+                    let rhs = self.pop();
+                    let lhs = self.pop();
+                    let (res, steps) =
+                        Env::eval_binary_op(*op, &lhs.result.value(), &rhs.result.value())?;
+                    self.push(
+                        Operand::new(res),
+                        lhs.work + rhs.work + steps,
+                        cmp::max(lhs.span, rhs.span) + steps,
+                        lhs.counters.par(rhs.counters),
+                    );
+                }
+                Instr::LiftedBinaryOp(op) => {
+                    let rhs = self.pop();
+                    let lhs = self.pop();
+                    let (res, steps) =
+                        Env::eval_binary_op(*op, &lhs.result.value(), &rhs.result.value())?;
+                    // The application, and then the body loading the operands in parallel.
+                    self.push(
+                        Operand::new(res),
+                        lhs.work + rhs.work + 3 + steps,
+                        cmp::max(lhs.span, rhs.span) + 2 + steps,
+                        lhs.counters.par(rhs.counters),
+                    );
+                }
+                Instr::Tuple { len, par } => {
+                    let (work, span, counters) = self.pop_components(*len, *par);
+                    let values = self
+                        .components
+                        .drain(..)
+                        .map(Operand::into_shared)
+                        .collect();
+                    self.push(Operand::new(Value::Tuple(values)), work, span, counters);
+                }
+                Instr::Cost { work, span } => self.marker(*work, *span, Counters::default()),
+                Instr::Step => {
+                    let top = self.stack.last_mut().unwrap();
+                    top.work += 1;
+                    top.span += 1;
+                }
+                Instr::Seq | Instr::Par => {
+                    let top = self.pop();
+                    let below = self.stack.last_mut().unwrap();
+                    let counters = mem::take(&mut below.counters);
+                    below.result = top.result;
+                    below.par = false;
+                    below.work += top.work;
+                    if matches!(instr, Instr::Par) {
+                        below.span = cmp::max(below.span, top.span);
+                        below.counters = counters.par(top.counters);
+                    } else {
+                        below.span += top.span;
+                        below.counters = counters.then(top.counters);
+                    }
+                }
+                Instr::Fork => self.stack.last_mut().unwrap().par = true,
+                Instr::Uncounted => {
+                    let top = self.stack.last_mut().unwrap();
+                    top.work = 0;
+                    top.span = 0;
+                    top.counters = Counters::default();
+                }
+                Instr::Branch { target } => {
+                    let cond = self.pop();
+                    let cond_result =
+                        cond.result
+                            .coerce_bool()
+                            .ok_or_else(|| Err::InvalidIteCond {
+                                cond: cond.result.clone().into_shared(),
+                            })?;
+                    self.marker(cond.work + 1, cond.span + 1, cond.counters);
+                    if !cond_result {
+                        pc = *target;
+                    }
+                }
+                Instr::Jump(target) => pc = *target,
+                Instr::ShortCircuit { or, target } => {
+                    let lhs = self.pop();
+                    let lhs_result =
+                        lhs.result
+                            .coerce_bool()
+                            .ok_or_else(|| Err::InvalidShortCircuitArg {
+                                arg: lhs.result.clone().into_shared(),
+                            })?;
+                    if lhs_result == *or {
+                        self.push(lhs.result, lhs.work + 1, lhs.span + 1, lhs.counters);
+                        pc = *target;
+                    } else {
+                        self.marker(lhs.work + 1, lhs.span + 1, lhs.counters);
+                    }
+                }
+                Instr::CheckBool => {
+                    let rhs = &self.top().result;
+                    rhs.coerce_bool()
+                        .ok_or_else(|| Err::InvalidShortCircuitArg {
+                            arg: rhs.clone().into_shared(),
+                        })?;
+                }
+                Instr::Bind(pattern) => {
+                    let value = self.top().result.clone();
+                    self.bind(pattern, &value)?;
+                }
+                Instr::MatchArm { pattern, target } => {
+                    let value = self.top().result.clone();
+                    if self.bind(pattern, &value).is_err() {
+                        pc = *target;
+                    }
+                }
+                Instr::Guard { target } => {
+                    let guard = self.pop();
+                    let guard_result =
+                        guard
+                            .result
+                            .coerce_bool()
+                            .ok_or_else(|| Err::InvalidGuard {
+                                cond: guard.result.clone().into_shared(),
+                            })?;
+                    let inner = self.stack.last_mut().unwrap();
+                    inner.work += guard.work;
+                    inner.span += guard.span;
+                    inner.counters = mem::take(&mut inner.counters).then(guard.counters);
+                    if !guard_result {
+                        pc = *target;
+                    }
+                }
+                Instr::NoMatch { patterns } => {
+                    return Err(Err::CaseNoMatch {
+                        inner: self.pop().result.into_shared(),
+                        patterns: patterns.clone(),
+                    })
+                }
+                Instr::PushHandler { target } => self.handlers.push(Handler {
+                    frames: self.frames.len(),
+                    stack: self.stack.len(),
+                    caught: self.caught.len(),
+                    target: *target,
+                }),
+                Instr::PopHandler => {
+                    self.handlers.pop();
+                }
+                Instr::EndHandle => {
+                    self.caught.pop();
+                }
+                Instr::Reraise => {
+                    let value = self.pop().result;
+                    let caught = self.caught.pop().unwrap();
+                    (func, pc) = self.raise(
+                        value,
+                        caught.location,
+                        caught.work,
+                        caught.span,
+                        caught.counters,
+                    )?;
+                }
+                Instr::Raise { location } => {
+                    let inner = self.pop();
+                    if !inner.result.value().is_exception() {
+                        return Err(Err::NotAnException {
+                            location: *location,
+                        });
+                    }
+                    (func, pc) = self.raise(
+                        inner.result,
+                        *location,
+                        inner.work + 1,
+                        inner.span + 1,
+                        inner.counters,
+                    )?;
+                }
+                Instr::Assert { location } => {
+                    let inner = self.pop();
+                    let cond = inner
+                        .result
+                        .coerce_bool()
+                        .ok_or_else(|| Err::InvalidAssertion {
+                            cond: inner.result.clone().into_shared(),
+                        })?;
+                    if cond {
+                        self.marker(inner.work + 1, inner.span + 1, inner.counters);
+                    } else {
+                        (func, pc) = self.raise(
+                            Operand::new(Value::assertion_failed()),
+                            *location,
+                            inner.work + 1,
+                            inner.span + 1,
+                            inner.counters,
+                        )?;
+                    }
+                }
+                Instr::CheckCost { measures, location } => {
+                    let inner = self.pop();
+                    let bounds = self.stack.split_off(self.stack.len() - measures.len());
+                    let mut violation = None;
+                    for (measure, bound) in measures.iter().zip(bounds) {
+                        let cost = match measure {
+                            CostMeasure::Work => inner.work,
+                            CostMeasure::Span => inner.span,
+                        };
+                        let bound = bound.result;
+                        let within = match bound {
+                            Operand::Integer(bound) => (cost as i128) <= (bound as i128),
+                            Operand::Float(bound) => (cost as f64) <= bound,
+                            _ => {
+                                return Err(Err::InvalidCostBound {
+                                    bound: bound.into_shared(),
+                                })
+                            }
+                        };
+                        if !within {
+                            violation = Some(Value::cost_contract_violated(
+                                *measure,
+                                cost,
+                                &bound.value(),
+                            ));
+                            break;
+                        }
+                    }
+                    match violation {
+                        Some(value) => {
+                            (func, pc) = self.raise(
+                                Operand::new(value),
+                                *location,
+                                inner.work,
+                                inner.span,
+                                inner.counters,
+                            )?
+                        }
+                        None => self.stack.push(inner),
+                    }
+                }
+                Instr::Tick(name) => match self.pop().result {
+                    Operand::Integer(count) if count >= 0 => {
+                        self.marker(0, 0, Counters::default().tick(name, count as u64))
+                    }
+                    count => {
+                        return Err(Err::InvalidTickCount {
+                            count: count.into_shared(),
+                        })
+                    }
+                },
+                Instr::Call { tail } => {
+                    let arg = self.pop();
+                    let lhs = self.pop();
+                    let cost = (
+                        lhs.work + arg.work + 1,
+                        cmp::max(lhs.span, arg.span) + 1,
+                        lhs.counters.par(arg.counters),
+                    );
+                    let lhs = lhs.result.into_shared();
+                    if let Some(next) = self.call(lhs, arg.result, *tail, cost, pc)? {
+                        (func, pc) = next;
+                    }
+                }
+                Instr::CallTuple { len, par, tail } => {
+                    let (arg_work, arg_span, arg_counters) = self.pop_components(*len, *par);
+                    let lhs = self.pop();
+                    let cost = (
+                        lhs.work + arg_work + 1,
+                        cmp::max(lhs.span, arg_span) + 1,
+                        lhs.counters.par(arg_counters),
+                    );
+                    let lhs = lhs.result.into_shared();
+
+                    // Binds the components to the patterns of a tuple parameter, without building
+                    // the tuple.
+                    let callee = match &*lhs {
+                        Value::Closure { func, .. } => Some(func.clone()),
+                        _ => None,
+                    };
+                    if let Some(Some(SlotPattern::Tuple(patterns))) =
+                        callee.as_ref().map(|callee| &callee.param)
+                    {
+                        if patterns.len() == *len {
+                            let (work, span, counters) = self.prepare_call(*tail, cost, pc);
+                            func = self.enter(lhs, None, work, span, counters)?;
+                            let mut components = mem::take(&mut self.components);
+                            for (pattern, component) in patterns.iter().zip(components.iter()) {
+                                self.bind(pattern, component)?;
+                            }
+                            components.clear();
+                            self.components = components;
+                            pc = 0;
+                            continue;
+                        }
+                    }
+
+                    let values = self
+                        .components
+                        .drain(..)
+                        .map(Operand::into_shared)
+                        .collect();
+                    let arg = Operand::new(Value::Tuple(values));
+                    if let Some(next) = self.call(lhs, arg, *tail, cost, pc)? {
+                        (func, pc) = next;
+                    }
+                }
+                Instr::Return => {
+                    let body = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    self.slots.truncate(frame.base);
+                    let (work, span) = (body.work + frame.work, body.span + frame.span);
+                    let counters = frame.counters.then(body.counters);
+                    match self.frames.last() {
+                        Some(caller) => {
+                            func = caller.func.clone();
+                            pc = caller.pc;
+                            self.push(body.result, work, span, counters);
+                        }
+                        None => {
+                            return Ok(Res {
+                                result: body.result.into_shared(),
+                                work,
+                                span,
+                                extras: counters.into(),
+                            })
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Evaluates an expression in the environment by compiling it to bytecode.
+pub(crate) fn eval_expr(env: &Env, expr: &Expr) -> EResult<Arc<Value>> {
+    Vm::new().run(bytecode::compile(env, expr), None)
+}
+
+/// Applies a compiled closure to an argument.  The returned cost does not include that of the
+/// application itself.
+pub(crate) fn apply(func: &Arc<Value>, arg: Arc<Value>) -> EResult<Arc<Value>> {
+    Vm::new().run(func.clone(), Some(arg))
+}
@@ -0,0 +1,94 @@
+use std::fs;
+
+use sparc::{Backend, Executor};
+
+/// The outcomes of the sample programs, which the backends must agree on.
+const EXPECTED: &[(&str, &str)] = &[
+    ("test/app.sparc", "3 (work 14, span 10)"),
+    ("test/bytecode.sparc", "(222, 7, 5, 9) (work 982, span 522)"),
+    ("test/comment.sparc", "9 (work 17, span 11)"),
+    ("test/compile.sparc", "(376, 31, Leaf -1) (work 910, span 160)"),
+    ("test/contract.sparc", "Runtime(Raise { value: Ctor { ctor: \"CostContractViolated\", inner: Some(String(\"span 26 exceeds the bound 4\")) }, location: Span { start: 832, end: 878 }, work: 45, span: 26, extras: Extras(None) })"),
+    ("test/datatype.sparc", "(3, 2) (work 78, span 46)"),
+    ("test/debug.sparc", "3 (work 40, span 25)"),
+    ("test/deep_list.sparc", "0 (work 2700021, span 1900016)"),
+    ("test/equality.sparc", "(true, true, true, true, true, -4, 1, -3, -1) (work 101, span 70)"),
+    ("test/exception.sparc", "Runtime(Raise { value: Ctor { ctor: \"Subscript\", inner: Some(Integer(0)) }, location: Span { start: 203, end: 222 }, work: 104, span: 77, extras: Extras(None) })"),
+    ("test/float.sparc", "(6.25, true, -2, 3.0, \"1.9999999999999996\") (work 53, span 34)"),
+    ("test/guard.sparc", "(Cons (1, Cons (2, Nil)), (-1, 4)) (work 112, span 85)"),
+    ("test/lambda.sparc", "<fun> (work 1, span 1)"),
+    ("test/let.sparc", "7 (work 11, span 8)"),
+    ("test/let_par.sparc", "15 (work 87, span 33)"),
+    ("test/let_pattern.sparc", "(3, Cons (2, Nil)) (work 60, span 47)"),
+    ("test/operators.sparc", "(-3, 10, (-1, -2), 42) (work 140, span 85, add 2/2)"),
+    ("test/pattern.sparc", "((3, false), (104, Cons (7, Cons (7, Nil)))) (work 82, span 55)"),
+    ("test/resolve.sparc", "((26, 1), 0) (work 74, span 41)"),
+    ("test/short_circuit.sparc", "(true, false, true, false, false) (work 95, span 62)"),
+    ("test/small_step.sparc", "8 (work 125, span 51)"),
+    ("test/space.sparc", "5070 (work 4683, span 2610)"),
+    ("test/string.sparc", "(true, false, \"(b)\", \"11\", \"ok\", true) (work 276, span 196)"),
+    ("test/tail_call.sparc", "(50005000, 20001) (work 540029, span 380023)"),
+    ("test/tick.sparc", "(Cons (1, Cons (1, Cons (3, Cons (4, Cons (5, Nil))))), 2080, 100000) (work 3104443, span 2100381, add 63/6, compare 7/7, loop 100001/100001)"),
+    ("test/trace.sparc", "(1, 0) (work 111, span 76)"),
+    ("test/tuple.sparc", "(3, (), ()) (work 12, span 10)"),
+    ("test/work_stealing.sparc", "32640 (work 19149, span 297)"),
+];
+
+/// Runs each sample program with the backend and returns its value, work, span and counters, or
+/// its error.
+fn run_samples(backend: Backend) -> Vec<(String, String)> {
+    let mut paths: Vec<_> = fs::read_dir("test")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sparc"))
+        .collect();
+    paths.sort();
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let input = fs::read_to_string(&path).unwrap();
+            let mut executor = Executor::new();
+            executor.set_backend(backend);
+            let outcome = match executor.load(&input) {
+                Ok(Some(res)) => {
+                    let counters = res
+                        .extras
+                        .counters()
+                        .iter()
+                        .map(|(name, count)| format!(", {} {}/{}", name, count.work, count.span))
+                        .collect::<String>();
+                    format!(
+                        "{} (work {}, span {}{})",
+                        res.result, res.work, res.span, counters
+                    )
+                }
+                Ok(None) => "no expression".to_string(),
+                Err(e) => format!("{:?}", e),
+            };
+            (path.display().to_string(), outcome)
+        })
+        .collect()
+}
+
+#[test]
+fn bytecode_agrees_with_env() {
+    assert_eq!(run_samples(Backend::Bytecode), run_samples(Backend::Env));
+}
+
+#[test]
+fn substitution_agrees_with_env() {
+    assert_eq!(
+        run_samples(Backend::Substitution { print_steps: false }),
+        run_samples(Backend::Env)
+    );
+}
+
+#[test]
+fn env_gives_expected_outcomes() {
+    let expected: Vec<_> = EXPECTED
+        .iter()
+        .map(|&(path, outcome)| (path.to_string(), outcome.to_string()))
+        .collect();
+    assert_eq!(run_samples(Backend::Env), expected);
+}
@@ -0,0 +1,48 @@
+use sparc::{Executor, FromValue, ToValue};
+
+#[derive(Debug, Clone, PartialEq, ToValue, FromValue)]
+enum Shape {
+    Empty,
+    Circle(i64),
+    Rect(i64, i64),
+}
+
+const PROGRAM: &str = "
+datatype 'a list = Nil | Cons of 'a * 'a list;
+datatype 'a option = NONE | SOME of 'a;
+datatype shape = Empty | Circle of int | Rect of int * int;
+
+fun grow (k, s) = case s {
+    Empty => Empty,
+    Circle r => (Circle (( * k) r)),
+    Rect (w, h) => (Rect ((( * k) w); (( * k) h))),
+};
+fun scale (k, l) = case l { Nil => Nil, Cons (s, rest) => (Cons ((grow (k; s)); (scale (k; rest)))), };
+fun first l = case l { Nil => NONE, Cons (s, _) => (SOME s), };
+fun both (k, l) = ((scale (k; l)); (first l));
+";
+
+/// Derived enums pass through `Executor::call`, alone and inside options, vectors and tuples.
+#[test]
+fn derived_enums_round_trip() {
+    let mut executor = Executor::new();
+    executor.load(PROGRAM).unwrap();
+
+    let shapes = vec![Shape::Rect(1, 2), Shape::Empty, Shape::Circle(3)];
+    let scaled = vec![Shape::Rect(2, 4), Shape::Empty, Shape::Circle(6)];
+    for shape in shapes.iter() {
+        assert_eq!(Shape::from_value(&shape.to_value()).as_ref(), Some(shape));
+    }
+
+    let grown = executor.call::<_, Shape>("grow", (3, Shape::Circle(2)));
+    assert_eq!(grown.unwrap().result, Shape::Circle(6));
+
+    let res = executor.call::<_, (Vec<Shape>, Option<Shape>)>("both", (2, shapes.clone()));
+    assert_eq!(res.unwrap().result, (scaled, Some(Shape::Rect(1, 2))));
+
+    let res = executor.call::<_, Option<Shape>>("first", Vec::<Shape>::new());
+    assert_eq!(res.unwrap().result, None);
+
+    // A value of another type is not converted.
+    assert!(executor.call::<_, Vec<Shape>>("first", shapes).is_err());
+}
@@ -0,0 +1,70 @@
+use sparc::{Backend, Err, Error, Executor, Stop};
+
+const BACKENDS: [Backend; 3] = [
+    Backend::Env,
+    Backend::Substitution { print_steps: false },
+    Backend::Bytecode,
+];
+
+/// A raise of a value that only turns out not to be an exception fails when it is evaluated.
+#[test]
+fn raising_a_value_checks_that_it_is_an_exception() {
+    let input = "exception E of int; val v = 5; val w = (E 1); ((raise w) handle { E n => n, })";
+    for backend in BACKENDS {
+        let mut executor = Executor::new();
+        executor.set_backend(backend);
+        let res = executor.load(input).unwrap().unwrap();
+        assert_eq!(res.result.to_string(), "1");
+
+        let res = executor.load("(raise v)");
+        assert!(
+            matches!(res, Err(Error::Runtime(Err::NotAnException { .. }))),
+            "{:?} raises {:?}",
+            backend,
+            res
+        );
+    }
+}
+
+/// The debugger checks and evaluates the declarations as the executor does.
+#[test]
+fn debugger_checks_raises() {
+    let executor = Executor::new();
+    let mut debugger = executor.debug("val v = 5; (raise v)").unwrap();
+    assert!(matches!(
+        debugger.cont(),
+        Stop::Finished(Err(Err::NotAnException { .. }))
+    ));
+
+    let mut debugger = executor.debug("(raise 5)").unwrap();
+    assert!(matches!(
+        debugger.cont(),
+        Stop::Finished(Err(Err::NotAnException { .. }))
+    ));
+
+    let mut debugger = executor
+        .debug("exception E; ((raise E) handle { E => 0, })")
+        .unwrap();
+    match debugger.cont() {
+        Stop::Finished(Ok(Some(res))) => assert_eq!(res.result.to_string(), "0"),
+        stop => panic!("unexpected {:?}", stop),
+    }
+}
+
+/// Violated assertions and cost contracts raise builtin exceptions, which handlers can catch.
+#[test]
+fn violated_contracts_raise_exceptions() {
+    let input = "(((assert false) handle { AssertionFailed => \"assertion\", }); \
+                 ((withcost (work <= 1) ((+ 1) 2)) handle { CostContractViolated m => m, }))";
+    for backend in BACKENDS {
+        let mut executor = Executor::new();
+        executor.set_backend(backend);
+        let res = executor.eval(input).unwrap();
+        assert_eq!(
+            res.result.to_string(),
+            "(\"assertion\", \"work 9 exceeds the bound 1\")",
+            "{:?}",
+            backend
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use lalrpop_util::ParseError;
+use sparc::{Error, Executor, LexicalErrorKind, SyntaxErrorKind};
+
+/// Returns the kind of the lexical error in the input, if any.
+fn lexical_error(input: &str) -> Option<LexicalErrorKind> {
+    match Executor::new().load(input) {
+        Err(Error::Parse(ParseError::User { error })) => match error.kind {
+            SyntaxErrorKind::Lexical(kind) => Some(kind),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[test]
+fn star_after_paren_starts_a_comment() {
+    assert_eq!(
+        lexical_error("((* 3) 4)"),
+        Some(LexicalErrorKind::UnterminatedComment)
+    );
+    assert_eq!(lexical_error("(( * 3) 4)"), None);
+}
+
+#[test]
+fn backslash_at_end_of_string_leaves_it_unterminated() {
+    assert_eq!(
+        lexical_error("\"\\"),
+        Some(LexicalErrorKind::UnterminatedString)
+    );
+    assert_eq!(
+        lexical_error("\"\\\n"),
+        Some(LexicalErrorKind::UnterminatedString)
+    );
+    assert_eq!(
+        lexical_error("\"\\q\""),
+        Some(LexicalErrorKind::InvalidEscape('q'))
+    );
+}
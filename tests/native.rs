@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use sparc::{Err, Executor, NativeFn, Value};
+
+/// Sums `n` copies of a value, with work `n` and span 1 as if the copies were summed in parallel.
+struct Times;
+
+impl NativeFn for Times {
+    fn name(&self) -> &str {
+        "times"
+    }
+
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn cost(&self, args: &[Arc<Value>]) -> (u64, u64) {
+        (args[0].coerce_int().unwrap_or(0) as u64, 1)
+    }
+
+    fn call(&self, args: &[Arc<Value>]) -> Result<Arc<Value>, Err> {
+        let n = args[0].coerce_int().unwrap();
+        let x = args[1].coerce_int().unwrap();
+        Ok(Arc::new(Value::Integer(n * x)))
+    }
+}
+
+/// A registered function is applied like a builtin one, and charged its cost.
+#[test]
+fn registered_functions_are_charged_their_cost() {
+    let mut executor = Executor::new();
+    executor.register(Times);
+
+    let few = executor.eval("((times 1) 7)").unwrap();
+    let many = executor.eval("((times 100) 7)").unwrap();
+    assert_eq!(few.result.coerce_int(), Some(7));
+    assert_eq!(many.result.coerce_int(), Some(700));
+    assert_eq!(many.work - few.work, 99);
+    assert_eq!(many.span, few.span);
+
+    let partial = executor.define("sevens", "(times 7)").unwrap();
+    assert_eq!(partial.result.to_string(), "<native times>");
+    let res = executor.call::<i64, i64>("sevens", 3).unwrap();
+    assert_eq!(res.result, 21);
+}
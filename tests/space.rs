@@ -0,0 +1,47 @@
+use std::fs;
+
+use sparc::{Allocs, Backend, Executor};
+
+/// The parallel bindings of the sample are live at once only under the parallel schedule.
+#[test]
+fn space_is_measured_when_requested() {
+    let input = fs::read_to_string("test/space.sparc").unwrap();
+
+    let mut executor = Executor::new();
+    let res = executor.load(&input).unwrap().unwrap();
+    assert_eq!(res.extras.space().allocs(), Allocs::default());
+    assert_eq!(res.extras.space().peak_seq(), 0);
+
+    let mut executor = Executor::new();
+    executor.set_space(true);
+    let res = executor.load(&input).unwrap().unwrap();
+    let space = res.extras.space();
+    assert_eq!(space.peak_seq(), 60);
+    assert_eq!(space.peak_par(), 120);
+    assert_eq!(space.live(), 0);
+    assert_eq!(space.allocs().ctors, 40);
+    assert_eq!(res.result.to_string(), "5070");
+
+    // Only the environment-based evaluator measures the space.
+    let mut executor = Executor::new();
+    executor.set_space(true);
+    executor.set_backend(Backend::Bytecode);
+    let res = executor.load(&input).unwrap().unwrap();
+    assert_eq!(res.extras.space().peak_par(), 0);
+}
+
+/// A tuple built from a variable is allocated, but a literal one is a constant.
+#[test]
+fn allocations_are_counted_by_kind() {
+    let mut executor = Executor::new();
+    executor.set_space(true);
+
+    let res = executor.eval("(1, 2)").unwrap();
+    assert_eq!(res.extras.space().allocs(), Allocs::default());
+
+    let res = executor.eval("let x = 1, in (x; x)").unwrap();
+    let allocs = res.extras.space().allocs();
+    assert_eq!(allocs.tuples, 1);
+    assert_eq!(allocs.total(), 1);
+    assert_eq!(res.extras.space().live(), 1);
+}
@@ -0,0 +1,87 @@
+use std::fs;
+use std::process::Command;
+
+use sparc::{Breakpoint, Executor, Stop};
+
+/// Each call of a function, in tail position or not, enters its body.
+#[test]
+fn trace_enters_bodies_of_calls() {
+    let output = Command::new(env!("CARGO_BIN_EXE_sparc"))
+        .args(["--trace", "test/trace.sparc"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let trace = String::from_utf8(output.stderr).unwrap();
+    let entered = |prefix: &str| {
+        trace
+            .lines()
+            .filter(|line| line.trim_start().starts_with(prefix))
+            .count()
+    };
+    assert_eq!(entered("-> case l {"), 3);
+    assert_eq!(entered("-> if "), 3);
+}
+
+#[test]
+fn debugger_stops_in_calls() {
+    let input = fs::read_to_string("test/debug.sparc").unwrap();
+    let mut debugger = Executor::new().debug(&input).unwrap();
+    let breakpoint = Breakpoint::Function("sum".into());
+    debugger.add_breakpoint(breakpoint.clone());
+
+    assert!(matches!(debugger.cont(), Stop::Enter { .. }));
+    assert!(matches!(debugger.cont(), Stop::Enter { .. }));
+    let backtrace = debugger.backtrace();
+    assert_eq!(backtrace.len(), 2);
+    assert!(backtrace
+        .iter()
+        .all(|call| call.name.as_deref() == Some("sum")));
+
+    debugger.remove_breakpoint(&breakpoint);
+    match debugger.finish() {
+        Stop::Return { call, res } => {
+            assert_eq!(call.name.as_deref(), Some("sum"));
+            assert_eq!(res.unwrap().result.to_string(), "2");
+        }
+        stop => panic!("unexpected {:?}", stop),
+    }
+}
+
+/// Calls in tail position run in constant stack space while traced or debugged.
+#[test]
+fn observed_tail_calls_do_not_grow_the_stack() {
+    let output = Command::new(env!("CARGO_BIN_EXE_sparc"))
+        .args(["--trace", "--trace-depth", "0", "test/tail_call.sparc"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let trace = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(
+        trace.lines().last(),
+        Some("<- (50005000, 20001) (work: 540029, span: 380023)")
+    );
+
+    let input = fs::read_to_string("test/tail_call.sparc").unwrap();
+    let mut debugger = Executor::new().debug(&input).unwrap();
+    match debugger.cont() {
+        Stop::Finished(Ok(Some(res))) => assert_eq!(res.result.to_string(), "(50005000, 20001)"),
+        stop => panic!("unexpected {:?}", stop),
+    }
+}
+
+/// Lifted operators are written as the operators, as in the source.
+#[test]
+fn small_steps_write_operators() {
+    let output = Command::new(env!("CARGO_BIN_EXE_sparc"))
+        .args(["--small-step", "test/small_step.sparc"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let steps = String::from_utf8(output.stderr).unwrap();
+    assert!(steps
+        .lines()
+        .any(|line| line == "--> let par a = (( * 3) (fact 2)), b = (fact 2), in ((+ a) b)"));
+    assert!(!steps.contains("fun"));
+}
@@ -0,0 +1,40 @@
+use std::fs;
+
+use sparc::{CompileError, Error, Executor};
+
+/// Every sample program compiles.
+#[test]
+fn samples_compile() {
+    for entry in fs::read_dir("test").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "sparc") {
+            let input = fs::read_to_string(&path).unwrap();
+            let res = Executor::new().emit_rust(&input);
+            assert!(res.is_ok(), "{} fails with {:?}", path.display(), res);
+        }
+    }
+}
+
+/// Datatypes and exceptions become enums, and parallel pairs forks of rayon.
+#[test]
+fn compiled_program_forks_with_rayon() {
+    let input = fs::read_to_string("test/compile.sparc").unwrap();
+    let rust = Executor::new().emit_rust(&input).unwrap();
+    assert!(rust.contains("enum T_tree {"));
+    assert!(rust.contains("    Negative(Value),"));
+    assert!(rust.contains("rayon::join("));
+    assert!(rust.contains("fn main() {"));
+}
+
+#[test]
+fn unbound_names_are_compile_errors() {
+    let executor = Executor::new();
+    assert!(matches!(
+        executor.emit_rust("val x = y; x"),
+        Err(Error::Compile(CompileError::Unbound(var))) if var == "y"
+    ));
+    assert!(matches!(
+        executor.emit_rust("(Foo 1)"),
+        Err(Error::Compile(CompileError::Undeclared(ctor))) if ctor == "Foo"
+    ));
+}
@@ -0,0 +1,51 @@
+use std::process::Command;
+
+/// Runs the work-stealing sample on `workers` workers, and returns its work, span and the averaged
+/// time, steals and idle steps.
+fn simulate(workers: usize) -> (f64, f64, f64, f64, f64) {
+    let output = Command::new(env!("CARGO_BIN_EXE_sparc"))
+        .args([
+            "--work-stealing",
+            &workers.to_string(),
+            "test/work_stealing.sparc",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let number = |key: &str| -> f64 {
+        let start = stdout.find(key).unwrap() + key.len();
+        let rest = &stdout[start..];
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap();
+        rest[..end].parse().unwrap()
+    };
+    (
+        number("Work: "),
+        number("Span: "),
+        number("time "),
+        number("steals "),
+        number("idle "),
+    )
+}
+
+/// A single worker executes every step itself, never stealing nor idling.
+#[test]
+fn one_worker_takes_the_work() {
+    let (work, _, time, steals, idle) = simulate(1);
+    assert_eq!(time, work);
+    assert_eq!(steals, 0.0);
+    assert_eq!(idle, 0.0);
+}
+
+/// Each step is spent either executing the DAG or idling, and the time is within W/P + S.
+#[test]
+fn workers_finish_within_the_bound() {
+    let (work, span, time, steals, idle) = simulate(4);
+    assert_eq!(4.0 * time, work + idle);
+    assert!(time >= work / 4.0);
+    assert!(time <= work / 4.0 + span);
+    assert!(steals > 0.0);
+}